@@ -0,0 +1,199 @@
+/*
+* A source of the current time which can be replaced in tests.
+*/
+
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Timezone {
+    // Coordinated universal time.
+    #[default]
+    Utc,
+    // The local time of the machine the server runs on.
+    Local,
+}
+
+impl Timezone {
+    pub fn parse(value: &str) -> Timezone {
+        // Parse a timezone name, falling back to UTC for unknown names.
+        match value.trim().to_ascii_lowercase().as_str() {
+            "local" => Timezone::Local,
+            _ => Timezone::Utc,
+        }
+    }
+
+    pub fn offset_at(&self, time: SystemTime) -> i64 {
+        // Get the offset from UTC in seconds at a point in time.
+        match self {
+            Timezone::Utc => 0,
+            Timezone::Local => local_offset(time),
+        }
+    }
+}
+
+pub trait Clock: Send + Sync {
+    // Get the current time.
+    fn now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+pub struct FixedClock(pub SystemTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        // A fixed clock always returns the same time.
+        self.0
+    }
+}
+
+pub fn unix_seconds(time: SystemTime) -> i64 {
+    // Get the seconds since the epoch of a point in time, negative before it.
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Convert days since the epoch to a (year, month, day) date.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as u32, day as u32)
+}
+
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Convert a (year, month, day) date to days since the epoch.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+pub fn rfc3339(time: SystemTime, offset: i64) -> String {
+    /*
+    Format a point in time with an offset from UTC in seconds,
+    e.g. "2023-11-14T22:13:20Z" or "2023-11-14T23:13:20+01:00".
+     */
+    let secs = unix_seconds(time) + offset;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let secs_of_day = secs.rem_euclid(86400);
+
+    let zone = if offset == 0 {
+        "Z".to_string()
+    } else {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let minutes = offset.abs() / 60;
+        format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        zone
+    )
+}
+
+#[cfg(unix)]
+fn local_offset(time: SystemTime) -> i64 {
+    /*
+    Get the offset of the local time from UTC at a point in time.
+    The C library applies the TZ environment variable and daylight saving time.
+     */
+    use std::os::raw::{c_char, c_int, c_long};
+
+    #[repr(C)]
+    struct Tm {
+        tm_sec: c_int,
+        tm_min: c_int,
+        tm_hour: c_int,
+        tm_mday: c_int,
+        tm_mon: c_int,
+        tm_year: c_int,
+        tm_wday: c_int,
+        tm_yday: c_int,
+        tm_isdst: c_int,
+        tm_gmtoff: c_long,
+        tm_zone: *const c_char,
+    }
+
+    extern "C" {
+        fn localtime_r(time: *const c_long, result: *mut Tm) -> *mut Tm;
+    }
+
+    let secs = unix_seconds(time) as c_long;
+    let mut tm = std::mem::MaybeUninit::<Tm>::uninit();
+    // SAFETY: localtime_r only writes to the provided struct, which matches the
+    // layout of struct tm on the supported platforms, and is reentrant.
+    let result = unsafe { localtime_r(&secs, tm.as_mut_ptr()) };
+    if result.is_null() {
+        return 0;
+    }
+    // SAFETY: a non-null result means the struct was filled in.
+    let tm = unsafe { tm.assume_init() };
+    // c_long is narrower than i64 on some platforms
+    #[allow(clippy::useless_conversion)]
+    i64::from(tm.tm_gmtoff)
+}
+
+#[cfg(not(unix))]
+fn local_offset(_time: SystemTime) -> i64 {
+    // The local offset is only known on unix, elsewhere local time is UTC.
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_fixed_clock() {
+        // Test that a fixed clock always returns the same time.
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(42);
+        let clock = FixedClock(time);
+        assert_eq!(clock.now(), time);
+        assert_eq!(clock.now(), time);
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        // Test that the same instant renders with the offset of the timezone.
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000);
+        assert_eq!(rfc3339(time, 0), "2023-11-14T22:13:20Z");
+        assert_eq!(rfc3339(time, 3600), "2023-11-14T23:13:20+01:00");
+        assert_eq!(
+            rfc3339(time, -(5 * 3600 + 1800)),
+            "2023-11-14T16:43:20-05:30"
+        );
+        assert_eq!(
+            rfc3339(SystemTime::UNIX_EPOCH, -60),
+            "1969-12-31T23:59:00-00:01"
+        );
+
+        assert_eq!(Timezone::Utc.offset_at(time), 0);
+        assert_eq!(Timezone::Local.offset_at(time), local_offset(time));
+        assert_eq!(Timezone::parse("LOCAL"), Timezone::Local);
+        assert_eq!(Timezone::parse("utc"), Timezone::Utc);
+    }
+}
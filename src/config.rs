@@ -0,0 +1,743 @@
+/*
+* Configuration of the webserver read from environment variables.
+*/
+
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    clock::{Clock, SystemClock, Timezone},
+    http::{
+        auth::{parse_auth, Auth},
+        cache::{parse_cache_rules, CacheRule},
+        file::{parse_extensions, EtagAlgorithm},
+        forwarded::parse_trusted_proxies,
+        response::parse_header_order,
+        status::StatusCode,
+    },
+    limit::MemoryBudget,
+    logger::log::{FlushPolicy, LogFormat, LogLevel, LogSampler},
+};
+
+pub struct Config {
+    pub address: String,
+    pub port: String,
+    pub path_to_resources: PathBuf,
+    // Refuse to start if the resources directory is missing.
+    pub require_resources: bool,
+    // Maximum time a handler may run before a 503 is sent, off by default.
+    // Handlers then run on a thread of their own each, which keeps running a
+    // handler that timed out until it returns, so handlers should check their deadline.
+    pub handler_timeout: Option<Duration>,
+    // Number of worker threads serving connections.
+    pub workers: usize,
+    // Log the method, path, status and duration of every request.
+    pub log_requests: bool,
+    // Maximum time to wait for in-flight requests on shutdown.
+    pub drain_timeout: Option<Duration>,
+    // List the entries of directories without an index file.
+    pub directory_listing: bool,
+    // Source of the time used for the Date header of responses.
+    pub clock: Arc<dyn Clock>,
+    // Cache-Control rules for served files by path prefix.
+    pub cache_rules: Vec<CacheRule>,
+    // Requests taking longer than this are logged as a warning.
+    pub slow_request_threshold: Option<Duration>,
+    // Proxies whose X-Forwarded-For and Forwarded headers are trusted.
+    pub trusted_proxies: Vec<IpAddr>,
+    // Credentials requests must send, checked before routing.
+    pub auth: Auth,
+    // Connections waiting for a worker before new ones are answered with 503.
+    pub queue_limit: Option<usize>,
+    // Time clients are asked to wait before retrying after a 503.
+    pub retry_after: Duration,
+    // Whether log lines are written as text, JSON or logfmt.
+    pub log_format: LogFormat,
+    // Timezone of the timestamps of log lines.
+    pub log_timezone: Timezone,
+    // Requests served over one connection before it is closed.
+    pub max_requests_per_connection: Option<usize>,
+    // Time an idle connection is kept open while waiting for the next request.
+    pub keep_alive_timeout: Option<Duration>,
+    // How requests for unknown paths are answered.
+    pub not_found: NotFoundResponse,
+    // Time a client has to send the rest of a request once it has started.
+    pub request_timeout: Option<Duration>,
+    // Syslog daemon to log to, e.g. "udp://127.0.0.1:514" or "local".
+    // Only used when built with the syslog feature.
+    pub syslog: Option<String>,
+    // Syslog facility of the log lines, e.g. "daemon" or "local0".
+    pub syslog_facility: String,
+    // Longest decoded request path accepted before answering with 414.
+    pub max_uri_length: Option<usize>,
+    // Longest header value accepted before answering with 431.
+    pub max_header_value_length: Option<usize>,
+    // Most header fields accepted before answering with 431.
+    pub max_headers: Option<usize>,
+    // Most bytes of the header section accepted before answering with 431.
+    pub max_header_bytes: Option<usize>,
+    // Capacity of the buffer requests are read from a connection through.
+    pub read_buffer_size: usize,
+    // Media type of the files rendered as templates by routes with a template context.
+    pub template_content_type: String,
+    // Whether template placeholders without a value are kept or removed.
+    pub template_keep_unknown: bool,
+    // Whether TRACE requests are echoed back, off by default as it can leak credentials.
+    pub trace: bool,
+    // Connections one client address may hold open before new ones are answered with 503.
+    pub max_connections_per_ip: Option<usize>,
+    // Status of the response when a handler has no file to serve, e.g. 204 No Content.
+    pub empty_handler_status: StatusCode,
+    // Extensions of the static files which may be served, any if None.
+    pub allowed_extensions: Option<Vec<String>>,
+    // Whether static files and directories with a leading dot, e.g. .env, are served.
+    pub serve_hidden_files: bool,
+    // How requests for hosts without routes of their own are answered once
+    // routes are scoped to hosts.
+    pub unknown_host: UnknownHostResponse,
+    // Largest body held in memory a response may have, file bodies are streamed.
+    pub max_response_body: Option<u64>,
+    // What is sent instead of a response whose body exceeds the limit.
+    pub oversized_response: OversizedResponse,
+    // Whether responses on kept alive connections advertise the timeout and
+    // remaining requests in a Keep-Alive header.
+    pub keep_alive_header: bool,
+    // How requests for / are answered when no route matches them.
+    pub root: RootResponse,
+    // Whether log lines are written by a background thread instead of the
+    // threads logging them.
+    pub background_logging: bool,
+    // How requests for /favicon.ico are answered when no route matches them.
+    pub favicon: FaviconResponse,
+    // When the log outputs are flushed, e.g. after every line.
+    pub log_flush: FlushPolicy,
+    // Which requests the access log covers, e.g. one in 100 successful ones
+    // while failed requests are always logged.
+    pub access_log_sampler: LogSampler,
+    // Headers sent first, in this order, before the other headers of a response.
+    pub header_order: Vec<String>,
+    // What happens to the body of a GET or HEAD request, which has no meaning.
+    pub get_body: GetBody,
+    // Time a write to a client may block before the connection is dropped,
+    // e.g. when the client stopped reading the response.
+    pub write_timeout: Option<Duration>,
+    // Workers low priority routes may not use, so normal requests are served
+    // while slow ones keep the other workers busy.
+    pub reserved_workers: usize,
+    // How the query string of a request is written to the access log.
+    pub log_query: QueryLogging,
+    // Bytes the bodies of all requests in flight and of their responses may
+    // take up together before new requests are answered with 503.
+    pub memory_budget: MemoryBudget,
+    // How the ETag of served files is computed, from a weak FNV-1a hash of their
+    // contents by default. Hashes are cached until a file changes.
+    pub etag_algorithm: EtagAlgorithm,
+    // Whether responses tell the time spent reading the request, routing it and
+    // running its handler in a Server-Timing header, for performance debugging.
+    pub server_timing: bool,
+    // Directory the files uploaded to routes streaming uploads are written to.
+    pub upload_dir: PathBuf,
+    // Bytes the files of one request streamed to disk may take up, beyond which
+    // it is answered with 413.
+    pub max_upload_size: u64,
+    // Lowest level of the log lines written, e.g. warning to drop debug and info lines.
+    pub log_level: LogLevel,
+    // Whether the route table is listed at /_routes. It tells clients every
+    // path the server answers, so it is meant for development only.
+    pub routes_endpoint: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: "127.0.0.1".to_string(),
+            port: "8080".to_string(),
+            path_to_resources: PathBuf::from("./res"),
+            require_resources: false,
+            handler_timeout: None,
+            workers: 4,
+            log_requests: false,
+            drain_timeout: Some(Duration::from_secs(30)),
+            directory_listing: false,
+            clock: Arc::new(SystemClock),
+            cache_rules: Vec::new(),
+            slow_request_threshold: Some(Duration::from_secs(1)),
+            trusted_proxies: Vec::new(),
+            auth: Auth::None,
+            queue_limit: None,
+            retry_after: Duration::from_secs(1),
+            log_format: LogFormat::Text,
+            log_timezone: Timezone::Utc,
+            not_found: NotFoundResponse::Page,
+            max_requests_per_connection: Some(100),
+            keep_alive_timeout: Some(Duration::from_secs(5)),
+            request_timeout: Some(Duration::from_secs(10)),
+            syslog: None,
+            syslog_facility: "user".to_string(),
+            max_uri_length: Some(8192),
+            max_header_value_length: Some(8192),
+            max_headers: Some(100),
+            max_header_bytes: Some(64 * 1024),
+            read_buffer_size: 8 * 1024,
+            template_content_type: "text/html".to_string(),
+            template_keep_unknown: true,
+            trace: false,
+            max_connections_per_ip: None,
+            empty_handler_status: StatusCode::NoContent,
+            allowed_extensions: None,
+            serve_hidden_files: false,
+            unknown_host: UnknownHostResponse::NotFound,
+            max_response_body: None,
+            oversized_response: OversizedResponse::Error,
+            keep_alive_header: true,
+            root: RootResponse::Serve("index.html".to_string()),
+            background_logging: false,
+            favicon: FaviconResponse::NotFound,
+            log_flush: FlushPolicy::Writer,
+            access_log_sampler: LogSampler::new(1),
+            header_order: Vec::new(),
+            get_body: GetBody::Discard,
+            write_timeout: Some(Duration::from_secs(30)),
+            reserved_workers: 0,
+            log_query: QueryLogging::Full,
+            memory_budget: MemoryBudget::new(None),
+            etag_algorithm: EtagAlgorithm::Fnv,
+            server_timing: false,
+            upload_dir: std::env::temp_dir(),
+            max_upload_size: 100 * 1024 * 1024,
+            log_level: LogLevel::Debug,
+            routes_endpoint: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn summary(&self) -> Vec<(&'static str, String)> {
+        /*
+        Summarize the effective configuration as log fields for the startup log line.
+        Credentials are never included, only the authentication strategy and their number.
+         */
+        let features: Vec<&str> = [
+            ("syslog", cfg!(feature = "syslog")),
+            ("request-logging", self.log_requests),
+            ("directory-listing", self.directory_listing),
+            ("hidden-files", self.serve_hidden_files),
+            ("trace", self.trace),
+            ("background-logging", self.background_logging),
+            ("server-timing", self.server_timing),
+            ("routes-endpoint", self.routes_endpoint),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| feature)
+        .collect();
+
+        vec![
+            ("address", self.address.clone()),
+            ("port", self.port.clone()),
+            ("workers", self.workers.to_string()),
+            ("resources", self.path_to_resources.display().to_string()),
+            (
+                "log_format",
+                format!("{:?}", self.log_format).to_ascii_lowercase(),
+            ),
+            (
+                "log_level",
+                format!("{:?}", self.log_level).to_ascii_lowercase(),
+            ),
+            (
+                "log_timezone",
+                format!("{:?}", self.log_timezone).to_ascii_lowercase(),
+            ),
+            ("auth", self.auth.summary()),
+            (
+                "features",
+                if features.is_empty() {
+                    "none".to_string()
+                } else {
+                    features.join(",")
+                },
+            ),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OversizedResponse {
+    // Answer with 500 Internal Server Error instead.
+    Error,
+    // Send only the first bytes of the body up to the limit.
+    Truncate,
+}
+
+impl OversizedResponse {
+    pub fn parse(value: &str) -> OversizedResponse {
+        // Parse "error" or "truncate", falling back to error for unknown values.
+        match value.trim().to_ascii_lowercase().as_str() {
+            "truncate" => OversizedResponse::Truncate,
+            _ => OversizedResponse::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnknownHostResponse {
+    // Answer with 404 Not Found.
+    NotFound,
+    // Serve the routes of this host instead, e.g. "www.example.com".
+    DefaultHost(String),
+    // Serve only the routes not scoped to a host.
+    Shared,
+}
+
+impl UnknownHostResponse {
+    pub fn parse(value: &str) -> UnknownHostResponse {
+        // Parse "404", "shared" or the name of the default host.
+        match value.trim() {
+            "" | "404" => UnknownHostResponse::NotFound,
+            value if value.eq_ignore_ascii_case("shared") => UnknownHostResponse::Shared,
+            host => UnknownHostResponse::DefaultHost(host.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RootResponse {
+    // Serve the file of the resources directory, e.g. "index.html".
+    Serve(String),
+    // Redirect to the path, e.g. "/docs/".
+    Redirect(String),
+    // Answer with 404 Not Found.
+    NotFound,
+}
+
+impl RootResponse {
+    pub fn parse(value: &str) -> RootResponse {
+        // Parse "404", "redirect:<path>" or the name of the index file.
+        match value.trim() {
+            "" | "404" => RootResponse::NotFound,
+            value => match value.strip_prefix("redirect:") {
+                Some(location) => RootResponse::Redirect(location.trim().to_string()),
+                None => RootResponse::Serve(value.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FaviconResponse {
+    // Look the icon up like any other path, which usually answers with 404.
+    NotFound,
+    // Serve a built-in transparent icon.
+    Default,
+    // Serve the file, relative to the resources directory unless absolute.
+    File(PathBuf),
+}
+
+impl FaviconResponse {
+    pub fn parse(value: &str) -> FaviconResponse {
+        // Parse "" or "off", "default" or the path of the icon file.
+        match value.trim() {
+            "" => FaviconResponse::NotFound,
+            value if value.eq_ignore_ascii_case("off") => FaviconResponse::NotFound,
+            value if value.eq_ignore_ascii_case("default") => FaviconResponse::Default,
+            path => FaviconResponse::File(PathBuf::from(path)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryLogging {
+    // Log the uri as it was requested.
+    Full,
+    // Log the path only, e.g. "/login" for "/login?token=abc".
+    Strip,
+    // Log the names of the parameters with masked values, e.g. "/login?token=***".
+    Redact,
+}
+
+impl QueryLogging {
+    pub fn parse(value: &str) -> QueryLogging {
+        // Parse "full", "strip" or "redact", falling back to full for unknown values.
+        match value.trim().to_ascii_lowercase().as_str() {
+            "strip" => QueryLogging::Strip,
+            "redact" => QueryLogging::Redact,
+            _ => QueryLogging::Full,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GetBody {
+    // Read the body so the connection stays usable, but don't pass it on.
+    Discard,
+    // Answer with 400 Bad Request and close the connection.
+    Reject,
+}
+
+impl GetBody {
+    pub fn parse(value: &str) -> GetBody {
+        // Parse "discard" or "reject", falling back to discard for unknown values.
+        match value.trim().to_ascii_lowercase().as_str() {
+            "reject" => GetBody::Reject,
+            _ => GetBody::Discard,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotFoundResponse {
+    // Serve the 404.html page of the resources directory.
+    Page,
+    // Redirect to the url, e.g. the start page.
+    Redirect(String),
+    // Send a JSON error body for API clients.
+    Json,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ResourcesError {
+    // The resources path does not exist.
+    Missing(PathBuf),
+    // The resources path exists but is not a directory.
+    NotADirectory(PathBuf),
+}
+
+impl std::fmt::Display for ResourcesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourcesError::Missing(path) => {
+                write!(f, "resources directory {} does not exist", path.display())
+            }
+            ResourcesError::NotADirectory(path) => {
+                write!(f, "resources path {} is not a directory", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResourcesError {}
+
+fn parse_duration_ms(value: &str) -> Option<Duration> {
+    /*
+    Parse a duration in milliseconds from an environment variable value.
+    A value of zero or an invalid value disables the duration.
+     */
+    match value.trim().parse::<u64>() {
+        Ok(0) | Err(_) => None,
+        Ok(ms) => Some(Duration::from_millis(ms)),
+    }
+}
+
+fn parse_bool(value: &str) -> bool {
+    /*
+    Parse a boolean flag from an environment variable value.
+     */
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes" | "on"
+    )
+}
+
+pub fn get_config() -> Config {
+    /*
+    Get the configuration for the webserver.
+    The configuration is read from environment variables.
+    If the environment variables are not set, default values are used.
+     */
+    config_from(&|key| std::env::var(key).ok())
+}
+
+pub fn config_from(lookup: &dyn Fn(&str) -> Option<String>) -> Config {
+    /*
+    Build the configuration from the values of the variables found by lookup,
+    using default values for the missing ones.
+    The variables are named like the environment variables, e.g. WORKERS.
+     */
+    let var = |key: &str, default: &str| lookup(key).unwrap_or_else(|| default.to_string());
+
+    // Set the path to the resources directory, by default the bundled one
+    let path_to_resources = match lookup("RESOURCES") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(var("CARGO_MANIFEST_DIR", ".")).join("res"),
+    };
+
+    Config {
+        address: var("ADDRESS", "127.0.0.1"),
+        port: var("PORT", "8080"),
+        path_to_resources,
+        require_resources: parse_bool(&var("REQUIRE_RESOURCES", "false")),
+        handler_timeout: parse_duration_ms(&var("HANDLER_TIMEOUT_MS", "0")),
+        workers: var("WORKERS", "4").parse().unwrap_or(4).max(1),
+        log_requests: parse_bool(&var("LOG_REQUESTS", "false")),
+        drain_timeout: parse_duration_ms(&var("DRAIN_TIMEOUT_MS", "30000")),
+        directory_listing: parse_bool(&var("DIRECTORY_LISTING", "false")),
+        clock: Arc::new(SystemClock),
+        cache_rules: parse_cache_rules(&var("CACHE_RULES", "")),
+        slow_request_threshold: parse_duration_ms(&var("SLOW_REQUEST_MS", "1000")),
+        trusted_proxies: parse_trusted_proxies(&var("TRUSTED_PROXIES", "")),
+        auth: parse_auth(
+            &var("AUTH", "none"),
+            &var("AUTH_REALM", "rust-webserver"),
+            &var("AUTH_CREDENTIALS", ""),
+        ),
+        queue_limit: match var("QUEUE_LIMIT", "0").parse() {
+            Ok(0) | Err(_) => None,
+            Ok(limit) => Some(limit),
+        },
+        retry_after: Duration::from_secs(var("RETRY_AFTER_SECS", "1").parse().unwrap_or(1)),
+        log_format: LogFormat::parse(&var("LOG_FORMAT", "text")),
+        log_timezone: Timezone::parse(&var("LOG_TIMEZONE", "utc")),
+        not_found: NotFoundResponse::Page,
+        max_requests_per_connection: match var("MAX_REQUESTS_PER_CONNECTION", "100").parse() {
+            Ok(0) | Err(_) => None,
+            Ok(max) => Some(max),
+        },
+        keep_alive_timeout: parse_duration_ms(&var("KEEP_ALIVE_TIMEOUT_MS", "5000")),
+        request_timeout: parse_duration_ms(&var("REQUEST_TIMEOUT_MS", "10000")),
+        syslog: Some(var("SYSLOG", "")).filter(|target| !target.is_empty()),
+        syslog_facility: var("SYSLOG_FACILITY", "user"),
+        max_uri_length: match var("MAX_URI_LENGTH", "8192").parse() {
+            Ok(0) | Err(_) => None,
+            Ok(max) => Some(max),
+        },
+        max_header_value_length: match var("MAX_HEADER_VALUE_LENGTH", "8192").parse() {
+            Ok(0) | Err(_) => None,
+            Ok(max) => Some(max),
+        },
+        max_headers: match var("MAX_HEADERS", "100").parse() {
+            Ok(0) | Err(_) => None,
+            Ok(max) => Some(max),
+        },
+        max_header_bytes: match var("MAX_HEADER_BYTES", "65536").parse() {
+            Ok(0) | Err(_) => None,
+            Ok(max) => Some(max),
+        },
+        read_buffer_size: var("READ_BUFFER_SIZE", "8192")
+            .parse()
+            .unwrap_or(8192)
+            .max(1),
+        template_content_type: var("TEMPLATE_CONTENT_TYPE", "text/html"),
+        template_keep_unknown: parse_bool(&var("TEMPLATE_KEEP_UNKNOWN", "true")),
+        trace: parse_bool(&var("TRACE", "false")),
+        max_connections_per_ip: match var("MAX_CONNECTIONS_PER_IP", "0").parse() {
+            Ok(0) | Err(_) => None,
+            Ok(max) => Some(max),
+        },
+        empty_handler_status: var("EMPTY_HANDLER_STATUS", "204")
+            .parse()
+            .ok()
+            .and_then(StatusCode::from_u16)
+            .unwrap_or(StatusCode::NoContent),
+        allowed_extensions: parse_extensions(&var("ALLOWED_EXTENSIONS", "")),
+        serve_hidden_files: parse_bool(&var("SERVE_HIDDEN_FILES", "false")),
+        unknown_host: UnknownHostResponse::parse(&var("UNKNOWN_HOST", "404")),
+        max_response_body: match var("MAX_RESPONSE_BODY", "0").parse() {
+            Ok(0) | Err(_) => None,
+            Ok(max) => Some(max),
+        },
+        oversized_response: OversizedResponse::parse(&var("OVERSIZED_RESPONSE", "error")),
+        keep_alive_header: parse_bool(&var("KEEP_ALIVE_HEADER", "true")),
+        root: RootResponse::parse(&var("ROOT", "index.html")),
+        background_logging: parse_bool(&var("BACKGROUND_LOGGING", "false")),
+        favicon: FaviconResponse::parse(&var("FAVICON", "")),
+        log_flush: FlushPolicy::parse(&var("LOG_FLUSH", "")),
+        access_log_sampler: LogSampler::new(var("LOG_SAMPLE_RATE", "1").parse().unwrap_or(1)),
+        header_order: parse_header_order(&var("HEADER_ORDER", "")),
+        get_body: GetBody::parse(&var("GET_BODY", "discard")),
+        write_timeout: parse_duration_ms(&var("WRITE_TIMEOUT_MS", "30000")),
+        reserved_workers: var("RESERVED_WORKERS", "0").parse().unwrap_or(0),
+        log_query: QueryLogging::parse(&var("LOG_QUERY", "full")),
+        memory_budget: MemoryBudget::new(match var("MEMORY_BUDGET", "0").parse() {
+            Ok(0) | Err(_) => None,
+            Ok(max) => Some(max),
+        }),
+        etag_algorithm: EtagAlgorithm::parse(&var("ETAG_ALGORITHM", "fnv")),
+        server_timing: parse_bool(&var("SERVER_TIMING", "false")),
+        upload_dir: lookup("UPLOAD_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir),
+        max_upload_size: var("MAX_UPLOAD_SIZE", "104857600")
+            .parse()
+            .unwrap_or(100 * 1024 * 1024),
+        log_level: LogLevel::parse(&var("LOG_LEVEL", "debug")),
+        routes_endpoint: parse_bool(&var("ROUTES_ENDPOINT", "false")),
+    }
+}
+
+pub fn socket_address(address: &str, port: &str) -> String {
+    /*
+    Join an address and a port into an address to bind to, e.g. "127.0.0.1:8080".
+    IPv6 literals are put in brackets, e.g. "[::1]:8080", whether or not
+    the address is already given in brackets.
+     */
+    let address = address.trim();
+    let unbracketed = address
+        .strip_prefix('[')
+        .and_then(|address| address.strip_suffix(']'))
+        .unwrap_or(address);
+    if unbracketed.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", unbracketed, port)
+    } else {
+        format!("{}:{}", address, port)
+    }
+}
+
+pub fn validate_resources(path: &Path) -> Result<(), ResourcesError> {
+    /*
+    Validate that the resources directory exists and is a directory.
+    This is meant to be called once at startup.
+     */
+    if !path.exists() {
+        return Err(ResourcesError::Missing(path.to_path_buf()));
+    }
+    if !path.is_dir() {
+        return Err(ResourcesError::NotADirectory(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_validate_resources() {
+        // Test that the bundled resources directory is accepted.
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res");
+        assert_eq!(validate_resources(&path), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_missing_resources() {
+        // Test that a missing resources directory is detected.
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("does-not-exist");
+        assert_eq!(
+            validate_resources(&path),
+            Err(ResourcesError::Missing(path.clone()))
+        );
+    }
+
+    #[test]
+    fn test_validate_resources_not_a_directory() {
+        // Test that a file in place of the resources directory is detected.
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        assert_eq!(
+            validate_resources(&path),
+            Err(ResourcesError::NotADirectory(path.clone()))
+        );
+    }
+
+    #[test]
+    fn test_socket_address() {
+        // Test that IPv6 literals are bracketed and other addresses are not.
+        assert_eq!(socket_address("127.0.0.1", "8080"), "127.0.0.1:8080");
+        assert_eq!(socket_address("::1", "8080"), "[::1]:8080");
+        assert_eq!(socket_address("[::1]", "8080"), "[::1]:8080");
+        assert_eq!(socket_address("localhost", "80"), "localhost:80");
+    }
+
+    #[test]
+    fn test_parse_duration_ms() {
+        // Test parsing of durations in milliseconds.
+        assert_eq!(parse_duration_ms("250"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration_ms("0"), None);
+        assert_eq!(parse_duration_ms("soon"), None);
+    }
+
+    #[test]
+    fn test_parse_unknown_host() {
+        // Test parsing how requests for unknown hosts are answered.
+        assert_eq!(
+            UnknownHostResponse::parse("404"),
+            UnknownHostResponse::NotFound
+        );
+        assert_eq!(
+            UnknownHostResponse::parse("Shared"),
+            UnknownHostResponse::Shared
+        );
+        assert_eq!(
+            UnknownHostResponse::parse(" www.example.com "),
+            UnknownHostResponse::DefaultHost("www.example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_root() {
+        // Test parsing how requests for / are answered.
+        assert_eq!(
+            RootResponse::parse("index.html"),
+            RootResponse::Serve("index.html".to_string())
+        );
+        assert_eq!(
+            RootResponse::parse("redirect: /docs/"),
+            RootResponse::Redirect("/docs/".to_string())
+        );
+        assert_eq!(RootResponse::parse("404"), RootResponse::NotFound);
+        assert_eq!(RootResponse::parse(""), RootResponse::NotFound);
+    }
+
+    #[test]
+    fn test_parse_favicon() {
+        // Test parsing how requests for /favicon.ico are answered.
+        assert_eq!(FaviconResponse::parse(""), FaviconResponse::NotFound);
+        assert_eq!(FaviconResponse::parse("OFF"), FaviconResponse::NotFound);
+        assert_eq!(FaviconResponse::parse("default"), FaviconResponse::Default);
+        assert_eq!(
+            FaviconResponse::parse("icons/site.ico"),
+            FaviconResponse::File(PathBuf::from("icons/site.ico"))
+        );
+    }
+
+    #[test]
+    fn test_parse_query_logging() {
+        // Test parsing how query strings are logged.
+        assert_eq!(QueryLogging::parse("strip"), QueryLogging::Strip);
+        assert_eq!(QueryLogging::parse(" Redact "), QueryLogging::Redact);
+        assert_eq!(QueryLogging::parse(""), QueryLogging::Full);
+    }
+
+    #[test]
+    fn test_parse_get_body() {
+        // Test parsing what happens to the body of a GET request.
+        assert_eq!(GetBody::parse("Reject"), GetBody::Reject);
+        assert_eq!(GetBody::parse("discard"), GetBody::Discard);
+        assert_eq!(GetBody::parse("unknown"), GetBody::Discard);
+    }
+
+    #[test]
+    fn test_config_from() {
+        // Test building the configuration from variables with defaults for missing ones.
+        let variables = HashMap::from([
+            ("PORT", "9090"),
+            ("WORKERS", "8"),
+            ("RESOURCES", "/srv/www"),
+            ("KEEP_ALIVE_TIMEOUT_MS", "0"),
+        ]);
+        let config = config_from(&|key| variables.get(key).map(|value| value.to_string()));
+
+        assert_eq!(config.address, "127.0.0.1");
+        assert_eq!(config.port, "9090");
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.path_to_resources, PathBuf::from("/srv/www"));
+        assert_eq!(config.keep_alive_timeout, None);
+        assert_eq!(config.request_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(config.handler_timeout, None);
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert!(!config.routes_endpoint);
+    }
+
+    #[test]
+    fn test_parse_bool() {
+        // Test parsing of boolean flags.
+        assert!(parse_bool("true"));
+        assert!(parse_bool("1"));
+        assert!(!parse_bool("false"));
+        assert!(!parse_bool(""));
+    }
+}
@@ -0,0 +1,193 @@
+/*
+* Reading of the configuration from a TOML file.
+*/
+
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    config::{config_from, Config},
+    error::Error,
+};
+
+pub fn load_config(path: &Path) -> Result<Config, Error> {
+    /*
+    Get the configuration from a TOML file, e.g. with `workers = 8`.
+    Keys are the names of the environment variables in lower case and
+    environment variables which are set take precedence over the file.
+     */
+    let text = fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("could not read {}: {}", path.display(), e)))?;
+    let values =
+        parse_toml(&text).map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+    Ok(config_from_layers(&values, &|key| std::env::var(key).ok()))
+}
+
+fn config_from_layers(
+    values: &HashMap<String, String>,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Config {
+    // Build the configuration from the values of a file, overridden by the
+    // variables found by lookup.
+    config_from(&|key| lookup(key).or_else(|| values.get(&key.to_ascii_lowercase()).cloned()))
+}
+
+pub fn parse_toml(text: &str) -> Result<HashMap<String, String>, String> {
+    /*
+    Parse the top-level keys of a TOML document into their values as text.
+    Only the subset needed for configuration is supported: strings, integers and
+    booleans assigned to bare keys, and comments. Tables and arrays are rejected.
+     */
+    let mut values = HashMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(error("tables are not supported"));
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected key = value"))?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(error("invalid key"));
+        }
+        let value = parse_value(value.trim()).map_err(|message| error(&message))?;
+        if values.insert(key.to_ascii_lowercase(), value).is_some() {
+            return Err(error("duplicate key"));
+        }
+    }
+    Ok(values)
+}
+
+fn parse_value(value: &str) -> Result<String, String> {
+    // Parse a value followed by an optional comment.
+    if let Some(rest) = value.strip_prefix('"') {
+        return parse_basic_string(rest);
+    }
+    if let Some(rest) = value.strip_prefix('\'') {
+        let (string, rest) = rest
+            .split_once('\'')
+            .ok_or_else(|| "unterminated string".to_string())?;
+        return end_of_value(rest).map(|_| string.to_string());
+    }
+
+    let value = match value.split_once('#') {
+        Some((value, _)) => value.trim_end(),
+        None => value,
+    };
+    match value {
+        "true" | "false" => Ok(value.to_string()),
+        _ => {
+            let digits = value.replace('_', "");
+            digits
+                .parse::<i64>()
+                .map(|number| number.to_string())
+                .map_err(|_| format!("unsupported value {}", value))
+        }
+    }
+}
+
+fn parse_basic_string(rest: &str) -> Result<String, String> {
+    // Parse the rest of a string in double quotes, resolving its escapes.
+    let mut string = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return end_of_value(&rest[index + 1..]).map(|_| string),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('"') => string.push('"'),
+                Some('\\') => string.push('\\'),
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                _ => return Err("unsupported escape".to_string()),
+            },
+            c => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+fn end_of_value(rest: &str) -> Result<(), String> {
+    // Check that only a comment follows a value.
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err("unexpected text after value".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_toml() {
+        // Test parsing values and rejecting unsupported documents.
+        let values = parse_toml(
+            "# webserver\n\
+             address = \"0.0.0.0\" # all interfaces\n\
+             workers = 1_6\n\
+             trace = true\n\
+             realm = 'a \"quoted\" realm'\n\
+             escaped = \"a\\\\b\\\"c\"\n",
+        )
+        .unwrap();
+        assert_eq!(values["address"], "0.0.0.0");
+        assert_eq!(values["workers"], "16");
+        assert_eq!(values["trace"], "true");
+        assert_eq!(values["realm"], "a \"quoted\" realm");
+        assert_eq!(values["escaped"], "a\\b\"c");
+
+        assert_eq!(
+            parse_toml("[server]\nport = 1"),
+            Err("line 1: tables are not supported".to_string())
+        );
+        assert!(parse_toml("port = 1\nport = 2").is_err());
+        assert!(parse_toml("proxies = [\"a\"]").is_err());
+        assert!(parse_toml("name = \"open").is_err());
+    }
+
+    #[test]
+    fn test_load_config() {
+        // Test that a configuration file is turned into the expected configuration,
+        // with variables overriding its values.
+        let values = parse_toml(
+            "address = \"0.0.0.0\"\n\
+             port = 9000\n\
+             workers = 12\n\
+             request_timeout_ms = 2500\n\
+             log_format = \"json\"\n\
+             log_level = \"warning\"\n\
+             resources = \"/srv/www\"\n",
+        )
+        .unwrap();
+        let variables = HashMap::from([("PORT", "9090")]);
+        let config = config_from_layers(&values, &|key| {
+            variables.get(key).map(|value| value.to_string())
+        });
+
+        assert_eq!(config.address, "0.0.0.0");
+        assert_eq!(config.port, "9090");
+        assert_eq!(config.workers, 12);
+        assert_eq!(config.request_timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(config.log_format, crate::logger::log::LogFormat::Json);
+        assert_eq!(config.log_level, crate::logger::log::LogLevel::Warning);
+        assert_eq!(config.path_to_resources, Path::new("/srv/www"));
+
+        let path = std::env::temp_dir().join(format!(
+            "rust-webserver-{}-missing.toml",
+            std::process::id()
+        ));
+        assert!(matches!(load_config(&path), Err(Error::Config(_))));
+    }
+}
@@ -0,0 +1,157 @@
+/*
+* The error type of the webserver.
+*/
+
+use std::{fmt, io};
+
+use crate::{
+    config::ResourcesError,
+    http::{request::HTTPError, status::StatusCode},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    // Reading from or writing to a client or file failed.
+    Io(io::Error),
+    // The listener could not be bound to the address.
+    Bind(String, io::Error),
+    // The request of a client could not be parsed.
+    Parse(HTTPError),
+    // A route could not be registered or resolved.
+    Route(String),
+    // A handler failed to produce a response.
+    Handler(HandlerError),
+    // The resources directory is unusable.
+    Resources(ResourcesError),
+    // The configuration file could not be read or parsed.
+    Config(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandlerError {
+    // The handler did not finish within the handler timeout.
+    TimedOut,
+    // The handler panicked.
+    Panicked,
+}
+
+impl Error {
+    pub fn status_code(&self) -> StatusCode {
+        // Get the status code of the response sent for this error.
+        match self {
+            Error::Parse(e) => e.status_code(),
+            Error::Route(_) => StatusCode::NotFound,
+            Error::Handler(HandlerError::TimedOut) => StatusCode::ServiceUnavailable,
+            Error::Handler(HandlerError::Panicked)
+            | Error::Io(_)
+            | Error::Bind(..)
+            | Error::Resources(_)
+            | Error::Config(_) => StatusCode::InternalServerError,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Bind(address, e) => {
+                write!(f, "could not bind to {}: {}", address, e)?;
+                if let Some(hint) = bind_hint(address, e) {
+                    write!(f, "; {}", hint)?;
+                }
+                Ok(())
+            }
+            Error::Parse(e) => write!(f, "invalid request: {:?}", e),
+            Error::Route(message) => write!(f, "route error: {}", message),
+            Error::Handler(HandlerError::TimedOut) => write!(f, "handler timed out"),
+            Error::Handler(HandlerError::Panicked) => write!(f, "handler panicked"),
+            Error::Resources(e) => write!(f, "{}", e),
+            Error::Config(message) => write!(f, "configuration error: {}", message),
+        }
+    }
+}
+
+fn bind_hint(address: &str, error: &io::Error) -> Option<&'static str> {
+    /*
+    Get advice on fixing a failed bind, e.g. for a privileged port without the privileges.
+     */
+    let port = address.rsplit(':').next()?.parse::<u16>().ok()?;
+    if error.kind() == io::ErrorKind::PermissionDenied && port != 0 && port < 1024 {
+        return Some(
+            "ports below 1024 need root or the CAP_NET_BIND_SERVICE capability, \
+             e.g. `sudo setcap cap_net_bind_service=+ep <binary>`, \
+             or use a port of 1024 or above such as PORT=8080",
+        );
+    }
+    None
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) | Error::Bind(_, e) => Some(e),
+            Error::Resources(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<HTTPError> for Error {
+    fn from(error: HTTPError) -> Self {
+        Error::Parse(error)
+    }
+}
+
+impl From<HandlerError> for Error {
+    fn from(error: HandlerError) -> Self {
+        Error::Handler(error)
+    }
+}
+
+impl From<ResourcesError> for Error {
+    fn from(error: ResourcesError) -> Self {
+        Error::Resources(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_conversions() {
+        // Test that conversions produce the matching variant.
+        let error: Error = io::Error::new(io::ErrorKind::BrokenPipe, "closed").into();
+        assert!(matches!(error, Error::Io(_)));
+
+        let error: Error = HTTPError::InvalidRequest.into();
+        assert!(matches!(error, Error::Parse(HTTPError::InvalidRequest)));
+
+        let error: Error = HandlerError::TimedOut.into();
+        assert_eq!(error.status_code(), StatusCode::ServiceUnavailable);
+        assert_eq!(error.to_string(), "handler timed out");
+    }
+
+    #[test]
+    fn test_privileged_bind_error() {
+        // Test that a denied bind to a privileged port explains how to fix it.
+        let denied = || io::Error::from(io::ErrorKind::PermissionDenied);
+        let error = Error::Bind("0.0.0.0:80".to_string(), denied());
+        let message = error.to_string();
+        assert!(message.starts_with("could not bind to 0.0.0.0:80: "));
+        assert!(message.contains("CAP_NET_BIND_SERVICE"));
+        assert!(message.contains("PORT=8080"));
+
+        let error = Error::Bind("[::1]:8080".to_string(), denied());
+        assert!(!error.to_string().contains("CAP_NET_BIND_SERVICE"));
+        let error = Error::Bind("0.0.0.0:80".to_string(), io::ErrorKind::AddrInUse.into());
+        assert!(!error.to_string().contains("CAP_NET_BIND_SERVICE"));
+    }
+}
@@ -0,0 +1,206 @@
+/*
+* Authentication of requests with HTTP Basic credentials or Bearer tokens.
+*/
+
+use super::request::Request;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Auth {
+    // Requests are not authenticated.
+    #[default]
+    None,
+    // Requests must send one of the user and password pairs.
+    Basic {
+        realm: String,
+        credentials: Vec<(String, String)>,
+    },
+    // Requests must send one of the tokens.
+    Bearer {
+        realm: String,
+        tokens: Vec<String>,
+    },
+}
+
+impl Auth {
+    pub fn challenge(&self) -> Option<String> {
+        // Get the WWW-Authenticate value sent with a 401 for this strategy.
+        match self {
+            Auth::None => None,
+            Auth::Basic { realm, .. } => Some(format!("Basic realm=\"{}\"", realm)),
+            Auth::Bearer { realm, .. } => Some(format!("Bearer realm=\"{}\"", realm)),
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        // Describe the strategy for logs without revealing any credentials,
+        // e.g. "basic (2 credentials)".
+        match self {
+            Auth::None => "none".to_string(),
+            Auth::Basic { credentials, .. } => format!("basic ({} credentials)", credentials.len()),
+            Auth::Bearer { tokens, .. } => format!("bearer ({} tokens)", tokens.len()),
+        }
+    }
+}
+
+pub fn parse_auth(strategy: &str, realm: &str, credentials: &str) -> Auth {
+    /*
+    Parse the authentication strategy, e.g. "basic" with credentials
+    "alice:secret,bob:hunter2" or "bearer" with tokens "token1,token2".
+    An unknown strategy disables authentication.
+     */
+    let entries = credentials
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty());
+    let realm = realm.to_string();
+    match strategy.trim().to_ascii_lowercase().as_str() {
+        "basic" => Auth::Basic {
+            realm,
+            credentials: entries
+                .filter_map(|entry| entry.split_once(':'))
+                .map(|(user, password)| (user.to_string(), password.to_string()))
+                .collect(),
+        },
+        "bearer" => Auth::Bearer {
+            realm,
+            tokens: entries.map(str::to_string).collect(),
+        },
+        _ => Auth::None,
+    }
+}
+
+pub fn is_authorized(auth: &Auth, request: &Request) -> bool {
+    /*
+    Check whether a request sends credentials accepted by the strategy.
+     */
+    let authorization = request.header("Authorization");
+    match auth {
+        Auth::None => true,
+        Auth::Basic { credentials, .. } => {
+            let decoded = authorization
+                .and_then(|value| strip_scheme(value, "Basic"))
+                .and_then(decode_base64)
+                .and_then(|decoded| String::from_utf8(decoded).ok());
+            let (user, password) = match decoded.as_deref().and_then(|d| d.split_once(':')) {
+                Some(pair) => pair,
+                None => return false,
+            };
+            credentials
+                .iter()
+                .any(|(expected_user, expected_password)| {
+                    constant_time_eq(user, expected_user)
+                        & constant_time_eq(password, expected_password)
+                })
+        }
+        Auth::Bearer { tokens, .. } => {
+            match authorization.and_then(|v| strip_scheme(v, "Bearer")) {
+                Some(token) => tokens
+                    .iter()
+                    .any(|expected| constant_time_eq(token, expected)),
+                None => false,
+            }
+        }
+    }
+}
+
+fn strip_scheme<'a>(value: &'a str, scheme: &str) -> Option<&'a str> {
+    // Get the credentials of an Authorization value using the scheme.
+    // Schemes are compared case-insensitively.
+    let (name, credentials) = value.trim().split_once(' ')?;
+    if name.eq_ignore_ascii_case(scheme) {
+        Some(credentials.trim())
+    } else {
+        None
+    }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    // Compare two secrets without returning early on the first difference.
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    // Decode standard base64 with optional padding.
+    let value = value.trim_end_matches('=');
+    let mut decoded = Vec::with_capacity(value.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in value.bytes() {
+        let digit = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | digit as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::parse_request;
+
+    fn request_with_authorization(value: &str) -> Request {
+        // Parse a request with an Authorization header.
+        let raw = format!("GET / HTTP/1.1\r\nAuthorization: {}\r\n\r\n", value);
+        parse_request(&mut raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_auth() {
+        // Test parsing of the authentication strategies.
+        assert_eq!(
+            parse_auth("Basic", "admin", "alice:secret, bob"),
+            Auth::Basic {
+                realm: "admin".to_string(),
+                credentials: vec![("alice".to_string(), "secret".to_string())],
+            }
+        );
+        assert_eq!(
+            parse_auth("bearer", "api", "t1,t2"),
+            Auth::Bearer {
+                realm: "api".to_string(),
+                tokens: vec!["t1".to_string(), "t2".to_string()],
+            }
+        );
+        assert_eq!(parse_auth("none", "", "t1"), Auth::None);
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        // Test verification of Basic credentials.
+        let auth = parse_auth("basic", "admin", "alice:secret");
+        // "alice:secret" and "alice:wrong"
+        assert!(is_authorized(
+            &auth,
+            &request_with_authorization("Basic YWxpY2U6c2VjcmV0")
+        ));
+        assert!(!is_authorized(
+            &auth,
+            &request_with_authorization("Basic YWxpY2U6d3Jvbmc=")
+        ));
+        assert!(!is_authorized(
+            &auth,
+            &request_with_authorization("Bearer YWxpY2U6c2VjcmV0")
+        ));
+        assert_eq!(auth.challenge().unwrap(), "Basic realm=\"admin\"");
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        // Test decoding base64 with and without padding.
+        assert_eq!(decode_base64("aGk=").unwrap(), b"hi");
+        assert_eq!(decode_base64("aGk").unwrap(), b"hi");
+        assert_eq!(decode_base64("aGVsbG8h").unwrap(), b"hello!");
+        assert_eq!(decode_base64("a*b"), None);
+    }
+}
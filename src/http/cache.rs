@@ -0,0 +1,137 @@
+/*
+* Cache-Control and Expires headers for served files.
+*/
+
+use std::time::{Duration, SystemTime};
+
+use super::{file::http_date, response::Response};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheRule {
+    // Request paths starting with the prefix use the Cache-Control value.
+    pub prefix: String,
+    pub cache_control: String,
+}
+
+pub fn parse_cache_rules(value: &str) -> Vec<CacheRule> {
+    /*
+    Parse cache rules in the form "prefix=cache-control;prefix=cache-control",
+    e.g. "/static/=public, max-age=31536000, immutable;/=no-cache".
+     */
+    value
+        .split(';')
+        .filter_map(|rule| rule.split_once('='))
+        .map(|(prefix, cache_control)| CacheRule {
+            prefix: prefix.trim().to_string(),
+            cache_control: cache_control.trim().to_string(),
+        })
+        .filter(|rule| !rule.prefix.is_empty() && !rule.cache_control.is_empty())
+        .collect()
+}
+
+pub fn find_cache_rule<'a>(rules: &'a [CacheRule], path: &str) -> Option<&'a CacheRule> {
+    /*
+    Find the rule with the longest prefix matching the request path.
+     */
+    rules
+        .iter()
+        .filter(|rule| path.starts_with(&rule.prefix))
+        .max_by_key(|rule| rule.prefix.len())
+}
+
+pub fn max_age(cache_control: &str) -> Option<u64> {
+    /*
+    Get the max-age directive of a Cache-Control value in seconds.
+     */
+    cache_control
+        .split(',')
+        .filter_map(|directive| directive.trim().split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("max-age"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+}
+
+pub fn apply_cache_headers(
+    response: &mut Response,
+    rules: &[CacheRule],
+    path: &str,
+    now: SystemTime,
+) {
+    /*
+    Add the Cache-Control header of the matching rule to a response.
+    An Expires header is added as well if the rule sets a max-age.
+     */
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let rule = match find_cache_rule(rules, path) {
+        Some(rule) => rule,
+        None => return,
+    };
+
+    response.add_header("Cache-Control", &rule.cache_control);
+    if let Some(max_age) = max_age(&rule.cache_control) {
+        response.add_header("Expires", &http_date(now + Duration::from_secs(max_age)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::status::StatusCode;
+
+    fn rules() -> Vec<CacheRule> {
+        // Long caching for static assets, none for everything else.
+        parse_cache_rules("/static/=public, max-age=31536000, immutable;/=no-cache")
+    }
+
+    #[test]
+    fn test_parse_cache_rules() {
+        // Test parsing of cache rules.
+        assert_eq!(
+            rules(),
+            vec![
+                CacheRule {
+                    prefix: "/static/".to_string(),
+                    cache_control: "public, max-age=31536000, immutable".to_string(),
+                },
+                CacheRule {
+                    prefix: "/".to_string(),
+                    cache_control: "no-cache".to_string(),
+                },
+            ]
+        );
+        assert!(parse_cache_rules("").is_empty());
+    }
+
+    #[test]
+    fn test_max_age() {
+        // Test reading the max-age directive.
+        assert_eq!(max_age("public, max-age=60"), Some(60));
+        assert_eq!(max_age("no-cache"), None);
+    }
+
+    #[test]
+    fn test_apply_cache_headers() {
+        // Test that a file under a configured prefix gets the expected headers.
+        let rules = rules();
+        let now = SystemTime::UNIX_EPOCH;
+
+        let mut response = Response::new(StatusCode::Ok, "text/css", Vec::new());
+        apply_cache_headers(&mut response, &rules, "/static/app.css?v=2", now);
+        assert_eq!(
+            response.header("Cache-Control"),
+            Some("public, max-age=31536000, immutable")
+        );
+        assert_eq!(
+            response.header("Expires"),
+            Some("Fri, 01 Jan 1971 00:00:00 GMT")
+        );
+
+        let mut response = Response::new(StatusCode::Ok, "text/html", Vec::new());
+        apply_cache_headers(&mut response, &rules, "/index.html", now);
+        assert_eq!(response.header("Cache-Control"), Some("no-cache"));
+        assert_eq!(response.header("Expires"), None);
+
+        let mut response = Response::new(StatusCode::Ok, "text/html", Vec::new());
+        apply_cache_headers(&mut response, &[], "/index.html", now);
+        assert_eq!(response.header("Cache-Control"), None);
+    }
+}
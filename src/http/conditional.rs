@@ -0,0 +1,110 @@
+/*
+* Evaluation of conditional request headers against entity tags.
+*/
+
+use super::{file::parse_http_date, request::Request};
+
+pub fn parse_entity_tags(value: &str) -> Vec<&str> {
+    /*
+    Split a comma separated list of entity tags, e.g. `"a", W/"b"`.
+    Commas inside the quotes of a tag don't split it.
+     */
+    let mut tags = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                tags.push(value[start..index].trim());
+                start = index + 1;
+            }
+            _ => (),
+        }
+    }
+    tags.push(value[start..].trim());
+    tags.into_iter().filter(|tag| !tag.is_empty()).collect()
+}
+
+pub fn weak_eq(a: &str, b: &str) -> bool {
+    /*
+    Compare two entity tags with the weak comparison function, which ignores
+    the weak indicator, so W/"a" matches "a".
+     */
+    let a = a.strip_prefix("W/").unwrap_or(a);
+    let b = b.strip_prefix("W/").unwrap_or(b);
+    a == b
+}
+
+pub fn if_none_match_matches(value: &str, etag: &str) -> bool {
+    /*
+    Check whether an If-None-Match header matches the entity tag of an existing resource.
+    The header may list several tags and "*" matches any existing resource.
+     */
+    if value.trim() == "*" {
+        return true;
+    }
+    parse_entity_tags(value)
+        .iter()
+        .any(|tag| weak_eq(tag, etag))
+}
+
+pub fn is_not_modified(request: &Request, etag: Option<&str>, last_modified: Option<&str>) -> bool {
+    /*
+    Check whether the copy a client has of a resource with the given validators
+    is current, so a GET or HEAD can be answered with 304.
+    If-None-Match takes precedence, If-Modified-Since is only evaluated
+    without it and against a Last-Modified date.
+     */
+    if !matches!(request.method.as_str(), "GET" | "HEAD") {
+        return false;
+    }
+    if let Some(value) = request.header("If-None-Match") {
+        return etag.is_some_and(|etag| if_none_match_matches(value, etag));
+    }
+    match (
+        request
+            .header("If-Modified-Since")
+            .and_then(parse_http_date),
+        last_modified.and_then(parse_http_date),
+    ) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entity_tags() {
+        // Test splitting lists of entity tags.
+        assert_eq!(
+            parse_entity_tags("\"a\", W/\"b\",\"c,d\""),
+            vec!["\"a\"", "W/\"b\"", "\"c,d\""]
+        );
+        assert!(parse_entity_tags("").is_empty());
+    }
+
+    #[test]
+    fn test_if_none_match_multiple_values() {
+        // Test that any tag of a list can match.
+        assert!(if_none_match_matches("\"x\", \"abc\"", "\"abc\""));
+        assert!(!if_none_match_matches("\"x\", \"y\"", "\"abc\""));
+    }
+
+    #[test]
+    fn test_if_none_match_weak() {
+        // Test the weak comparison of entity tags.
+        assert!(if_none_match_matches("W/\"abc\"", "\"abc\""));
+        assert!(if_none_match_matches("\"abc\"", "W/\"abc\""));
+        assert!(!if_none_match_matches("W/\"abd\"", "\"abc\""));
+    }
+
+    #[test]
+    fn test_if_none_match_any() {
+        // Test that "*" matches any existing resource.
+        assert!(if_none_match_matches("*", "\"abc\""));
+    }
+}
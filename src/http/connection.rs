@@ -0,0 +1,56 @@
+/*
+* The stream of a client connection, whose read timeout changes between the
+* phases of a connection and whose writes may time out.
+*/
+
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use super::counting::CountingStream;
+
+pub trait Connection: Read + Write {
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        // Set how long a read may block before failing with a timeout.
+        // Streams without timeouts, e.g. in-memory buffers, ignore it.
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        // Set how long a write may block on a client that stopped reading.
+        // Streams without timeouts ignore it.
+        Ok(())
+    }
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+impl<C: Connection + ?Sized> Connection for &mut C {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+}
+
+impl<C: Connection> Connection for CountingStream<C> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_mut().set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_mut().set_write_timeout(timeout)
+    }
+}
@@ -0,0 +1,89 @@
+/*
+* A stream adapter which counts the bytes read from and written to a client.
+*/
+
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ByteCounts {
+    // Bytes read from the client.
+    pub read: u64,
+    // Bytes written to the client.
+    pub written: u64,
+}
+
+pub struct CountingStream<S> {
+    inner: S,
+    counts: ByteCounts,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S) -> CountingStream<S> {
+        // Wrap a stream to count the bytes passing through it.
+        CountingStream {
+            inner,
+            counts: ByteCounts::default(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        // Get the wrapped stream, e.g. to change its timeouts.
+        &mut self.inner
+    }
+
+    pub fn counts(&self) -> ByteCounts {
+        // Get the bytes read and written so far.
+        self.counts
+    }
+}
+
+impl<S: Read> Read for CountingStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.counts.read += read as u64;
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for CountingStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.counts.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counting_stream() {
+        // Test counting bytes read and written.
+        let mut reader = CountingStream::new("hello world".as_bytes());
+        let mut buf = [0; 5];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            reader.counts(),
+            ByteCounts {
+                read: 5,
+                written: 0
+            }
+        );
+
+        let mut writer = CountingStream::new(Vec::new());
+        writer.write_all(b"hello").unwrap();
+        writer.write_all(b" world").unwrap();
+        assert_eq!(
+            writer.counts(),
+            ByteCounts {
+                read: 0,
+                written: 11
+            }
+        );
+    }
+}
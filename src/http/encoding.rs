@@ -0,0 +1,118 @@
+/*
+* Negotiation of precompressed siblings of served files, e.g. app.js.br for app.js.
+*/
+
+use std::path::{Path, PathBuf};
+
+// Content codings of precompressed siblings with their file suffix, in order of preference.
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+pub fn accepts_encoding(accept_encoding: &str, coding: &str) -> bool {
+    /*
+    Check whether an Accept-Encoding header accepts a content coding,
+    e.g. "gzip, br;q=0.5" accepts br while "*, br;q=0" does not.
+    A coding listed by name takes precedence over the "*" wildcard.
+     */
+    let mut wildcard = false;
+    for element in accept_encoding.split(',') {
+        let mut params = element.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let accepted = params
+            .filter_map(|param| param.trim().split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("q"))
+            .is_none_or(|(_, q)| q.trim().parse::<f32>().is_ok_and(|q| q > 0.0));
+
+        if name.eq_ignore_ascii_case(coding) {
+            return accepted;
+        }
+        if name == "*" {
+            wildcard = accepted;
+        }
+    }
+    wildcard
+}
+
+pub fn precompressed_sibling(
+    path: &Path,
+    accept_encoding: Option<&str>,
+) -> Option<(PathBuf, &'static str)> {
+    /*
+    Get the precompressed sibling of a file to serve instead of it, with its content coding.
+    Brotli is preferred over gzip when both siblings exist and are accepted.
+    Returns None if no accepted sibling exists, so the file is served as is.
+     */
+    let accept_encoding = accept_encoding?;
+    PRECOMPRESSED
+        .iter()
+        .filter(|(coding, _)| accepts_encoding(accept_encoding, coding))
+        .map(|(coding, suffix)| (sibling_path(path, suffix), *coding))
+        .find(|(sibling, _)| sibling.is_file())
+}
+
+pub fn has_precompressed_sibling(path: &Path) -> bool {
+    /*
+    Check whether a file has any precompressed sibling, in which case its
+    responses depend on the Accept-Encoding of the request.
+     */
+    PRECOMPRESSED
+        .iter()
+        .any(|(_, suffix)| sibling_path(path, suffix).is_file())
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    // Get the path of a file with a suffix appended, e.g. app.js.br for app.js.
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(".");
+    sibling.push(suffix);
+    PathBuf::from(sibling)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_accepts_encoding() {
+        // Test parsing of Accept-Encoding with quality values and wildcards.
+        assert!(accepts_encoding("gzip, br", "br"));
+        assert!(accepts_encoding("GZIP;q=0.5", "gzip"));
+        assert!(!accepts_encoding("gzip;q=0", "gzip"));
+        assert!(!accepts_encoding("gzip", "br"));
+        assert!(accepts_encoding("*", "br"));
+        assert!(!accepts_encoding("*, br;q=0", "br"));
+        assert!(!accepts_encoding("", "gzip"));
+    }
+
+    #[test]
+    fn test_precompressed_sibling() {
+        // Test that Brotli is preferred, falling back through gzip to the file itself.
+        let dir =
+            std::env::temp_dir().join(format!("rust-webserver-{}-encoding", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.js");
+        fs::write(&path, "plain").unwrap();
+        fs::write(dir.join("app.js.br"), "brotli").unwrap();
+        fs::write(dir.join("app.js.gz"), "gzip").unwrap();
+
+        let br = (dir.join("app.js.br"), "br");
+        let gz = (dir.join("app.js.gz"), "gzip");
+        assert_eq!(
+            precompressed_sibling(&path, Some("gzip, br")),
+            Some(br.clone())
+        );
+        assert_eq!(
+            precompressed_sibling(&path, Some("br;q=0.1, gzip")),
+            Some(br)
+        );
+        assert_eq!(precompressed_sibling(&path, Some("gzip")), Some(gz.clone()));
+        assert_eq!(precompressed_sibling(&path, Some("identity")), None);
+        assert_eq!(precompressed_sibling(&path, None), None);
+
+        fs::remove_file(dir.join("app.js.br")).unwrap();
+        assert_eq!(precompressed_sibling(&path, Some("br, gzip")), Some(gz));
+        assert!(has_precompressed_sibling(&path));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+/*
+* A typed map for data attached to a request, e.g. by middleware.
+*/
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+};
+
+#[derive(Clone, Default)]
+pub struct Extensions {
+    // Extensions holds at most one value of each type, keyed by the type.
+    // The values are shared, so cloning a request does not clone them.
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Extensions {
+        // Create an empty map.
+        Extensions::default()
+    }
+
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> bool {
+        // Insert a value, replacing the value of the same type.
+        // Returns whether a value of the type was replaced.
+        self.map
+            .insert(TypeId::of::<T>(), Arc::new(value))
+            .is_some()
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        // Get the value of a type, if one was inserted.
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> bool {
+        // Remove the value of a type, returning whether there was one.
+        self.map.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        // Get the number of values.
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        // Check whether no values were inserted.
+        self.map.is_empty()
+    }
+}
+
+impl PartialEq for Extensions {
+    fn eq(&self, other: &Self) -> bool {
+        // Values of any type can't be compared, so maps are equal if they
+        // share the very same values, e.g. the maps of a request and its clone.
+        self.map.len() == other.map.len()
+            && self.map.iter().all(|(key, value)| {
+                other
+                    .map
+                    .get(key)
+                    .is_some_and(|other| Arc::ptr_eq(value, other))
+            })
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Extensions({} values)", self.map.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct UserId(u32);
+
+    #[test]
+    fn test_extensions() {
+        // Test storing and reading values by their type.
+        let mut extensions = Extensions::new();
+        assert!(extensions.is_empty());
+        assert!(!extensions.insert(UserId(7)));
+        assert!(!extensions.insert("request-1".to_string()));
+        assert_eq!(extensions.get::<UserId>(), Some(&UserId(7)));
+        assert_eq!(
+            extensions.get::<String>().map(String::as_str),
+            Some("request-1")
+        );
+        assert_eq!(extensions.get::<u32>(), None);
+
+        let copy = extensions.clone();
+        assert_eq!(copy, extensions);
+        assert!(extensions.insert(UserId(8)));
+        assert_eq!(extensions.get::<UserId>(), Some(&UserId(8)));
+        assert_eq!(copy.get::<UserId>(), Some(&UserId(7)));
+        assert_ne!(copy, extensions);
+
+        assert!(extensions.remove::<UserId>());
+        assert!(!extensions.remove::<UserId>());
+        assert_eq!(extensions.len(), 1);
+    }
+}
@@ -0,0 +1,774 @@
+/*
+* Serving of files with validators and byte range support.
+*/
+
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    fs::{self, File, Metadata},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use super::{
+    conditional::if_none_match_matches,
+    encoding::{has_precompressed_sibling, precompressed_sibling},
+    hash::{to_hex, Fnv1a, Sha256},
+    mime::content_type,
+    request::Request,
+    response::{FileBody, Response},
+    status::StatusCode,
+    url::{decode_path, split_uri},
+};
+use crate::clock::{civil_from_days, days_from_civil, unix_seconds};
+
+// Files larger than this are streamed instead of read into memory.
+const STREAM_THRESHOLD: u64 = 256 * 1024;
+
+// Content hashes kept before the cache is cleared, bounding its memory.
+const ETAG_CACHE_SIZE: usize = 4096;
+
+type EtagCache = HashMap<(PathBuf, EtagAlgorithm), (u64, Option<SystemTime>, String)>;
+
+lazy_static! {
+    // Tags of hashed files by path and algorithm, along with the size and
+    // modification time they were computed for.
+    static ref ETAG_CACHE: Mutex<EtagCache> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    // The whole file was requested.
+    Full,
+    // An inclusive range of bytes was requested.
+    Partial(u64, u64),
+    // The requested range lies outside the file.
+    Unsatisfiable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum EtagAlgorithm {
+    // Derive the tag from the size and modification time without reading the file.
+    Metadata,
+    // Hash the contents with FNV-1a, fast but not collision resistant,
+    // so its tags are weak.
+    #[default]
+    Fnv,
+    // Hash the contents with SHA-256, for strong tags ranges can be resumed with.
+    Sha256,
+}
+
+impl EtagAlgorithm {
+    pub fn parse(value: &str) -> EtagAlgorithm {
+        // Parse "metadata", "fnv" or "sha256", falling back to FNV-1a for unknown values.
+        match value.trim().to_ascii_lowercase().as_str() {
+            "metadata" => EtagAlgorithm::Metadata,
+            "sha256" | "sha-256" => EtagAlgorithm::Sha256,
+            _ => EtagAlgorithm::Fnv,
+        }
+    }
+}
+
+pub fn resolve_path(root: &Path, uri: &str) -> Option<PathBuf> {
+    /*
+    Map the path of a request uri onto a path below the root directory.
+    The path is percent-decoded, e.g. "/my%20file.txt" maps to "my file.txt".
+    Returns None if the path would escape the root, e.g. through "..".
+     */
+    let (path, _) = split_uri(uri);
+    let path = decode_path(path)?;
+    let path = path.strip_prefix('/')?;
+
+    let mut resolved = root.to_path_buf();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    Some(resolved)
+}
+
+pub fn escapes_root(root: &Path, path: &Path) -> bool {
+    /*
+    Check whether a path below the root leads outside of it once symlinks are
+    followed, e.g. a link to /etc/passwd placed in the resources directory.
+    Links whose targets stay below the root are fine. Paths that don't exist
+    can't leak anything and are left to be answered with 404.
+     */
+    match (root.canonicalize(), path.canonicalize()) {
+        (Ok(root), Ok(path)) => !path.starts_with(root),
+        (Err(_), Ok(_)) => true,
+        (_, Err(_)) => false,
+    }
+}
+
+pub fn is_hidden(uri: &str) -> bool {
+    /*
+    Check whether a request path names a hidden file or lies below a hidden
+    directory, i.e. has a segment with a leading dot, e.g. "/.env" or "/.git/config".
+     */
+    let (path, _) = split_uri(uri);
+    let path = decode_path(path).unwrap_or_else(|| path.to_string());
+    path.split('/')
+        .any(|segment| segment.starts_with('.') && segment != "." && segment != "..")
+}
+
+pub fn parse_extensions(value: &str) -> Option<Vec<String>> {
+    /*
+    Parse a comma separated list of file extensions, e.g. "html, .css, js".
+    Returns None for an empty list, which allows every extension.
+     */
+    let extensions: Vec<String> = value
+        .split(',')
+        .map(|extension| extension.trim().trim_start_matches('.').to_string())
+        .filter(|extension| !extension.is_empty())
+        .collect();
+    Some(extensions).filter(|extensions| !extensions.is_empty())
+}
+
+pub fn has_allowed_extension(path: &Path, allowed: &[String]) -> bool {
+    /*
+    Check whether a file has one of the allowed extensions, ignoring case.
+    Files without an extension are never allowed.
+     */
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            allowed
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(extension))
+        })
+}
+
+pub fn etag(path: &Path, metadata: &Metadata, algorithm: EtagAlgorithm) -> io::Result<String> {
+    /*
+    Get an entity tag for a file with the given algorithm.
+    Hashing gives files with identical contents the same tag wherever they are
+    and whenever they were written, e.g. W/"af63bd4c8601b7be" for FNV-1a, which
+    is weak as it may collide, or a strong tag of the SHA-256 digest.
+    Hashes are cached until the size or modification time of the file changes,
+    so a file is only read again once it was written.
+     */
+    if algorithm == EtagAlgorithm::Metadata {
+        return Ok(metadata_etag(metadata));
+    }
+    let key = (path.to_path_buf(), algorithm);
+    let (length, modified) = (metadata.len(), metadata.modified().ok());
+    let cached = ETAG_CACHE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&key)
+        .filter(|(cached_length, cached_modified, _)| {
+            *cached_length == length && *cached_modified == modified
+        })
+        .map(|(_, _, tag)| tag.clone());
+    if let Some(tag) = cached {
+        return Ok(tag);
+    }
+
+    let tag = match algorithm {
+        EtagAlgorithm::Sha256 => {
+            let mut hash = Sha256::new();
+            read_chunks(File::open(path)?, |chunk| hash.update(chunk))?;
+            format!("\"{}\"", to_hex(&hash.finish()))
+        }
+        _ => {
+            let mut hash = Fnv1a::new();
+            read_chunks(File::open(path)?, |chunk| hash.update(chunk))?;
+            format!("W/\"{:016x}\"", hash.finish())
+        }
+    };
+    let mut cache = ETAG_CACHE.lock().unwrap_or_else(PoisonError::into_inner);
+    if cache.len() >= ETAG_CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(key, (length, modified, tag.clone()));
+    Ok(tag)
+}
+
+fn read_chunks<R: Read>(mut reader: R, mut consume: impl FnMut(&[u8])) -> io::Result<()> {
+    // Read all bytes of a reader, passing them on chunk by chunk.
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(read) => consume(&chunk[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn metadata_etag(metadata: &Metadata) -> String {
+    // Get a strong entity tag for a file based on its size and modification time.
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+pub fn last_modified(metadata: &Metadata) -> Option<String> {
+    /*
+    Get the Last-Modified header value of a file.
+     */
+    metadata.modified().ok().map(http_date)
+}
+
+pub fn http_date(time: SystemTime) -> String {
+    /*
+    Format a point in time as an HTTP date, e.g. "Sun, 06 Nov 1994 08:49:37 GMT".
+     */
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = unix_seconds(time).max(0);
+    let days = secs / 86400;
+    let secs_of_day = secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    /*
+    Parse an HTTP date in the preferred format, e.g. "Sun, 06 Nov 1994 08:49:37 GMT".
+    The obsolete RFC 850 and asctime formats are not supported.
+     */
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (_, date) = value.trim().split_once(", ")?;
+    let parts: Vec<&str> = date.split(' ').collect();
+    if parts.len() != 5 || parts[4] != "GMT" {
+        return None;
+    }
+    let day: u32 = parts[0].parse().ok()?;
+    let month = MONTHS.iter().position(|month| *month == parts[1])? as u32 + 1;
+    let year: i64 = parts[2].parse().ok()?;
+    let time: Vec<u64> = parts[3]
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    if !(1..=31).contains(&day) || time.len() != 3 || time[0] > 23 || time[1] > 59 || time[2] > 60 {
+        return None;
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let secs = days * 86400 + time[0] * 3600 + time[1] * 60 + time[2];
+    Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+}
+
+pub fn parse_range(value: &str, len: u64) -> ByteRange {
+    /*
+    Parse the value of a Range header for a file of the given length.
+    Only a single byte range is supported, anything else serves the whole file.
+     */
+    let spec = match value.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return ByteRange::Full,
+    };
+
+    if start.is_empty() {
+        // a suffix range requests the last bytes of the file
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    let start = match start.parse::<u64>() {
+        Ok(start) => start,
+        Err(_) => return ByteRange::Full,
+    };
+    let end = if end.is_empty() {
+        len.saturating_sub(1)
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => end.min(len.saturating_sub(1)),
+            _ => return ByteRange::Full,
+        }
+    };
+
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(start, end)
+}
+
+pub fn if_range_matches(value: &str, etag: &str, last_modified: Option<&str>) -> bool {
+    /*
+    Check whether the validator of an If-Range header still matches the file.
+    Entity tags are compared strongly, so weak tags never match.
+     */
+    let value = value.trim();
+    if value.starts_with('"') {
+        return value == etag;
+    }
+    if value.starts_with("W/") {
+        return false;
+    }
+    last_modified == Some(value)
+}
+
+pub fn serve_file(
+    path: &Path,
+    request: &Request,
+    algorithm: EtagAlgorithm,
+) -> io::Result<Response> {
+    /*
+    Serve a file honoring the conditional and range headers of the request.
+    The content type is detected from the extension of the file and the ETag
+    computed with the given algorithm.
+    A GET or HEAD whose If-None-Match matches the file is answered with 304.
+    A range is only served if the If-Range validator, when present, still matches.
+    Large files are not read here but streamed when the response is written.
+    A precompressed sibling, e.g. app.js.br, is served in place of the file if the
+    client accepts its encoding. Responses for files with a sibling vary by
+    Accept-Encoding whichever file is served, those for files without one don't.
+     */
+    let content_type = content_type(path);
+    let vary = has_precompressed_sibling(path);
+    let (path, encoding) = match precompressed_sibling(path, request.header("Accept-Encoding")) {
+        Some((sibling, encoding)) => (sibling, Some(encoding)),
+        None => (path.to_path_buf(), None),
+    };
+    let path = path.as_path();
+
+    let metadata = fs::metadata(path)?;
+    let etag = etag(path, &metadata, algorithm)?;
+    let last_modified = last_modified(&metadata);
+    let add_encoding_headers = |response: &mut Response| {
+        if let Some(encoding) = encoding {
+            response.add_header("Content-Encoding", encoding);
+        }
+        if vary {
+            response.add_vary("Accept-Encoding");
+        }
+    };
+
+    if let Some(value) = request.header("If-None-Match") {
+        if matches!(request.method.as_str(), "GET" | "HEAD") && if_none_match_matches(value, &etag)
+        {
+            let mut response = Response::new(StatusCode::NotModified, content_type, Vec::new());
+            response.add_header("ETag", &etag);
+            if let Some(last_modified) = last_modified {
+                response.add_header("Last-Modified", &last_modified);
+            }
+            add_encoding_headers(&mut response);
+            return Ok(response);
+        }
+    }
+
+    // small files are read at once, larger ones are streamed when the response is written
+    // and the body of a HEAD response is never written, so it is not read at all
+    let contents = if metadata.len() > STREAM_THRESHOLD || request.method == "HEAD" {
+        None
+    } else {
+        Some(fs::read(path)?)
+    };
+    let total = match &contents {
+        Some(contents) => contents.len() as u64,
+        None => metadata.len(),
+    };
+
+    let range = match request.header("Range") {
+        Some(value) => match request.header("If-Range") {
+            Some(validator) if !if_range_matches(validator, &etag, last_modified.as_deref()) => {
+                ByteRange::Full
+            }
+            _ => parse_range(value, total),
+        },
+        None => ByteRange::Full,
+    };
+
+    let body = |status: StatusCode, start: u64, length: u64| match &contents {
+        Some(contents) => {
+            let body = contents[start as usize..(start + length) as usize].to_vec();
+            Response::new(status, content_type, body)
+        }
+        None => Response::from_file_range(
+            status,
+            content_type,
+            FileBody {
+                path: path.to_path_buf(),
+                start,
+                length,
+            },
+        ),
+    };
+
+    let mut response = match range {
+        ByteRange::Full => body(StatusCode::Ok, 0, total),
+        ByteRange::Partial(start, end) => {
+            let mut response = body(StatusCode::PartialContent, start, end - start + 1);
+            response.add_header(
+                "Content-Range",
+                &format!("bytes {}-{}/{}", start, end, total),
+            );
+            response
+        }
+        ByteRange::Unsatisfiable => {
+            let mut response =
+                Response::new(StatusCode::RangeNotSatisfiable, content_type, Vec::new());
+            response.add_header("Content-Range", &format!("bytes */{}", total));
+            response
+        }
+    };
+
+    response.add_header("Accept-Ranges", "bytes");
+    response.add_header("ETag", &etag);
+    if let Some(last_modified) = last_modified {
+        response.add_header("Last-Modified", &last_modified);
+    }
+    add_encoding_headers(&mut response);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{path::PathBuf, time::Duration};
+
+    fn write_temp_file(name: &str, contents: &str) -> PathBuf {
+        // Write a file into the temporary directory for a test.
+        let path =
+            std::env::temp_dir().join(format!("rust-webserver-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn request_with_headers(headers: &[(&str, &str)]) -> Request {
+        // Create a GET request with the given headers.
+        Request {
+            method: "GET".to_string(),
+            uri: "/".to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: Vec::new(),
+            extensions: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_servable_files() {
+        // Test detecting hidden paths and checking extensions against an allow-list.
+        assert!(is_hidden("/.env"));
+        assert!(is_hidden("/.git/config?x=1"));
+        assert!(is_hidden("/a/%2Ehtaccess"));
+        assert!(!is_hidden("/a/./index.html"));
+        assert!(!is_hidden("/docs/v1.2/"));
+
+        let allowed = parse_extensions("html, .CSS,").unwrap();
+        assert_eq!(allowed, vec!["html", "CSS"]);
+        assert_eq!(parse_extensions(" , "), None);
+        assert!(has_allowed_extension(Path::new("/srv/a.HTML"), &allowed));
+        assert!(has_allowed_extension(Path::new("/srv/a.css"), &allowed));
+        assert!(!has_allowed_extension(Path::new("/srv/main.rs"), &allowed));
+        assert!(!has_allowed_extension(Path::new("/srv/Makefile"), &allowed));
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        // Test mapping request paths below a root directory.
+        let root = Path::new("/srv/res");
+        assert_eq!(
+            resolve_path(root, "/docs/a.html?x=1"),
+            Some(PathBuf::from("/srv/res/docs/a.html"))
+        );
+        assert_eq!(resolve_path(root, "/"), Some(PathBuf::from("/srv/res")));
+        assert_eq!(resolve_path(root, "/docs/../../etc/passwd"), None);
+        assert_eq!(resolve_path(root, "docs"), None);
+        assert_eq!(
+            resolve_path(root, "/a+b%20c.txt?q=a+b"),
+            Some(PathBuf::from("/srv/res/a+b c.txt"))
+        );
+        assert_eq!(resolve_path(root, "/%2e%2e/etc/passwd"), None);
+    }
+
+    #[test]
+    fn test_http_date() {
+        // Test formatting of HTTP dates.
+        assert_eq!(
+            http_date(SystemTime::UNIX_EPOCH),
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+        assert_eq!(
+            http_date(SystemTime::UNIX_EPOCH + Duration::from_secs(784111777)),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        // Test parsing HTTP dates and rejecting other formats.
+        for secs in [0, 784111777, 951782400, 4102444799] {
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(parse_http_date(&http_date(time)), Some(time));
+        }
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 24:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        // Test parsing of single byte ranges.
+        assert_eq!(parse_range("bytes=0-4", 10), ByteRange::Partial(0, 4));
+        assert_eq!(parse_range("bytes=5-", 10), ByteRange::Partial(5, 9));
+        assert_eq!(parse_range("bytes=-3", 10), ByteRange::Partial(7, 9));
+        assert_eq!(parse_range("bytes=8-20", 10), ByteRange::Partial(8, 9));
+        assert_eq!(parse_range("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-1,3-4", 10), ByteRange::Full);
+        assert_eq!(parse_range("lines=0-1", 10), ByteRange::Full);
+        assert_eq!(parse_range("bytes=4-2", 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn test_if_range_matches() {
+        // Test comparison of If-Range validators.
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert!(if_range_matches("\"abc\"", "\"abc\"", Some(date)));
+        assert!(!if_range_matches("\"abd\"", "\"abc\"", Some(date)));
+        assert!(!if_range_matches("W/\"abc\"", "\"abc\"", Some(date)));
+        assert!(if_range_matches(date, "\"abc\"", Some(date)));
+        assert!(!if_range_matches(date, "\"abc\"", None));
+    }
+
+    #[test]
+    fn test_if_range_matching_validator() {
+        // Test that a matching strong validator serves the requested range.
+        let path = write_temp_file("if-range-match.txt", "0123456789");
+        let etag = etag(&path, &fs::metadata(&path).unwrap(), EtagAlgorithm::Sha256).unwrap();
+
+        let request = request_with_headers(&[("Range", "bytes=2-5"), ("If-Range", &etag)]);
+        let response = serve_file(&path, &request, EtagAlgorithm::Sha256).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(response.status, StatusCode::PartialContent);
+        assert_eq!(response.body, b"2345".to_vec());
+        assert_eq!(response.header("Content-Range"), Some("bytes 2-5/10"));
+    }
+
+    #[test]
+    fn test_serve_file_head() {
+        // Test that a HEAD request gets the headers of the file without reading it.
+        let path = write_temp_file("head.txt", "0123456789");
+        let mut request = request_with_headers(&[]);
+        request.method = "HEAD".to_string();
+
+        let response = serve_file(&path, &request, EtagAlgorithm::default()).unwrap();
+        let etag = etag(
+            &path,
+            &fs::metadata(&path).unwrap(),
+            EtagAlgorithm::default(),
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(response.body.is_empty());
+        assert!(response.file.is_some());
+        assert_eq!(response.content_length(), 10);
+        assert_eq!(response.header("ETag"), Some(etag.as_str()));
+        assert!(response.header("Last-Modified").is_some());
+    }
+
+    #[test]
+    fn test_serve_precompressed() {
+        // Test that an accepted Brotli sibling is served with the type of the original file.
+        let path = write_temp_file("precompressed.css", "body {}");
+        let mut sibling = path.clone().into_os_string();
+        sibling.push(".br");
+        fs::write(&sibling, "brotli").unwrap();
+
+        let accepted = serve_file(
+            &path,
+            &request_with_headers(&[("Accept-Encoding", "gzip, br")]),
+            EtagAlgorithm::default(),
+        )
+        .unwrap();
+        let plain = serve_file(
+            &path,
+            &request_with_headers(&[("Accept-Encoding", "gzip")]),
+            EtagAlgorithm::default(),
+        )
+        .unwrap();
+        fs::remove_file(&sibling).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(accepted.body, b"brotli");
+        assert_eq!(accepted.content_type, "text/css; charset=UTF-8");
+        assert_eq!(accepted.header("Content-Encoding"), Some("br"));
+        assert_eq!(accepted.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(plain.body, b"body {}");
+        assert_eq!(plain.header("Content-Encoding"), None);
+        assert_eq!(plain.header("Vary"), Some("Accept-Encoding"));
+    }
+
+    #[test]
+    fn test_serve_large_file() {
+        // Test that large files are streamed instead of read into memory.
+        let contents = "x".repeat(STREAM_THRESHOLD as usize + 1);
+        let path = write_temp_file("large.txt", &contents);
+
+        let response =
+            serve_file(&path, &request_with_headers(&[]), EtagAlgorithm::default()).unwrap();
+        let range = serve_file(
+            &path,
+            &request_with_headers(&[("Range", "bytes=-10")]),
+            EtagAlgorithm::default(),
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(response.body.is_empty());
+        assert_eq!(response.content_length(), STREAM_THRESHOLD + 1);
+        assert_eq!(
+            range.file,
+            Some(FileBody {
+                path: path.clone(),
+                start: STREAM_THRESHOLD - 9,
+                length: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn test_etag_algorithms() {
+        // Test that content hashes give identical files the same tag and different
+        // files different ones, weak for the default and strong for SHA-256,
+        // whose tags ranges may be resumed with.
+        let first = write_temp_file("etag-first.txt", "0123456789");
+        let copy = write_temp_file("etag-copy.txt", "0123456789");
+        let other = write_temp_file("etag-other.txt", "0123456780");
+        let tag = |path: &Path, algorithm| etag(path, &fs::metadata(path).unwrap(), algorithm);
+
+        for algorithm in [EtagAlgorithm::default(), EtagAlgorithm::Sha256] {
+            let first_tag = tag(&first, algorithm).unwrap();
+            assert_eq!(first_tag, tag(&copy, algorithm).unwrap());
+            assert_ne!(first_tag, tag(&other, algorithm).unwrap());
+
+            let request = request_with_headers(&[("Range", "bytes=2-5"), ("If-Range", &first_tag)]);
+            let response = serve_file(&copy, &request, algorithm).unwrap();
+            let status = match algorithm {
+                EtagAlgorithm::Sha256 => StatusCode::PartialContent,
+                _ => StatusCode::Ok,
+            };
+            assert_eq!(response.status, status);
+        }
+        assert_eq!(EtagAlgorithm::default(), EtagAlgorithm::Fnv);
+        assert!(tag(&first, EtagAlgorithm::Fnv).unwrap().starts_with("W/\""));
+        assert_eq!(
+            tag(&first, EtagAlgorithm::Sha256).unwrap(),
+            "\"84d89877f0d4041efb6bf91a16f0248f2fd573e6af05c19f96bedb9f882f7882\""
+        );
+        assert_eq!(
+            tag(&first, EtagAlgorithm::Metadata).unwrap(),
+            metadata_etag(&fs::metadata(&first).unwrap())
+        );
+
+        // a changed file is hashed again instead of keeping its cached tag
+        let first_tag = tag(&first, EtagAlgorithm::Fnv).unwrap();
+        fs::write(&first, "0123456780").unwrap();
+        File::options()
+            .write(true)
+            .open(&first)
+            .and_then(|file| file.set_modified(SystemTime::UNIX_EPOCH))
+            .unwrap();
+        assert_eq!(
+            tag(&first, EtagAlgorithm::Fnv).unwrap(),
+            tag(&other, EtagAlgorithm::Fnv).unwrap()
+        );
+        assert_ne!(tag(&first, EtagAlgorithm::Fnv).unwrap(), first_tag);
+        for path in [&first, &copy, &other] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(EtagAlgorithm::parse(" SHA-256 "), EtagAlgorithm::Sha256);
+        assert_eq!(EtagAlgorithm::parse("metadata"), EtagAlgorithm::Metadata);
+        assert_eq!(EtagAlgorithm::parse("md5"), EtagAlgorithm::Fnv);
+    }
+
+    #[test]
+    fn test_if_range_changed_validator() {
+        // Test that a changed validator serves the full file.
+        let path = write_temp_file("if-range-changed.txt", "0123456789");
+
+        let request = request_with_headers(&[("Range", "bytes=2-5"), ("If-Range", "\"stale\"")]);
+        let response = serve_file(&path, &request, EtagAlgorithm::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.body, b"0123456789".to_vec());
+        assert_eq!(response.header("Content-Range"), None);
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        // Test that a matching If-None-Match yields 304.
+        let path = write_temp_file("if-none-match.txt", "0123456789");
+        let etag = etag(
+            &path,
+            &fs::metadata(&path).unwrap(),
+            EtagAlgorithm::default(),
+        )
+        .unwrap();
+
+        let strong = format!("\"other\", {}", etag.trim_start_matches("W/"));
+        let request = request_with_headers(&[("If-None-Match", &strong)]);
+        let not_modified = serve_file(&path, &request, EtagAlgorithm::default()).unwrap();
+
+        let request = request_with_headers(&[("If-None-Match", "*")]);
+        let any = serve_file(&path, &request, EtagAlgorithm::default()).unwrap();
+
+        let request = request_with_headers(&[("If-None-Match", "\"other\"")]);
+        let modified = serve_file(&path, &request, EtagAlgorithm::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(not_modified.status, StatusCode::NotModified);
+        assert!(not_modified.body.is_empty());
+        assert_eq!(not_modified.header("ETag"), Some(etag.as_str()));
+        assert_eq!(any.status, StatusCode::NotModified);
+        assert_eq!(modified.status, StatusCode::Ok);
+    }
+
+    #[test]
+    fn test_unsatisfiable_range() {
+        // Test that a range past the end of the file is rejected.
+        let path = write_temp_file("unsatisfiable.txt", "0123456789");
+
+        let request = request_with_headers(&[("Range", "bytes=20-")]);
+        let response = serve_file(&path, &request, EtagAlgorithm::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(response.status, StatusCode::RangeNotSatisfiable);
+        assert_eq!(response.header("Content-Range"), Some("bytes */10"));
+    }
+}
@@ -0,0 +1,141 @@
+/*
+* Resolution of the client address of requests passing through reverse proxies.
+*/
+
+use std::net::{IpAddr, SocketAddr};
+
+use super::request::Request;
+
+pub fn parse_trusted_proxies(value: &str) -> Vec<IpAddr> {
+    /*
+    Parse a comma separated list of proxy addresses, e.g. "10.0.0.1,::1".
+    Invalid addresses are skipped.
+     */
+    value
+        .split(',')
+        .filter_map(|address| address.trim().parse().ok())
+        .collect()
+}
+
+pub fn client_ip(peer: IpAddr, request: &Request, trusted_proxies: &[IpAddr]) -> IpAddr {
+    /*
+    Get the address of the client which sent a request.
+    The forwarding headers are only honored if the peer is a trusted proxy.
+    The chain of forwarded addresses is walked back from the peer and the first
+    address which is not a trusted proxy is the client, so clients cannot spoof
+    their address by sending the headers themselves.
+     */
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for address in forwarded_chain(request).into_iter().rev() {
+        client = address;
+        if !trusted_proxies.contains(&address) {
+            break;
+        }
+    }
+    client
+}
+
+fn forwarded_chain(request: &Request) -> Vec<IpAddr> {
+    /*
+    Get the forwarded addresses of a request, the original client first.
+    The standard Forwarded header takes precedence over X-Forwarded-For.
+     */
+    let entries: Vec<&str> = if let Some(value) = request.header("Forwarded") {
+        value
+            .split(',')
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(key, _)| key.eq_ignore_ascii_case("for"))
+                    .map_or("", |(_, value)| value)
+            })
+            .collect()
+    } else if let Some(value) = request.header("X-Forwarded-For") {
+        value.split(',').collect()
+    } else {
+        Vec::new()
+    };
+
+    // keep only the addresses after the last unparsable entry, as anything
+    // before it cannot be attributed to a known hop
+    let mut chain = Vec::new();
+    for entry in entries {
+        match parse_node(entry) {
+            Some(address) => chain.push(address),
+            None => chain.clear(),
+        }
+    }
+    chain
+}
+
+fn parse_node(node: &str) -> Option<IpAddr> {
+    /*
+    Parse an address of a forwarding header, e.g. 192.0.2.1, "[2001:db8::1]:8080".
+     */
+    let node = node.trim().trim_matches('"');
+    if let Ok(address) = node.parse::<IpAddr>() {
+        return Some(address);
+    }
+    if let Ok(address) = node.parse::<SocketAddr>() {
+        return Some(address.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|node| node.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::parse_request;
+
+    fn request_with_header(name: &str, value: &str) -> Request {
+        // Parse a request with a single header.
+        let raw = format!("GET / HTTP/1.1\r\n{}: {}\r\n\r\n", name, value);
+        parse_request(&mut raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_client_ip_untrusted_peer() {
+        // Test that the headers of untrusted peers are ignored.
+        let peer: IpAddr = "203.0.113.7".parse().unwrap();
+        let request = request_with_header("X-Forwarded-For", "198.51.100.1");
+        assert_eq!(client_ip(peer, &request, &[]), peer);
+    }
+
+    #[test]
+    fn test_client_ip_x_forwarded_for() {
+        // Test that trusted proxies are skipped in X-Forwarded-For.
+        let trusted = parse_trusted_proxies("10.0.0.1, 10.0.0.2");
+        let request = request_with_header("X-Forwarded-For", "1.2.3.4, 198.51.100.1, 10.0.0.2");
+        assert_eq!(
+            client_ip("10.0.0.1".parse().unwrap(), &request, &trusted),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_client_ip_forwarded() {
+        // Test reading the client from the Forwarded header.
+        let trusted = parse_trusted_proxies("::1");
+        let request = request_with_header(
+            "Forwarded",
+            "for=\"[2001:db8::1]:4711\";proto=https, for=::1",
+        );
+        assert_eq!(
+            client_ip("::1".parse().unwrap(), &request, &trusted),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+
+        let request = request_with_header("Forwarded", "for=unknown");
+        assert_eq!(
+            client_ip("::1".parse().unwrap(), &request, &trusted),
+            "::1".parse::<IpAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,196 @@
+/*
+* Hashes of file contents used for entity tags.
+*/
+
+pub struct Fnv1a {
+    // Fnv1a is the 64-bit FNV-1a hash, fast but not collision resistant.
+    state: u64,
+}
+
+impl Fnv1a {
+    pub fn new() -> Fnv1a {
+        // Create a hash of no bytes.
+        Fnv1a {
+            state: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        // Add bytes to the hash.
+        for &byte in bytes {
+            self.state ^= u64::from(byte);
+            self.state = self.state.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        // Get the hash of the bytes added so far.
+        self.state
+    }
+}
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a::new()
+    }
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+pub struct Sha256 {
+    // Sha256 is the SHA-256 hash, for when tags must not collide.
+    state: [u32; 8],
+    // Bytes added since the last complete block.
+    block: Vec<u8>,
+    // Number of bytes added so far.
+    length: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        // Create a hash of no bytes.
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        // Add bytes to the hash, compressing every complete block of 64 bytes.
+        self.length += bytes.len() as u64;
+        while !bytes.is_empty() {
+            let take = (64 - self.block.len()).min(bytes.len());
+            self.block.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+            if self.block.len() == 64 {
+                compress(&mut self.state, &self.block);
+                self.block.clear();
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        /*
+        Get the hash of the bytes added, after padding them with a one bit,
+        zeros and their length in bits as the standard requires.
+         */
+        let bits = self.length.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block.len() != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Sha256::new()
+    }
+}
+
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    // Mix a block of 64 bytes into the state of a SHA-256 hash.
+    let mut w = [0u32; 64];
+    for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let choice = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(choice)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let majority = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(majority);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    // Format bytes as lowercase hexadecimal digits.
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a() {
+        // Test the hash against the reference values of FNV-1a.
+        assert_eq!(Fnv1a::new().finish(), 0xcbf29ce484222325);
+        let mut hash = Fnv1a::new();
+        hash.update(b"foo");
+        hash.update(b"bar");
+        assert_eq!(hash.finish(), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn test_sha256() {
+        // Test the hash against known digests, also of input added in pieces.
+        assert_eq!(
+            to_hex(&Sha256::new().finish()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        let mut hash = Sha256::new();
+        hash.update(b"abc");
+        assert_eq!(
+            to_hex(&hash.finish()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let mut hash = Sha256::new();
+        for piece in [
+            "abcdbcdecdefdefgefghfghighijhi",
+            "jkijkljklmklmnlmnomnopnopq",
+        ] {
+            hash.update(piece.as_bytes());
+        }
+        assert_eq!(
+            to_hex(&hash.finish()),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+}
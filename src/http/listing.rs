@@ -0,0 +1,133 @@
+/*
+* Generated HTML listings of the entries of a directory.
+*/
+
+use std::{fs, io, path::Path};
+
+use super::file::http_date;
+
+pub fn directory_listing(dir: &Path, uri: &str, show_hidden: bool) -> io::Result<String> {
+    /*
+    Render an HTML page listing the entries of a directory.
+    Entries are sorted by name and link relative to the request path.
+    Entries with a leading dot are only listed if show_hidden is set.
+     */
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !show_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        let mut name = entry.file_name().to_string_lossy().to_string();
+        if metadata.is_dir() {
+            name.push('/');
+        }
+        let size = if metadata.is_dir() {
+            "-".to_string()
+        } else {
+            metadata.len().to_string()
+        };
+        let modified = metadata
+            .modified()
+            .map(http_date)
+            .unwrap_or_else(|_| "-".to_string());
+        entries.push((name, size, modified));
+    }
+    entries.sort();
+
+    let path = uri.split(['?', '#']).next().unwrap_or("/");
+    let base = if path.ends_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/", path)
+    };
+
+    let mut rows = String::new();
+    for (name, size, modified) in entries {
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&base),
+            percent_encode(&name),
+            escape_html(&name),
+            size,
+            modified
+        ));
+    }
+
+    Ok(format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>Index of {0}</title>\n</head>\n<body>\n<h1>Index of {0}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Last Modified</th></tr>\n{1}</table>\n</body>\n</html>\n",
+        escape_html(&base),
+        rows
+    ))
+}
+
+pub fn escape_html(value: &str) -> String {
+    /*
+    Escape a string so it can be embedded in HTML text and attributes.
+     */
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn percent_encode(value: &str) -> String {
+    /*
+    Percent-encode a file name for use in a link.
+    Slashes are kept so directory links stay intact.
+     */
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_listing() {
+        // Test that a listing contains the sorted entries of a directory.
+        let dir =
+            std::env::temp_dir().join(format!("rust-webserver-{}-listing", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("b.txt"), "bb").unwrap();
+        fs::write(dir.join("a <1>.html"), "a").unwrap();
+
+        let listing = directory_listing(&dir, "/files", false).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let a = listing.find("a &lt;1&gt;.html").unwrap();
+        let b = listing.find(">b.txt<").unwrap();
+        let sub = listing.find(">sub/<").unwrap();
+        assert!(a < b && b < sub);
+        assert!(listing.contains("href=\"/files/a%20%3C1%3E.html\""));
+        assert!(listing.contains("<td>2</td>"));
+        assert!(listing.contains("<title>Index of /files/</title>"));
+    }
+
+    #[test]
+    fn test_escape_html() {
+        // Test escaping of special characters in HTML.
+        assert_eq!(
+            escape_html("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+/*
+* Detection of the content type of served files and matching of media types.
+*/
+
+use std::path::Path;
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+const CONTENT_TYPES: [(&str, &str); 24] = [
+    ("html", "text/html; charset=UTF-8"),
+    ("htm", "text/html; charset=UTF-8"),
+    ("css", "text/css; charset=UTF-8"),
+    ("js", "text/javascript; charset=UTF-8"),
+    ("mjs", "text/javascript; charset=UTF-8"),
+    ("json", "application/json"),
+    ("txt", "text/plain; charset=UTF-8"),
+    ("csv", "text/csv; charset=UTF-8"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("pdf", "application/pdf"),
+    ("wasm", "application/wasm"),
+    ("zip", "application/zip"),
+    ("mp4", "video/mp4"),
+    ("mp3", "audio/mpeg"),
+];
+
+pub fn content_type(path: &Path) -> &'static str {
+    /*
+    Get the content type of a file from its extension.
+    Files with an unknown extension are served as arbitrary binary data.
+     */
+    let extension = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension,
+        None => return DEFAULT_CONTENT_TYPE,
+    };
+    CONTENT_TYPES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map_or(DEFAULT_CONTENT_TYPE, |(_, content_type)| content_type)
+}
+
+pub fn media_type_matches(content_type: &str, media_type: &str) -> bool {
+    /*
+    Check whether a Content-Type value has the given media type, ignoring
+    parameters and case, e.g. "Application/JSON; charset=utf-8" has application/json.
+     */
+    let actual = content_type.split(';').next().unwrap_or("").trim();
+    actual.eq_ignore_ascii_case(media_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_type() {
+        // Test detection of content types by extension.
+        assert_eq!(
+            content_type(Path::new("app.css")),
+            "text/css; charset=UTF-8"
+        );
+        assert_eq!(
+            content_type(Path::new("INDEX.HTML")),
+            "text/html; charset=UTF-8"
+        );
+        assert_eq!(content_type(Path::new("logo.png")), "image/png");
+        assert_eq!(content_type(Path::new("archive.bin")), DEFAULT_CONTENT_TYPE);
+        assert_eq!(content_type(Path::new("README")), DEFAULT_CONTENT_TYPE);
+    }
+
+    #[test]
+    fn test_media_type_matches() {
+        // Test that parameters and case are ignored when matching media types.
+        assert!(media_type_matches(
+            "Application/JSON; charset=utf-8",
+            "application/json"
+        ));
+        assert!(!media_type_matches("text/plain", "application/json"));
+        assert!(!media_type_matches(
+            "application/json-patch+json",
+            "application/json"
+        ));
+    }
+}
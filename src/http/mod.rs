@@ -0,0 +1,19 @@
+pub mod auth;
+pub mod cache;
+pub mod conditional;
+pub mod connection;
+pub mod counting;
+pub mod encoding;
+pub mod extensions;
+pub mod file;
+pub mod forwarded;
+pub mod hash;
+pub mod listing;
+pub mod mime;
+pub mod multipart;
+pub mod request;
+pub mod response;
+pub mod status;
+pub mod template;
+pub mod url;
+pub mod writer;
@@ -0,0 +1,379 @@
+/*
+* Streaming parser for multipart/form-data bodies which writes uploaded files to disk.
+*/
+
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::request::HTTPError;
+
+// Size of the chunks the body is read in.
+const CHUNK_SIZE: usize = 8 * 1024;
+// Longest header line of a part and largest field kept in memory.
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+const MAX_FIELD_SIZE: usize = 64 * 1024;
+
+// Counter making the names of the temporary files of this process unique.
+static UPLOADS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, PartialEq)]
+pub struct Part {
+    // Part is a field of a form, uploaded files are kept on disk.
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub body: PartBody,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PartBody {
+    // The value of a plain form field.
+    Memory(Vec<u8>),
+    // The temporary file an uploaded file was written to.
+    File(PathBuf),
+}
+
+// The parts of a request body streamed to disk, kept in the extensions of the
+// request. Their temporary files are removed when it is dropped.
+#[derive(Debug)]
+pub struct Uploads(pub Vec<Part>);
+
+impl Drop for Uploads {
+    fn drop(&mut self) {
+        for part in &self.0 {
+            if let PartBody::File(path) = &part.body {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+pub fn boundary(content_type: &str) -> Option<&str> {
+    /*
+    Get the boundary of a multipart/form-data Content-Type,
+    e.g. "multipart/form-data; boundary=abc" has the boundary abc.
+     */
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    params
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"'))
+        .filter(|boundary| !boundary.is_empty())
+}
+
+pub fn stream_multipart<R: Read>(
+    reader: R,
+    boundary: &str,
+    dir: &Path,
+    disk_limit: u64,
+) -> Result<Vec<Part>, HTTPError> {
+    /*
+    Parse a multipart/form-data body, writing the parts with a file name to
+    temporary files in dir as they are read instead of holding them in memory.
+    The reader must end with the body, e.g. by limiting it to the Content-Length.
+    Fails with ContentTooLarge once the files exceed disk_limit bytes in total
+    or a plain field exceeds 64 KiB. On any error the temporary files written
+    so far are removed.
+     */
+    let mut files = Vec::new();
+    let result = parse_parts(reader, boundary, dir, disk_limit, &mut files);
+    if result.is_err() {
+        for file in files {
+            let _ = fs::remove_file(file);
+        }
+    }
+    result
+}
+
+fn parse_parts<R: Read>(
+    reader: R,
+    boundary: &str,
+    dir: &Path,
+    disk_limit: u64,
+    files: &mut Vec<PathBuf>,
+) -> Result<Vec<Part>, HTTPError> {
+    // Parse the parts, recording each temporary file as soon as it is created.
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    // the leading line break lets the first boundary match like all others
+    let mut scanner = Scanner {
+        reader,
+        buffer: b"\r\n".to_vec(),
+    };
+    scanner.read_until(&delimiter, |_| Ok(()))?;
+
+    let mut parts = Vec::new();
+    let mut written = 0;
+    loop {
+        match scanner.take(2)?.as_slice() {
+            b"--" => return Ok(parts),
+            b"\r\n" => (),
+            _ => return Err(HTTPError::InvalidRequest),
+        }
+
+        let mut part = Part {
+            name: String::new(),
+            filename: None,
+            content_type: None,
+            body: PartBody::Memory(Vec::new()),
+        };
+        loop {
+            let line = scanner.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            let line = String::from_utf8(line).map_err(|_| HTTPError::InvalidRequest)?;
+            let (name, value) = line.split_once(':').ok_or(HTTPError::InvalidRequest)?;
+            if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+                part.name = disposition_param(value, "name").unwrap_or_default();
+                part.filename = disposition_param(value, "filename");
+            } else if name.trim().eq_ignore_ascii_case("Content-Type") {
+                part.content_type = Some(value.trim().to_string());
+            }
+        }
+
+        if part.filename.is_some() {
+            let path = dir.join(format!(
+                "upload-{}-{}",
+                std::process::id(),
+                UPLOADS.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut file = File::create_new(&path).map_err(|_| HTTPError::InternalServerError)?;
+            files.push(path.clone());
+            scanner.read_until(&delimiter, |chunk| {
+                written += chunk.len() as u64;
+                if written > disk_limit {
+                    return Err(HTTPError::ContentTooLarge);
+                }
+                file.write_all(chunk)
+                    .map_err(|_| HTTPError::InternalServerError)
+            })?;
+            part.body = PartBody::File(path);
+        } else {
+            let mut value = Vec::new();
+            scanner.read_until(&delimiter, |chunk| {
+                if value.len() + chunk.len() > MAX_FIELD_SIZE {
+                    return Err(HTTPError::ContentTooLarge);
+                }
+                value.extend_from_slice(chunk);
+                Ok(())
+            })?;
+            part.body = PartBody::Memory(value);
+        }
+        parts.push(part);
+    }
+}
+
+fn disposition_param(value: &str, key: &str) -> Option<String> {
+    /*
+    Get a parameter of a Content-Disposition value,
+    e.g. the name of `form-data; name="file"; filename="a.txt"`.
+     */
+    value
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+struct Scanner<R> {
+    // Scanner reads a body in chunks and finds delimiters spanning chunk borders.
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> Scanner<R> {
+    fn fill(&mut self) -> Result<(), HTTPError> {
+        // Read the next chunk into the buffer, a body ending early is invalid.
+        let mut chunk = [0; CHUNK_SIZE];
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Err(HTTPError::InvalidRequest),
+                Ok(read) => {
+                    self.buffer.extend_from_slice(&chunk[..read]);
+                    return Ok(());
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => return Err(HTTPError::InvalidRequest),
+            }
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<Vec<u8>, HTTPError> {
+        // Take the next bytes of the body.
+        while self.buffer.len() < length {
+            self.fill()?;
+        }
+        Ok(self.buffer.drain(..length).collect())
+    }
+
+    fn read_line(&mut self) -> Result<Vec<u8>, HTTPError> {
+        // Read a header line of a part without its line ending.
+        let mut searched = 0;
+        loop {
+            if let Some(index) = find(&self.buffer[searched..], b"\r\n") {
+                let end = searched + index;
+                let line = self.buffer[..end].to_vec();
+                self.buffer.drain(..end + 2);
+                return Ok(line);
+            }
+            if self.buffer.len() > MAX_LINE_LENGTH {
+                return Err(HTTPError::InvalidRequest);
+            }
+            searched = self.buffer.len().saturating_sub(1);
+            self.fill()?;
+        }
+    }
+
+    fn read_until<F>(&mut self, delimiter: &[u8], mut sink: F) -> Result<(), HTTPError>
+    where
+        F: FnMut(&[u8]) -> Result<(), HTTPError>,
+    {
+        // Pass the bytes up to the delimiter to the sink and consume the delimiter.
+        // Bytes which may be the start of a delimiter are held back until the
+        // next chunk tells whether they are.
+        loop {
+            if let Some(index) = find(&self.buffer, delimiter) {
+                sink(&self.buffer[..index])?;
+                self.buffer.drain(..index + delimiter.len());
+                return Ok(());
+            }
+            let safe = self.buffer.len().saturating_sub(delimiter.len() - 1);
+            if safe > 0 {
+                sink(&self.buffer[..safe])?;
+                self.buffer.drain(..safe);
+            }
+            self.fill()?;
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    // Find the first occurrence of needle in haystack.
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Trickle<'a> {
+        // Trickle returns a body in reads of at most a few bytes, like a slow client.
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let length = self.step.min(buf.len()).min(self.data.len());
+            buf[..length].copy_from_slice(&self.data[..length]);
+            self.data = &self.data[length..];
+            Ok(length)
+        }
+    }
+
+    fn upload_dir(name: &str) -> PathBuf {
+        // Create an empty directory for the uploads of a test.
+        let dir =
+            std::env::temp_dir().join(format!("rust-webserver-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn form(contents: &[u8]) -> Vec<u8> {
+        // Build a form with a plain field and an uploaded file.
+        let mut body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            holiday\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"beach.bin\"\r\n\
+            Content-Type: application/octet-stream\r\n\r\n"
+            .to_vec();
+        body.extend_from_slice(contents);
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+        body
+    }
+
+    #[test]
+    fn test_boundary() {
+        // Test reading the boundary from the Content-Type.
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"a b\""),
+            Some("a b")
+        );
+        assert_eq!(boundary("text/plain; boundary=x"), None);
+        assert_eq!(boundary("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn test_stream_multipart() {
+        // Test that a file spanning many chunks is written to disk unchanged.
+        let dir = upload_dir("multipart");
+        // the contents contain near misses of the delimiter across chunk borders
+        let contents: Vec<u8> = (0..100_000)
+            .flat_map(|i: u32| {
+                if i.is_multiple_of(1000) {
+                    b"\r\n--XyY".to_vec()
+                } else {
+                    vec![(i % 251) as u8]
+                }
+            })
+            .collect();
+        let body = form(&contents);
+
+        let parts = stream_multipart(
+            Trickle {
+                data: &body,
+                step: 777,
+            },
+            "XyZ",
+            &dir,
+            1 << 20,
+        )
+        .unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "title");
+        assert_eq!(parts[0].body, PartBody::Memory(b"holiday".to_vec()));
+        assert_eq!(parts[1].filename.as_deref(), Some("beach.bin"));
+        assert_eq!(
+            parts[1].content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+        match &parts[1].body {
+            PartBody::File(path) => assert_eq!(fs::read(path).unwrap(), contents),
+            body => panic!("expected a file, got {:?}", body),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stream_multipart_limit() {
+        // Test that exceeding the disk limit fails and removes the temporary file.
+        let dir = upload_dir("multipart-limit");
+        let body = form(&[7; 50_000]);
+
+        let result = stream_multipart(body.as_slice(), "XyZ", &dir, 10_000);
+        assert_eq!(result, Err(HTTPError::ContentTooLarge));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        let truncated = &body[..body.len() - 20];
+        let result = stream_multipart(truncated, "XyZ", &dir, 1 << 20);
+        assert_eq!(result, Err(HTTPError::InvalidRequest));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,843 @@
+/*
+* Parsing of HTTP requests read from a client.
+*/
+
+use std::{
+    io::{self, BufRead, Read},
+    time::Instant,
+};
+
+use super::{
+    extensions::Extensions,
+    mime::media_type_matches,
+    multipart::{Part, Uploads},
+    status::StatusCode,
+    url::{decode_path, parse_query, split_uri},
+};
+
+const VERSIONS: [&str; 2] = ["HTTP/1.0", "HTTP/1.1"];
+const METHODS: [&str; 8] = [
+    "GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS", "TRACE",
+];
+// Bytes a header line may have on top of the limit of its value, for the name and colon.
+const HEADER_NAME_ALLOWANCE: usize = 256;
+// Bytes the request line may have on top of its target, for the method and version.
+const REQUEST_LINE_ALLOWANCE: usize = 64;
+// Bytes a chunk size line may have, including chunk extensions.
+const MAX_CHUNK_LINE: usize = 1024;
+// Bytes a trailer field line of a chunked body may have.
+const MAX_TRAILER_LINE: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HTTPError {
+    InvalidRequest,
+    NotFound,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+    RequestTimeout,
+    UriTooLong,
+    ContentTooLarge,
+    HeaderFieldsTooLarge,
+}
+
+impl HTTPError {
+    pub fn status_code(&self) -> StatusCode {
+        // Get the status code of the response for this error.
+        match self {
+            HTTPError::InvalidRequest => StatusCode::BadRequest,
+            HTTPError::NotFound => StatusCode::NotFound,
+            HTTPError::InternalServerError => StatusCode::InternalServerError,
+            HTTPError::NotImplemented => StatusCode::NotImplemented,
+            HTTPError::ServiceUnavailable => StatusCode::ServiceUnavailable,
+            HTTPError::RequestTimeout => StatusCode::RequestTimeout,
+            HTTPError::UriTooLong => StatusCode::UriTooLong,
+            HTTPError::ContentTooLarge => StatusCode::ContentTooLarge,
+            HTTPError::HeaderFieldsTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub uri: String,
+    pub version: String,
+    // Headers in the order they were received.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    // Data attached to the request while it is handled, e.g. by middleware.
+    pub extensions: Extensions,
+}
+
+// The time by which the handler of a request should be done, kept in the
+// extensions of the request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deadline(pub Instant);
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        // Get the value of a header by name.
+        // Header names are compared case-insensitively.
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn content_length(&self) -> Result<Option<u64>, HTTPError> {
+        /*
+        Get the length of the body announced by the Content-Length headers.
+        Repeated headers or comma separated values must all agree and consist of
+        ASCII digits only, e.g. "+5" or "5, 6" are rejected, as a proxy in front
+        could read them differently and pass on a smuggled request.
+         */
+        let mut length = None;
+        let values = self
+            .headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("Content-Length"))
+            .flat_map(|(_, value)| value.split(','));
+        for value in values {
+            let value = value.trim();
+            if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(HTTPError::InvalidRequest);
+            }
+            let value = value
+                .parse::<u64>()
+                .map_err(|_| HTTPError::InvalidRequest)?;
+            if length.is_some_and(|length| length != value) {
+                return Err(HTTPError::InvalidRequest);
+            }
+            length = Some(value);
+        }
+        Ok(length)
+    }
+
+    pub fn keep_alive(&self) -> bool {
+        // Check whether the client wants the connection kept open after this request.
+        // HTTP/1.1 connections persist unless closed, HTTP/1.0 ones only on request.
+        let has_option = |option: &str| {
+            self.header("Connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case(option))
+            })
+        };
+        if self.version == "HTTP/1.0" {
+            has_option("keep-alive")
+        } else {
+            !has_option("close")
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        // Get the time by which the handler should be done, after which its
+        // result is discarded. Slow handlers may check it to give up early.
+        // Requests without a handler timeout have no deadline.
+        self.extensions.get::<Deadline>().map(|deadline| deadline.0)
+    }
+
+    pub fn uploads(&self) -> &[Part] {
+        // Get the parts of a multipart body streamed to disk, for routes which
+        // stream uploads. The files of the parts are removed once the request
+        // and all copies of it are dropped, so handlers move files they keep.
+        self.extensions
+            .get::<Uploads>()
+            .map_or(&[], |uploads| uploads.0.as_slice())
+    }
+
+    pub fn param(&self, name: &str) -> Option<String> {
+        // Get the first value of a parameter from the query string or a form body.
+        // The query string takes precedence, the body is only read if it is
+        // sent as application/x-www-form-urlencoded.
+        let find = |pairs: Vec<(String, String)>| {
+            pairs
+                .into_iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+        };
+        if let Some(value) = split_uri(&self.uri)
+            .1
+            .and_then(|query| find(parse_query(query)))
+        {
+            return Some(value);
+        }
+        let is_form = self.header("Content-Type").is_some_and(|content_type| {
+            media_type_matches(content_type, "application/x-www-form-urlencoded")
+        });
+        if !is_form {
+            return None;
+        }
+        std::str::from_utf8(&self.body)
+            .ok()
+            .and_then(|body| find(parse_query(body)))
+    }
+}
+
+pub fn validate_host(request: &Request) -> Result<(), HTTPError> {
+    /*
+    Check that an HTTP/1.1 request has exactly one Host header, as the version requires.
+    HTTP/1.0 requests predate the header and are exempt.
+     */
+    if request.version == "HTTP/1.0" {
+        return Ok(());
+    }
+    let hosts = request
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Host"))
+        .count();
+    if hosts == 1 {
+        Ok(())
+    } else {
+        Err(HTTPError::InvalidRequest)
+    }
+}
+
+pub fn parse_request<R: BufRead>(reader: &mut R) -> Result<Request, HTTPError> {
+    // Parse a request without limiting the length of its header values.
+    parse_request_with_limit(reader, None)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HeadLimits {
+    // HeadLimits bounds how much of a request head is read into memory.
+    // Decoded path length beyond which a request is answered with 414.
+    pub max_uri_length: Option<usize>,
+    // Header value length beyond which a request is answered with 431.
+    pub max_header_value: Option<usize>,
+    // Number of header fields beyond which a request is answered with 431.
+    pub max_headers: Option<usize>,
+    // Bytes of the whole header section beyond which a request is answered with 431.
+    pub max_header_bytes: Option<usize>,
+}
+
+pub fn parse_request_with_limit<R: BufRead>(
+    reader: &mut R,
+    max_header_value: Option<usize>,
+) -> Result<Request, HTTPError> {
+    // Parse a request with a limit on its header values, reading its whole body.
+    let limits = HeadLimits {
+        max_header_value,
+        ..HeadLimits::default()
+    };
+    let mut request = parse_request_head(reader, &limits)?;
+    read_request_body(reader, &mut request, &mut |_| true)?;
+    Ok(request)
+}
+
+pub fn parse_request_head<R: BufRead>(
+    reader: &mut R,
+    limits: &HeadLimits,
+) -> Result<Request, HTTPError> {
+    /* Parse the request line and headers from the client, leaving the body unread.
+     * The request must use a supported method with the HTTP version 1.0 or 1.1.
+     * A well-formed method which isn't supported is answered with 501, a
+     * malformed request line with 400.
+     * Lines may end with CRLF or, for lenient clients, a bare LF.
+     * The request line may be three times the maximum URI length, room for a
+     * percent-encoded path and a query, plus the method and version. A longer
+     * one is answered with 414 without reading all of it.
+     * A header value longer than the maximum is answered with 431, as are more
+     * header fields or header bytes in all than allowed. Header lines are never
+     * read much further than these limits into memory.
+     * If the request is valid, return the parsed request.
+     * If the request is invalid, return an error corresponding to the HTTP status code.
+     */
+    let max_request_line = limits
+        .max_uri_length
+        .map(|max| max.saturating_mul(3).saturating_add(REQUEST_LINE_ALLOWANCE));
+    let mut request_line = String::new();
+    if !read_limited_line_into(
+        reader,
+        &mut request_line,
+        max_request_line,
+        HTTPError::UriTooLong,
+    )? {
+        return Err(HTTPError::InvalidRequest);
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, uri, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(uri), Some(version), None) => (method, uri, version),
+        _ => return Err(HTTPError::InvalidRequest),
+    };
+
+    if !is_token(method) || !VERSIONS.contains(&version) {
+        return Err(HTTPError::InvalidRequest);
+    }
+
+    if !METHODS.contains(&method) {
+        return Err(HTTPError::NotImplemented);
+    }
+
+    // the header lines are read into one buffer and only the fields are copied
+    let mut headers = Vec::with_capacity(8);
+    let mut line = String::new();
+    let max_header_value = limits.max_header_value;
+    let max_value_line = max_header_value.map(|max| max + HEADER_NAME_ALLOWANCE);
+    let mut remaining = limits.max_header_bytes;
+    loop {
+        let max_line = match (max_value_line, remaining) {
+            (Some(value), Some(remaining)) => Some(value.min(remaining)),
+            (value, remaining) => value.or(remaining),
+        };
+        if !read_limited_line_into(reader, &mut line, max_line, HTTPError::HeaderFieldsTooLarge)? {
+            break;
+        }
+        if line.is_empty() {
+            break;
+        }
+        if limits.max_headers.is_some_and(|max| headers.len() >= max) {
+            return Err(HTTPError::HeaderFieldsTooLarge);
+        }
+        remaining = remaining.map(|remaining| remaining.saturating_sub(line.len() + 2));
+
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => return Err(HTTPError::InvalidRequest),
+        };
+        if name.is_empty() {
+            return Err(HTTPError::InvalidRequest);
+        }
+        if max_header_value.is_some_and(|max| value.len() > max) {
+            return Err(HTTPError::HeaderFieldsTooLarge);
+        }
+        headers.push((name.to_string(), value.to_string()));
+    }
+
+    Ok(Request {
+        method: method.to_string(),
+        uri: uri.to_string(),
+        version: version.to_string(),
+        headers,
+        body: Vec::new(),
+        extensions: Extensions::new(),
+    })
+}
+
+pub fn read_request_body<R: BufRead>(
+    reader: &mut R,
+    request: &mut Request,
+    reserve: &mut dyn FnMut(u64) -> bool,
+) -> Result<(), HTTPError> {
+    /*
+    Read the body of a request whose head was parsed.
+    A body is read if the request has a Content-Length header or a chunked
+    Transfer-Encoding. Other transfer codings are not implemented.
+    Memory for the body is reserved before it is read, for the Content-Length
+    or each chunk. If reserve refuses it, the request is answered with 503
+    and the rest of the body is left unread.
+     */
+    if let Some(encoding) = request.header("Transfer-Encoding") {
+        // only a single chunked coding is supported, and a Content-Length
+        // next to it could be used to smuggle a second request
+        if !encoding.trim().eq_ignore_ascii_case("chunked") {
+            return Err(HTTPError::NotImplemented);
+        }
+        if request.header("Content-Length").is_some() {
+            return Err(HTTPError::InvalidRequest);
+        }
+        request.body = read_chunked_body(reader, reserve)?;
+    } else if let Some(length) = request.content_length()? {
+        if !reserve(length) {
+            return Err(HTTPError::ServiceUnavailable);
+        }
+        read_body(reader, length, &mut request.body)?;
+    }
+    Ok(())
+}
+
+fn read_chunked_body<R: BufRead>(
+    reader: &mut R,
+    reserve: &mut dyn FnMut(u64) -> bool,
+) -> Result<Vec<u8>, HTTPError> {
+    /*
+    Read a body sent with the chunked transfer coding.
+    Memory for each chunk is reserved before it is read.
+    Chunk extensions and trailer fields are discarded. Over-long chunk size
+    lines are answered with 400 and trailer lines with 431, before they are
+    read to their end.
+     */
+    let mut body = Vec::new();
+    let mut line = String::new();
+    loop {
+        if !read_limited_line_into(
+            reader,
+            &mut line,
+            Some(MAX_CHUNK_LINE),
+            HTTPError::InvalidRequest,
+        )? {
+            return Err(HTTPError::InvalidRequest);
+        }
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| HTTPError::InvalidRequest)?;
+        if size == 0 {
+            break;
+        }
+        if !reserve(size) {
+            return Err(HTTPError::ServiceUnavailable);
+        }
+
+        read_body(reader, size, &mut body)?;
+        let ended = read_limited_line_into(reader, &mut line, Some(0), HTTPError::InvalidRequest)?;
+        if !ended || !line.is_empty() {
+            return Err(HTTPError::InvalidRequest);
+        }
+    }
+
+    // skip the trailer fields up to the empty line ending the body
+    loop {
+        let read = read_limited_line_into(
+            reader,
+            &mut line,
+            Some(MAX_TRAILER_LINE),
+            HTTPError::HeaderFieldsTooLarge,
+        )?;
+        match read {
+            true if !line.is_empty() => continue,
+            true => return Ok(body),
+            false => return Err(HTTPError::InvalidRequest),
+        }
+    }
+}
+
+fn read_body<R: BufRead>(reader: &mut R, length: u64, body: &mut Vec<u8>) -> Result<(), HTTPError> {
+    /*
+    Append exactly length bytes of the body to the buffer.
+    The buffer grows with the bytes actually received, so a client cannot make
+    the server allocate memory by only announcing a large body.
+     */
+    match reader.take(length).read_to_end(body) {
+        Ok(read) if read as u64 == length => Ok(()),
+        Err(e) if is_timeout(&e) => Err(HTTPError::RequestTimeout),
+        _ => Err(HTTPError::InvalidRequest),
+    }
+}
+
+fn is_token(value: &str) -> bool {
+    // Check whether a value is a token as methods are, e.g. "GET" but not "GE(T".
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+pub fn skip_empty_lines<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    /*
+    Skip the empty lines a client may send before the request line.
+    Returns false if the client closed the connection without sending a request,
+    e.g. a health check which only opens the connection.
+     */
+    loop {
+        let buf = reader.fill_buf()?;
+        match buf.first() {
+            None => return Ok(false),
+            Some(b'\r' | b'\n') => reader.consume(1),
+            Some(_) => return Ok(true),
+        }
+    }
+}
+
+fn read_limited_line_into<R: BufRead>(
+    reader: &mut R,
+    line: &mut String,
+    max: Option<usize>,
+    too_long: HTTPError,
+) -> Result<bool, HTTPError> {
+    /*
+    Read a single line without its line ending into a buffer, replacing its
+    contents, failing with the too_long error once it is longer than max bytes.
+    At most the limit and the line ending are read, so a client can't make the
+    server buffer an endless line.
+    Returns false if the client closed the connection.
+     */
+    line.clear();
+    let result = match max {
+        Some(max) => {
+            let limit = max as u64 + 2;
+            match reader.take(limit).read_line(line) {
+                Ok(read) if read as u64 == limit && !line.ends_with('\n') => return Err(too_long),
+                result => result,
+            }
+        }
+        None => reader.read_line(line),
+    };
+    match result {
+        Ok(0) => Ok(false),
+        Ok(_) => {
+            let length = line.trim_end_matches(['\r', '\n']).len();
+            line.truncate(length);
+            Ok(true)
+        }
+        Err(e) if is_timeout(&e) => Err(HTTPError::RequestTimeout),
+        Err(_) => Err(HTTPError::InvalidRequest),
+    }
+}
+
+pub fn is_timeout(error: &io::Error) -> bool {
+    /*
+    Check whether a read or write failed because the timeout of the socket passed.
+     */
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+pub fn validate_uri_length(request: &Request, max: Option<usize>) -> Result<(), HTTPError> {
+    /*
+    Check that the decoded path of a request is at most max bytes long.
+    A path which can't be decoded is measured as sent.
+     */
+    let max = match max {
+        Some(max) => max,
+        None => return Ok(()),
+    };
+    let (path, _) = split_uri(&request.uri);
+    let length = decode_path(path).map_or(path.len(), |path| path.len());
+    if length > max {
+        Err(HTTPError::UriTooLong)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        // Test parsing a request with headers.
+        let mut reader = "GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-1\r\n\r\n".as_bytes();
+        let request = parse_request(&mut reader).unwrap();
+
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.uri, "/");
+        assert_eq!(request.version, "HTTP/1.1");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.header("RANGE"), Some("bytes=0-1"));
+        assert_eq!(request.header("Accept"), None);
+    }
+
+    #[test]
+    fn test_parse_line_endings() {
+        // Test that CRLF, LF-only and mixed line endings find the same body.
+        for (name, ending) in [("CRLF", "\r\n"), ("LF", "\n")] {
+            let head = format!(
+                "POST /form HTTP/1.1{0}Host: localhost{0}Content-Length: 4{0}{0}",
+                ending
+            );
+            let text = format!("{}a\r\nbGET / HTTP/1.1{1}Host: x{1}{1}", head, ending);
+            let mut reader = text.as_bytes();
+
+            let request = parse_request(&mut reader).unwrap();
+            assert_eq!(request.header("Host"), Some("localhost"), "{}", name);
+            assert_eq!(request.body, b"a\r\nb".to_vec(), "{}", name);
+            let next = parse_request(&mut reader).unwrap();
+            assert_eq!(next.uri, "/", "{}", name);
+            assert_eq!(next.header("Host"), Some("x"), "{}", name);
+        }
+
+        let mut reader = "POST / HTTP/1.1\nHost: a\r\nTransfer-Encoding: chunked\n\r\n\
+                          3\nabc\r\n0\n\n"
+            .as_bytes();
+        let request = parse_request(&mut reader).unwrap();
+        assert_eq!(request.header("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(request.body, b"abc".to_vec());
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_parse_request_body() {
+        // Test parsing a request with a body.
+        let mut reader = "POST /form HTTP/1.1\r\nContent-Length: 5\r\n\r\nx=1&y".as_bytes();
+        let request = parse_request(&mut reader).unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.body, b"x=1&y".to_vec());
+
+        let mut reader = "POST /form HTTP/1.1\r\nContent-Length: 9\r\n\r\nx=1".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+
+        let mut reader =
+            "POST /form HTTP/1.1\r\nContent-Length: 99999999999999\r\n\r\nx=1".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+    }
+
+    #[test]
+    fn test_parse_request_transfer_encoding() {
+        // Test reading chunked bodies and rejecting other transfer codings.
+        let mut reader = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                          5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n"
+            .as_bytes();
+        assert_eq!(parse_request(&mut reader).unwrap().body, b"hello world");
+
+        let mut reader = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::NotImplemented));
+
+        let mut reader = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::NotImplemented));
+
+        let mut reader = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
+                          Content-Length: 5\r\n\r\n0\r\n\r\n"
+            .as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+
+        let mut reader = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+    }
+
+    #[test]
+    fn test_content_length() {
+        // Test that conflicting or malformed Content-Length headers are rejected.
+        let parse = |headers: &str| {
+            parse_request(&mut format!("POST / HTTP/1.1\r\n{headers}\r\nhello").as_bytes())
+        };
+        assert_eq!(parse("Content-Length: 5\r\n").unwrap().body, b"hello");
+        assert_eq!(
+            parse("Content-Length: 5\r\ncontent-length: 5\r\n")
+                .unwrap()
+                .body,
+            b"hello"
+        );
+        assert_eq!(parse("Content-Length: 5, 5\r\n").unwrap().body, b"hello");
+
+        for headers in [
+            "Content-Length: 5\r\nContent-Length: 4\r\n",
+            "Content-Length: 5, 4\r\n",
+            "Content-Length: +5\r\n",
+            "Content-Length: -5\r\n",
+            "Content-Length: 0x5\r\n",
+            "Content-Length: 5 5\r\n",
+            "Content-Length: \r\n",
+        ] {
+            assert_eq!(
+                parse(headers),
+                Err(HTTPError::InvalidRequest),
+                "{}",
+                headers
+            );
+        }
+    }
+
+    #[test]
+    fn test_skip_empty_lines() {
+        // Test that empty lines before the request line are skipped.
+        let mut reader = "\r\n\r\nGET / HTTP/1.1\r\n\r\n".as_bytes();
+        assert!(skip_empty_lines(&mut reader).unwrap());
+        assert_eq!(parse_request(&mut reader).unwrap().uri, "/");
+
+        let mut reader = "\r\n".as_bytes();
+        assert!(!skip_empty_lines(&mut reader).unwrap());
+
+        let mut reader = "".as_bytes();
+        assert!(!skip_empty_lines(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn test_validate_host() {
+        // Test that HTTP/1.1 requests need exactly one Host header while HTTP/1.0 ones don't.
+        let parse = |raw: &str| parse_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(
+            validate_host(&parse("GET / HTTP/1.1\r\n\r\n")),
+            Err(HTTPError::InvalidRequest)
+        );
+        assert_eq!(
+            validate_host(&parse("GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n")),
+            Err(HTTPError::InvalidRequest)
+        );
+        assert_eq!(
+            validate_host(&parse("GET / HTTP/1.1\r\nhost: a\r\n\r\n")),
+            Ok(())
+        );
+        assert_eq!(validate_host(&parse("GET / HTTP/1.0\r\n\r\n")), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_uri_length() {
+        // Test that the path is measured decoded and without the query.
+        let parse = |raw: &str| parse_request(&mut raw.as_bytes()).unwrap();
+        let request = parse("GET /%61%62c?long=query HTTP/1.1\r\n\r\n");
+        assert_eq!(validate_uri_length(&request, Some(4)), Ok(()));
+        assert_eq!(
+            validate_uri_length(&request, Some(3)),
+            Err(HTTPError::UriTooLong)
+        );
+        assert_eq!(validate_uri_length(&request, None), Ok(()));
+    }
+
+    #[test]
+    fn test_header_value_limit() {
+        // Test that an over-long header value is rejected with 431 without reading all of it.
+        let parse = |raw: &str| parse_request_with_limit(&mut raw.as_bytes(), Some(16));
+        assert!(parse("GET / HTTP/1.1\r\nX-Short: 0123456789abcdef\r\n\r\n").is_ok());
+        assert_eq!(
+            parse("GET / HTTP/1.1\r\nX-Long: 0123456789abcdefg\r\n\r\n"),
+            Err(HTTPError::HeaderFieldsTooLarge)
+        );
+
+        let raw = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "x".repeat(1 << 20));
+        let mut reader = raw.as_bytes();
+        assert_eq!(
+            parse_request_with_limit(&mut reader, Some(16)),
+            Err(HTTPError::HeaderFieldsTooLarge)
+        );
+        assert!(reader.len() > raw.len() - 512);
+
+        assert!(parse_request(&mut raw.as_bytes()).is_ok());
+        assert_eq!(
+            HTTPError::HeaderFieldsTooLarge.status_code(),
+            StatusCode::RequestHeaderFieldsTooLarge
+        );
+    }
+
+    #[test]
+    fn test_header_section_limits() {
+        // Test that too many small headers are rejected with 431 without reading all of them.
+        let limits = HeadLimits {
+            max_header_value: Some(16),
+            max_headers: Some(4),
+            max_header_bytes: Some(64),
+            ..HeadLimits::default()
+        };
+        let parse = |raw: &str| parse_request_head(&mut raw.as_bytes(), &limits);
+        assert!(parse("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n\r\n").is_ok());
+        assert_eq!(
+            parse("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\nE: 5\r\n\r\n"),
+            Err(HTTPError::HeaderFieldsTooLarge)
+        );
+        assert_eq!(
+            parse("GET / HTTP/1.1\r\nX-Long-1: 0123456789abcdef\r\nX-Long-2: 0123456789abcdef\r\nX-Long-3: 0123456789abcdef\r\n\r\n"),
+            Err(HTTPError::HeaderFieldsTooLarge)
+        );
+
+        let raw = format!("GET / HTTP/1.1\r\n{}\r\n", "X: 1\r\n".repeat(1 << 16));
+        let mut reader = raw.as_bytes();
+        assert_eq!(
+            parse_request_head(&mut reader, &limits),
+            Err(HTTPError::HeaderFieldsTooLarge)
+        );
+        assert!(reader.len() > raw.len() - 512);
+        assert!(parse_request(&mut raw.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_request_line_limit() {
+        // Test that an endless request line is answered with 414 without reading all of it.
+        let limits = HeadLimits {
+            max_uri_length: Some(16),
+            ..HeadLimits::default()
+        };
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "x".repeat(1 << 20));
+        let mut reader = raw.as_bytes();
+        assert_eq!(
+            parse_request_head(&mut reader, &limits),
+            Err(HTTPError::UriTooLong)
+        );
+        assert!(reader.len() > raw.len() - 512);
+
+        // an encoded path within the limit fits on the line
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "%41".repeat(15));
+        let request = parse_request_head(&mut raw.as_bytes(), &limits).unwrap();
+        assert_eq!(validate_uri_length(&request, limits.max_uri_length), Ok(()));
+        assert!(parse_request(
+            &mut format!("GET /{} HTTP/1.1\r\n\r\n", "x".repeat(1 << 16)).as_bytes()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_chunk_line_limit() {
+        // Test that endless chunk size and trailer lines are rejected without reading all of them.
+        let endless = "1".repeat(1 << 20);
+        let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{endless}\r\n");
+        let mut reader = raw.as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+        assert!(reader.len() > raw.len() - 2048);
+
+        let raw = format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\n0\r\nX-Trailer: {endless}\r\n\r\n"
+        );
+        let mut reader = raw.as_bytes();
+        assert_eq!(
+            parse_request(&mut reader),
+            Err(HTTPError::HeaderFieldsTooLarge)
+        );
+        assert!(reader.len() > raw.len() - 16384);
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nab\r\n0\r\n\r\n";
+        assert_eq!(
+            parse_request(&mut raw.as_bytes()),
+            Err(HTTPError::InvalidRequest)
+        );
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        // Test the connection defaults of both versions.
+        let parse = |raw: &str| parse_request(&mut raw.as_bytes()).unwrap();
+        assert!(parse("GET / HTTP/1.1\r\n\r\n").keep_alive());
+        assert!(!parse("GET / HTTP/1.1\r\nConnection: Close\r\n\r\n").keep_alive());
+        assert!(!parse("GET / HTTP/1.0\r\n\r\n").keep_alive());
+        assert!(parse("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").keep_alive());
+    }
+
+    #[test]
+    fn test_param() {
+        // Test that parameters are read from the query first, then from a form body.
+        let parse = |raw: &str| parse_request(&mut raw.as_bytes()).unwrap();
+        let request = parse("GET /search?x=1&y=a%20b&x=2 HTTP/1.1\r\n\r\n");
+        assert_eq!(request.param("x"), Some("1".to_string()));
+        assert_eq!(request.param("y"), Some("a b".to_string()));
+        assert_eq!(request.param("z"), None);
+
+        let form = "Content-Type: application/x-www-form-urlencoded; charset=utf-8";
+        let request = parse(&format!(
+            "POST /search HTTP/1.1\r\n{form}\r\nContent-Length: 9\r\n\r\nx=3&z=c+d"
+        ));
+        assert_eq!(request.param("x"), Some("3".to_string()));
+        assert_eq!(request.param("z"), Some("c d".to_string()));
+
+        let request = parse(&format!(
+            "POST /search?x=1 HTTP/1.1\r\n{form}\r\nContent-Length: 7\r\n\r\nx=3&w=4"
+        ));
+        assert_eq!(request.param("x"), Some("1".to_string()));
+        assert_eq!(request.param("w"), Some("4".to_string()));
+
+        let request = parse(
+            "POST /search HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 3\r\n\r\nx=3",
+        );
+        assert_eq!(request.param("x"), None);
+    }
+
+    #[test]
+    fn test_parse_invalid_request() {
+        // Test that invalid requests are rejected.
+        let mut reader = "FOO / HTTP/1.1\r\n\r\n".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::NotImplemented));
+
+        let mut reader = "G(E)T / HTTP/1.1\r\n\r\n".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+
+        let mut reader = "FOO / HTTP/9.9\r\n\r\n".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+
+        let mut reader = "\x01\x02garbage\r\n\r\n".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+
+        let mut reader = "GET / HTTP/2.0\r\n\r\n".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+
+        let mut reader = "GET /\r\n\r\n".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+
+        let mut reader = "GET / HTTP/1.1\r\nno colon\r\n\r\n".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+
+        let mut reader = "".as_bytes();
+        assert_eq!(parse_request(&mut reader), Err(HTTPError::InvalidRequest));
+    }
+}
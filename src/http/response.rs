@@ -0,0 +1,473 @@
+/*
+* A response that is sent back to the client.
+*/
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    file::{etag, last_modified, EtagAlgorithm},
+    mime::content_type,
+    status::StatusCode,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: StatusCode,
+    // Additional headers besides Content-Length and Content-Type.
+    pub headers: Vec<(String, String)>,
+    pub content_type: String,
+    pub body: Vec<u8>,
+    // Body streamed from a file instead of held in memory, the body is empty then.
+    pub file: Option<FileBody>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileBody {
+    // FileBody is a range of bytes of a file sent as the body of a response.
+    pub path: PathBuf,
+    pub start: u64,
+    pub length: u64,
+}
+
+impl Response {
+    pub fn new(status: StatusCode, content_type: &str, body: Vec<u8>) -> Response {
+        // Create a new response with the given status, content type and body.
+        Response {
+            status,
+            headers: Vec::new(),
+            content_type: content_type.to_string(),
+            body,
+            file: None,
+        }
+    }
+
+    pub fn from_file_range(status: StatusCode, content_type: &str, file: FileBody) -> Response {
+        // Create a response whose body is streamed from a range of a file when written.
+        let mut response = Response::new(status, content_type, Vec::new());
+        response.file = Some(file);
+        response
+    }
+
+    pub fn from_file(path: &Path) -> Response {
+        /*
+        Create a response serving a whole file, for handlers answering with a file
+        of their choosing. The content type is detected from the extension and
+        the ETag and Last-Modified headers are set from the metadata of the file.
+        A missing file is answered with 404, a file that can't be read with 500.
+        See serve_file for serving files with conditional and range requests.
+         */
+        let read = fs::metadata(path).and_then(|metadata| Ok((fs::read(path)?, metadata)));
+        let (body, metadata) = match read {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Response::from_status(StatusCode::NotFound)
+            }
+            Err(_) => return Response::from_status(StatusCode::InternalServerError),
+        };
+        let mut response = Response::new(StatusCode::Ok, content_type(path), body);
+        if let Ok(etag) = etag(path, &metadata, EtagAlgorithm::default()) {
+            response.add_header("ETag", &etag);
+        }
+        if let Some(last_modified) = last_modified(&metadata) {
+            response.add_header("Last-Modified", &last_modified);
+        }
+        response
+    }
+
+    pub fn from_status(status: StatusCode) -> Response {
+        // Create a response for any status with a generated page naming the status.
+        Response::new(
+            status,
+            "text/html; charset=UTF-8",
+            generated_status_page(status).into_bytes(),
+        )
+    }
+
+    pub fn redirect(status: StatusCode, location: &str) -> Response {
+        // Create a response sending the client to another location, e.g. with 302 Found.
+        let mut response = Response::new(status, "text/html; charset=UTF-8", Vec::new());
+        response.add_header("Location", location);
+        response
+    }
+
+    pub fn download(body: Vec<u8>, filename: &str) -> Response {
+        // Create a response which clients save as a file instead of displaying it.
+        // The content type is detected from the extension of the file name.
+        let mut response = Response::new(StatusCode::Ok, content_type(Path::new(filename)), body);
+        response.add_header("Content-Disposition", &content_disposition(filename));
+        response
+    }
+
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        // Add a header to the response.
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn add_vary(&mut self, name: &str) {
+        // Add a request header the response depends on to its Vary header,
+        // merging it into an existing Vary header instead of repeating it.
+        let existing = self
+            .headers
+            .iter_mut()
+            .find(|(key, _)| key.eq_ignore_ascii_case("Vary"));
+        match existing {
+            Some((_, value)) => {
+                let listed = value
+                    .split(',')
+                    .map(str::trim)
+                    .any(|listed| listed == "*" || listed.eq_ignore_ascii_case(name));
+                if !listed {
+                    value.push_str(", ");
+                    value.push_str(name);
+                }
+            }
+            None => self.add_header("Vary", name),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        // Get the value of a header by name.
+        // Header names are compared case-insensitively.
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn order_headers(&mut self, order: &[String]) {
+        // Move the headers named in the order to the front, in that order.
+        // Other headers follow in the order they were added.
+        let position = |name: &str| {
+            order
+                .iter()
+                .position(|ordered| ordered.eq_ignore_ascii_case(name))
+                .unwrap_or(order.len())
+        };
+        self.headers.sort_by_key(|(name, _)| position(name));
+    }
+
+    pub fn content_length(&self) -> u64 {
+        // Get the length of the body, whether it is held in memory or streamed.
+        match &self.file {
+            Some(file) => file.length,
+            None => self.body.len() as u64,
+        }
+    }
+
+    pub fn head(&self) -> Vec<u8> {
+        // Serialize the status line and headers, including the blank line ending them.
+        // Statuses without a body are framed by their headers alone, so they
+        // have no Content-Length. Header names are sent in canonical casing.
+        let mut head = format!("{}\r\n", self.status.status_line());
+        if self.status.allows_body() {
+            head.push_str(&format!("Content-Length: {}\r\n", self.content_length()));
+        }
+        head.push_str(&format!("Content-Type: {}\r\n", self.content_type));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", canonical_header_name(name), value));
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Serialize the response so it can be written to the client.
+        // A body streamed from a file is not included, see ResponseWriter.
+        let mut bytes = self.head();
+        if self.status.allows_body() {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrecomputedResponse {
+    // PrecomputedResponse is a response serialized once and written as is on
+    // every request, e.g. for health checks.
+    response: Response,
+    // The serialized response for connections kept open and for closing ones.
+    keep_alive: Vec<u8>,
+    close: Vec<u8>,
+    // Length of the body ending the bytes, left out for a HEAD request.
+    body_length: usize,
+}
+
+impl PrecomputedResponse {
+    pub fn new(mut response: Response) -> io::Result<PrecomputedResponse> {
+        /*
+        Serialize a response once so no formatting or file reads are needed per request.
+        A body streamed from a file is read into memory here, so later changes
+        to the file are not picked up.
+        The bytes have no Date header, as it would be out of date on every later request.
+         */
+        if let Some(file) = response.file.take() {
+            let mut reader = File::open(&file.path)?;
+            reader.seek(SeekFrom::Start(file.start))?;
+            reader.take(file.length).read_to_end(&mut response.body)?;
+        }
+
+        let keep_alive = response.to_bytes();
+        let body_length = keep_alive.len() - response.head().len();
+        let mut closing = response.clone();
+        closing.add_header("Connection", "close");
+        Ok(PrecomputedResponse {
+            close: closing.to_bytes(),
+            keep_alive,
+            body_length,
+            response,
+        })
+    }
+
+    pub fn response(&self) -> &Response {
+        // Get the response the bytes were serialized from.
+        &self.response
+    }
+
+    pub fn bytes(&self, close: bool, head_only: bool) -> &[u8] {
+        // Get the bytes to write, announcing whether the connection is closed
+        // after them and leaving out the body for a HEAD request.
+        let bytes = if close { &self.close } else { &self.keep_alive };
+        if head_only {
+            &bytes[..bytes.len() - self.body_length]
+        } else {
+            bytes
+        }
+    }
+}
+
+pub fn generated_status_page(status: StatusCode) -> String {
+    // Generate a minimal page naming the status, e.g. "404 Not Found".
+    format!(
+        "<!DOCTYPE html><html><head><title>{0}</title></head><body><h1>{0}</h1></body></html>",
+        status
+    )
+}
+
+pub fn content_disposition(filename: &str) -> String {
+    /*
+    Get the Content-Disposition value for downloading a file under a name,
+    e.g. attachment; filename="report 1.pdf".
+    Quotes and backslashes are escaped and control characters replaced.
+    Names with non-ASCII characters also get a UTF-8 encoded filename* parameter,
+    with an ASCII approximation in filename for older clients.
+     */
+    let mut fallback = String::with_capacity(filename.len());
+    for c in filename.chars() {
+        match c {
+            '"' | '\\' => {
+                fallback.push('\\');
+                fallback.push(c);
+            }
+            c if c.is_ascii() && !c.is_ascii_control() => fallback.push(c),
+            _ => fallback.push('_'),
+        }
+    }
+
+    if filename.is_ascii() && !filename.contains(|c: char| c.is_ascii_control()) {
+        return format!("attachment; filename=\"{}\"", fallback);
+    }
+
+    let mut encoded = String::with_capacity(filename.len() * 3);
+    for byte in filename.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => encoded.push(byte as char),
+            b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+pub fn canonical_header_name(name: &str) -> String {
+    /*
+    Get the canonical casing of a header name, e.g. "Content-Type" for "content-type".
+    Each dash separated word is capitalized, except for names with a casing of
+    their own, e.g. ETag.
+     */
+    const SPECIAL: [&str; 5] = ["ETag", "WWW-Authenticate", "TE", "DNT", "X-XSS-Protection"];
+    if let Some(special) = SPECIAL
+        .iter()
+        .find(|special| special.eq_ignore_ascii_case(name))
+    {
+        return special.to_string();
+    }
+    let mut canonical = String::with_capacity(name.len());
+    let mut word_start = true;
+    for c in name.chars() {
+        canonical.push(if word_start {
+            c.to_ascii_uppercase()
+        } else {
+            c.to_ascii_lowercase()
+        });
+        word_start = c == '-';
+    }
+    canonical
+}
+
+pub fn parse_header_order(value: &str) -> Vec<String> {
+    /*
+    Parse a comma separated list of header names, e.g. "Date, Server".
+     */
+    value
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_bytes() {
+        // Test serializing a response with an additional header.
+        let mut response = Response::new(StatusCode::Ok, "text/plain", b"hello".to_vec());
+        response.add_header("ETag", "\"abc\"");
+
+        assert_eq!(response.header("etag"), Some("\"abc\""));
+        assert_eq!(
+            response.to_bytes(),
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nContent-Type: text/plain\r\nETag: \"abc\"\r\n\r\nhello"
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_header_order() {
+        // Test that headers are sent in the configured order and canonical casing.
+        let mut response = Response::new(StatusCode::Ok, "text/plain", b"hi".to_vec());
+        response.add_header("x-request-id", "7");
+        response.add_header("etag", "\"abc\"");
+        response.add_header("cache-control", "no-cache");
+        response.add_header("DATE", "Sun, 06 Nov 1994 08:49:37 GMT");
+        response.order_headers(&parse_header_order("Date, ETag,"));
+        assert_eq!(
+            String::from_utf8(response.head()).unwrap(),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Length: 2\r\n\
+             Content-Type: text/plain\r\n\
+             Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+             ETag: \"abc\"\r\n\
+             X-Request-Id: 7\r\n\
+             Cache-Control: no-cache\r\n\r\n"
+        );
+        assert_eq!(
+            canonical_header_name("www-authenticate"),
+            "WWW-Authenticate"
+        );
+        assert_eq!(parse_header_order(" , "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_bodiless_statuses() {
+        // Test that 1xx, 204 and 304 responses are sent without a body or Content-Length.
+        for status in [
+            StatusCode::Continue,
+            StatusCode::NoContent,
+            StatusCode::NotModified,
+        ] {
+            let mut response = Response::from_status(status);
+            response.add_header("ETag", "\"abc\"");
+            let bytes = String::from_utf8(response.to_bytes()).unwrap();
+            assert!(!bytes.contains("Content-Length"), "{}", status);
+            assert!(bytes.ends_with("\r\nETag: \"abc\"\r\n\r\n"), "{}", status);
+        }
+
+        let response = Response::new(StatusCode::Ok, "text/plain", Vec::new());
+        assert!(String::from_utf8(response.to_bytes())
+            .unwrap()
+            .contains("\r\nContent-Length: 0\r\n"));
+
+        let precomputed =
+            PrecomputedResponse::new(Response::from_status(StatusCode::NoContent)).unwrap();
+        assert_eq!(
+            precomputed.bytes(false, true),
+            precomputed.bytes(false, false)
+        );
+        assert!(precomputed
+            .bytes(true, true)
+            .ends_with(b"Connection: close\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_add_vary() {
+        // Test that varying headers are merged into a single Vary header.
+        let mut response = Response::new(StatusCode::Ok, "text/plain", Vec::new());
+        response.add_vary("Accept-Encoding");
+        response.add_vary("accept-encoding");
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+
+        response.add_vary("Origin");
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding, Origin"));
+        assert_eq!(response.headers.len(), 1);
+
+        let mut response = Response::new(StatusCode::Ok, "text/plain", Vec::new());
+        response.add_header("Vary", "*");
+        response.add_vary("Accept-Encoding");
+        assert_eq!(response.header("Vary"), Some("*"));
+    }
+
+    #[test]
+    fn test_from_file() {
+        // Test serving a file with its headers and a missing file with 404.
+        let path =
+            std::env::temp_dir().join(format!("rust-webserver-{}-page.html", std::process::id()));
+        fs::write(&path, "<p>hi</p>").unwrap();
+        let response = Response::from_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.content_type, "text/html; charset=UTF-8");
+        assert_eq!(response.body, b"<p>hi</p>");
+        assert!(response
+            .header("ETag")
+            .is_some_and(|etag| etag.starts_with("W/\"")));
+        assert!(response.header("Last-Modified").is_some());
+        assert!(String::from_utf8(response.head())
+            .unwrap()
+            .contains("\r\nContent-Length: 9\r\n"));
+
+        let missing = Response::from_file(&path);
+        assert_eq!(missing.status, StatusCode::NotFound);
+        assert_eq!(missing.header("ETag"), None);
+    }
+
+    #[test]
+    fn test_from_status() {
+        // Test creating a response for a status without a page.
+        let response = Response::from_status(StatusCode::TooManyRequests);
+        assert!(response
+            .to_bytes()
+            .starts_with(b"HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(String::from_utf8(response.body)
+            .unwrap()
+            .contains("<h1>429 Too Many Requests</h1>"));
+    }
+
+    #[test]
+    fn test_download() {
+        // Test that downloads are named with escaped file names.
+        let response = Response::download(b"a,b".to_vec(), "my \"big\" report.csv");
+        assert_eq!(response.content_type, "text/csv; charset=UTF-8");
+        assert_eq!(
+            response.header("Content-Disposition"),
+            Some("attachment; filename=\"my \\\"big\\\" report.csv\"")
+        );
+
+        assert_eq!(
+            content_disposition("café.txt"),
+            "attachment; filename=\"caf_.txt\"; filename*=UTF-8''caf%C3%A9.txt"
+        );
+    }
+}
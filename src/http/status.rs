@@ -0,0 +1,236 @@
+/*
+* HTTP status codes and their reason phrases.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatusCode {
+    Continue,
+    SwitchingProtocols,
+    Ok,
+    Created,
+    Accepted,
+    NonAuthoritativeInformation,
+    NoContent,
+    ResetContent,
+    PartialContent,
+    MultipleChoices,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    PaymentRequired,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    ProxyAuthenticationRequired,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PreconditionFailed,
+    ContentTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    MisdirectedRequest,
+    UnprocessableContent,
+    UpgradeRequired,
+    PreconditionRequired,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+}
+
+const STATUS_CODES: [(StatusCode, u16, &str); 46] = [
+    (StatusCode::Continue, 100, "Continue"),
+    (StatusCode::SwitchingProtocols, 101, "Switching Protocols"),
+    (StatusCode::Ok, 200, "OK"),
+    (StatusCode::Created, 201, "Created"),
+    (StatusCode::Accepted, 202, "Accepted"),
+    (
+        StatusCode::NonAuthoritativeInformation,
+        203,
+        "Non-Authoritative Information",
+    ),
+    (StatusCode::NoContent, 204, "No Content"),
+    (StatusCode::ResetContent, 205, "Reset Content"),
+    (StatusCode::PartialContent, 206, "Partial Content"),
+    (StatusCode::MultipleChoices, 300, "Multiple Choices"),
+    (StatusCode::MovedPermanently, 301, "Moved Permanently"),
+    (StatusCode::Found, 302, "Found"),
+    (StatusCode::SeeOther, 303, "See Other"),
+    (StatusCode::NotModified, 304, "Not Modified"),
+    (StatusCode::TemporaryRedirect, 307, "Temporary Redirect"),
+    (StatusCode::PermanentRedirect, 308, "Permanent Redirect"),
+    (StatusCode::BadRequest, 400, "Bad Request"),
+    (StatusCode::Unauthorized, 401, "Unauthorized"),
+    (StatusCode::PaymentRequired, 402, "Payment Required"),
+    (StatusCode::Forbidden, 403, "Forbidden"),
+    (StatusCode::NotFound, 404, "Not Found"),
+    (StatusCode::MethodNotAllowed, 405, "Method Not Allowed"),
+    (StatusCode::NotAcceptable, 406, "Not Acceptable"),
+    (
+        StatusCode::ProxyAuthenticationRequired,
+        407,
+        "Proxy Authentication Required",
+    ),
+    (StatusCode::RequestTimeout, 408, "Request Timeout"),
+    (StatusCode::Conflict, 409, "Conflict"),
+    (StatusCode::Gone, 410, "Gone"),
+    (StatusCode::LengthRequired, 411, "Length Required"),
+    (StatusCode::PreconditionFailed, 412, "Precondition Failed"),
+    (StatusCode::ContentTooLarge, 413, "Content Too Large"),
+    (StatusCode::UriTooLong, 414, "URI Too Long"),
+    (
+        StatusCode::UnsupportedMediaType,
+        415,
+        "Unsupported Media Type",
+    ),
+    (
+        StatusCode::RangeNotSatisfiable,
+        416,
+        "Range Not Satisfiable",
+    ),
+    (StatusCode::ExpectationFailed, 417, "Expectation Failed"),
+    (StatusCode::MisdirectedRequest, 421, "Misdirected Request"),
+    (
+        StatusCode::UnprocessableContent,
+        422,
+        "Unprocessable Content",
+    ),
+    (StatusCode::UpgradeRequired, 426, "Upgrade Required"),
+    (
+        StatusCode::PreconditionRequired,
+        428,
+        "Precondition Required",
+    ),
+    (StatusCode::TooManyRequests, 429, "Too Many Requests"),
+    (
+        StatusCode::RequestHeaderFieldsTooLarge,
+        431,
+        "Request Header Fields Too Large",
+    ),
+    (
+        StatusCode::InternalServerError,
+        500,
+        "Internal Server Error",
+    ),
+    (StatusCode::NotImplemented, 501, "Not Implemented"),
+    (StatusCode::BadGateway, 502, "Bad Gateway"),
+    (StatusCode::ServiceUnavailable, 503, "Service Unavailable"),
+    (StatusCode::GatewayTimeout, 504, "Gateway Timeout"),
+    (
+        StatusCode::HttpVersionNotSupported,
+        505,
+        "HTTP Version Not Supported",
+    ),
+];
+
+impl StatusCode {
+    pub fn from_u16(code: u16) -> Option<StatusCode> {
+        // Get the status for a numeric code, if the code is known.
+        STATUS_CODES
+            .iter()
+            .find(|(_, number, _)| *number == code)
+            .map(|(status, _, _)| *status)
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        // Get the numeric code of the status.
+        self.entry().1
+    }
+
+    pub fn reason_phrase(&self) -> &'static str {
+        // Get the reason phrase of the status, e.g. "Not Found".
+        self.entry().2
+    }
+
+    pub fn status_line(&self) -> String {
+        // Get the status line of a response with this status.
+        format!("HTTP/1.1 {} {}", self.as_u16(), self.reason_phrase())
+    }
+
+    pub fn is_success(&self) -> bool {
+        // Check whether the status is a 2xx status.
+        (200..300).contains(&self.as_u16())
+    }
+
+    pub fn allows_body(&self) -> bool {
+        // Check whether a response with the status may have a body.
+        // 1xx, 204 and 304 responses end with their headers.
+        !matches!(self.as_u16(), 100..=199 | 204 | 304)
+    }
+
+    fn entry(&self) -> &'static (StatusCode, u16, &'static str) {
+        // Every status has an entry in the table.
+        STATUS_CODES
+            .iter()
+            .find(|(status, _, _)| status == self)
+            .expect("status code missing from table")
+    }
+}
+
+impl std::fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.as_u16(), self.reason_phrase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_line() {
+        // Test that several codes serialize with the correct status line.
+        assert_eq!(StatusCode::Ok.status_line(), "HTTP/1.1 200 OK");
+        assert_eq!(
+            StatusCode::Unauthorized.status_line(),
+            "HTTP/1.1 401 Unauthorized"
+        );
+        assert_eq!(
+            StatusCode::Forbidden.status_line(),
+            "HTTP/1.1 403 Forbidden"
+        );
+        assert_eq!(StatusCode::Conflict.status_line(), "HTTP/1.1 409 Conflict");
+        assert_eq!(
+            StatusCode::UnprocessableContent.status_line(),
+            "HTTP/1.1 422 Unprocessable Content"
+        );
+        assert_eq!(
+            StatusCode::TooManyRequests.status_line(),
+            "HTTP/1.1 429 Too Many Requests"
+        );
+        assert_eq!(
+            StatusCode::InternalServerError.status_line(),
+            "HTTP/1.1 500 Internal Server Error"
+        );
+        assert_eq!(
+            StatusCode::ServiceUnavailable.status_line(),
+            "HTTP/1.1 503 Service Unavailable"
+        );
+    }
+
+    #[test]
+    fn test_from_u16() {
+        // Test looking up statuses by their numeric code.
+        assert_eq!(StatusCode::from_u16(404), Some(StatusCode::NotFound));
+        assert_eq!(StatusCode::from_u16(299), None);
+
+        for (status, code, _) in STATUS_CODES.iter() {
+            assert_eq!(StatusCode::from_u16(*code), Some(*status));
+            assert_eq!(status.as_u16(), *code);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+/*
+* Substitution of {{key}} placeholders in served files.
+*/
+
+use std::collections::HashMap;
+
+pub fn render(template: &str, context: &HashMap<String, String>, keep_unknown: bool) -> String {
+    /*
+    Replace the {{key}} placeholders of a template with the values of the context.
+    Whitespace around the key is ignored, so {{ name }} is the same as {{name}}.
+    Placeholders without a value are kept as they are or removed, depending on
+    keep_unknown, and an unterminated {{ is kept as text.
+     */
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = match after.find("}}") {
+            Some(end) => end,
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+
+        match context.get(after[..end].trim()) {
+            Some(value) => output.push_str(value),
+            None if keep_unknown => output.push_str(&rest[start..start + end + 4]),
+            None => (),
+        }
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        // Test substituting known placeholders and handling unknown ones.
+        let context = HashMap::from([("name".to_string(), "World".to_string())]);
+        assert_eq!(
+            render("<h1>Hello {{name}}, {{ name }}!</h1>", &context, true),
+            "<h1>Hello World, World!</h1>"
+        );
+        assert_eq!(
+            render("{{other}} {{name", &context, true),
+            "{{other}} {{name"
+        );
+        assert_eq!(render("a{{other}}b", &context, false), "ab");
+    }
+}
@@ -0,0 +1,128 @@
+/*
+* Splitting and percent-decoding of request uris.
+*/
+
+pub fn split_uri(uri: &str) -> (&str, Option<&str>) {
+    /*
+    Split a request uri into its path and query, dropping any fragment.
+     */
+    let uri = uri.split('#').next().unwrap_or("");
+    match uri.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (uri, None),
+    }
+}
+
+pub fn decode_path(path: &str) -> Option<String> {
+    /*
+    Percent-decode the path of a uri.
+    A plus sign is a literal plus in a path, e.g. "/a+b" stays "/a+b".
+    Returns None if the decoded path is not valid UTF-8.
+     */
+    percent_decode(path, false)
+}
+
+pub fn decode_query_component(value: &str) -> Option<String> {
+    /*
+    Percent-decode a name or value of a query string or url encoded form.
+    A plus sign stands for a space, e.g. "a+b" becomes "a b".
+    Returns None if the decoded value is not valid UTF-8.
+     */
+    percent_decode(value, true)
+}
+
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    /*
+    Parse the name and value pairs of a query string, e.g. "x=1&y=a+b".
+    Names without a value get an empty value, undecodable pairs are skipped.
+     */
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Some((
+                decode_query_component(name)?,
+                decode_query_component(value)?,
+            ))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str, plus_as_space: bool) -> Option<String> {
+    /*
+    Decode percent escapes, keeping malformed escapes as they are.
+     */
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let digit = |offset: usize| bytes.get(i + offset).copied().and_then(hex_value);
+                if let (Some(high), Some(low)) = (digit(1), digit(2)) {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                decoded.push(b'%');
+            }
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    // Get the value of a hexadecimal digit.
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_uri() {
+        // Test splitting uris into path and query.
+        assert_eq!(split_uri("/a?x=1#top"), ("/a", Some("x=1")));
+        assert_eq!(split_uri("/a#top?x"), ("/a", None));
+        assert_eq!(split_uri("/a"), ("/a", None));
+    }
+
+    #[test]
+    fn test_plus_in_path_and_query() {
+        // Test that a plus is literal in paths but a space in queries.
+        let (path, query) = split_uri("/a+b%20c?x=a+b%2Bc");
+        assert_eq!(decode_path(path).unwrap(), "/a+b c");
+        assert_eq!(
+            parse_query(query.unwrap()),
+            vec![("x".to_string(), "a b+c".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        // Test decoding of valid, malformed and non UTF-8 escapes.
+        assert_eq!(decode_path("/caf%C3%A9").unwrap(), "/café");
+        assert_eq!(decode_path("/100%").unwrap(), "/100%");
+        assert_eq!(decode_path("/%zz%4").unwrap(), "/%zz%4");
+        assert_eq!(decode_path("/%FF"), None);
+        assert_eq!(
+            parse_query("a&b=&=c&&d=%41"),
+            vec![
+                ("a".to_string(), String::new()),
+                ("b".to_string(), String::new()),
+                (String::new(), "c".to_string()),
+                ("d".to_string(), "A".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,297 @@
+/*
+* Writing of responses to a client without holding file bodies in memory.
+*/
+
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use super::response::Response;
+
+// Size of the chunks a file body is read and written in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub struct UnreadableBody(pub io::Error);
+
+// UnreadableBody is the cause of a failed write when the file body could not be
+// read before any of the response was sent, so an error response can still be sent.
+impl fmt::Display for UnreadableBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "could not read the file body: {}", self.0)
+    }
+}
+
+impl Error for UnreadableBody {}
+
+pub fn is_unreadable_body(error: &io::Error) -> bool {
+    // Check whether a write failed before sending anything as the file body
+    // could not be read.
+    error
+        .get_ref()
+        .is_some_and(|cause| cause.is::<UnreadableBody>())
+}
+
+pub struct ResponseWriter<W: Write> {
+    // ResponseWriter writes responses to a client in chunks of bounded size.
+    writer: W,
+}
+
+impl<W: Write> ResponseWriter<W> {
+    pub fn new(writer: W) -> ResponseWriter<W> {
+        // Create a writer for responses to the given client.
+        ResponseWriter { writer }
+    }
+
+    pub fn write_response(&mut self, response: &Response) -> io::Result<()> {
+        // Write a response to the client.
+        // The headers are batched with the start of the body to save writes and
+        // file bodies are streamed one chunk at a time.
+        // Fails if a file body is shorter than announced, e.g. because the file
+        // was truncated, as the client can't tell where the response ends then.
+        // A file that can't be opened or read before the first chunk went out
+        // fails with an UnreadableBody, see is_unreadable_body.
+        let mut buffer = response.head();
+        let file = match &response.file {
+            Some(file) if response.status.allows_body() => file,
+            _ => {
+                buffer.extend_from_slice(&response.body);
+                self.write_all(&buffer)?;
+                return self.writer.flush();
+            }
+        };
+
+        let unreadable = |e: io::Error| io::Error::other(UnreadableBody(e));
+        let mut reader = File::open(&file.path).map_err(unreadable)?;
+        reader
+            .seek(SeekFrom::Start(file.start))
+            .map_err(unreadable)?;
+        let mut reader = reader.take(file.length);
+
+        let mut written = 0;
+        let mut sent = false;
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if !sent => return Err(unreadable(e)),
+                Err(e) => return Err(e),
+            };
+            buffer.extend_from_slice(&chunk[..read]);
+            written += read as u64;
+            if buffer.len() >= CHUNK_SIZE {
+                self.write_all(&buffer)?;
+                buffer.clear();
+                sent = true;
+            }
+        }
+        self.write_all(&buffer)?;
+        self.writer.flush()?;
+
+        if written < file.length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file is shorter than its announced length",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn write_head(&mut self, response: &Response) -> io::Result<()> {
+        // Write only the status line and headers of a response, e.g. for a HEAD request.
+        // The Content-Length still announces the length of the body.
+        self.write_all(&response.head())?;
+        self.writer.flush()
+    }
+
+    fn write_all(&mut self, mut buffer: &[u8]) -> io::Result<()> {
+        /*
+        Write all of a buffer to the client, one write call at a time.
+        A write may accept only part of the buffer, so the rest is written by
+        further calls, and calls interrupted by a signal are retried.
+        WouldBlock is not retried: the sockets of clients are blocking, so it
+        only occurs when the write timeout elapsed and the client stopped reading.
+         */
+        while !buffer.is_empty() {
+            match self.writer.write(buffer) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "client accepted no more bytes",
+                    ))
+                }
+                Ok(written) => buffer = &buffer[written..],
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        // Get a reference to the client the responses are written to.
+        &self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{response::FileBody, status::StatusCode};
+
+    struct RecordingWriter {
+        // RecordingWriter keeps what is written and the size of the largest write.
+        output: Vec<u8>,
+        largest_write: usize,
+    }
+
+    impl Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.largest_write = self.largest_write.max(buf.len());
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_file_body() {
+        // Test that a large file body is written in bounded chunks.
+        let path =
+            std::env::temp_dir().join(format!("rust-webserver-{}-large.bin", std::process::id()));
+        let contents: Vec<u8> = (0..1_000_000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        let response = Response::from_file_range(
+            StatusCode::PartialContent,
+            "application/octet-stream",
+            FileBody {
+                path: path.clone(),
+                start: 10,
+                length: 900_000,
+            },
+        );
+        let mut writer = ResponseWriter::new(RecordingWriter {
+            output: Vec::new(),
+            largest_write: 0,
+        });
+        writer.write_response(&response).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let recorded = writer.get_ref();
+        let head = response.head();
+        assert!(recorded.output.starts_with(&head));
+        assert!(String::from_utf8_lossy(&head).contains("\r\nContent-Length: 900000\r\n"));
+        assert_eq!(&recorded.output[head.len()..], &contents[10..900_010]);
+        assert!(recorded.largest_write < 2 * CHUNK_SIZE);
+    }
+
+    struct TrickleWriter {
+        // TrickleWriter accepts at most a few bytes per call and is interrupted
+        // on every other call.
+        output: Vec<u8>,
+        calls: usize,
+    }
+
+    impl Write for TrickleWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.calls.is_multiple_of(2) {
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            }
+            self.output.write(&buf[..buf.len().min(3)])
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_unreadable_file_body() {
+        // Test that nothing is written if the file body can't be opened.
+        let response = Response::from_file_range(
+            StatusCode::Ok,
+            "text/plain",
+            FileBody {
+                path: std::env::temp_dir().join("rust-webserver-missing-body"),
+                start: 0,
+                length: 10,
+            },
+        );
+        let mut writer = ResponseWriter::new(Vec::new());
+        let error = writer.write_response(&response).unwrap_err();
+        assert!(is_unreadable_body(&error));
+        assert!(writer.get_ref().is_empty());
+        assert!(!is_unreadable_body(&io::Error::from(
+            io::ErrorKind::BrokenPipe
+        )));
+    }
+
+    #[test]
+    fn test_write_partial_writes() {
+        // Test that partial and interrupted writes still deliver the full response.
+        let path =
+            std::env::temp_dir().join(format!("rust-webserver-{}-trickle.bin", std::process::id()));
+        let contents: Vec<u8> = (0..100_000).map(|i| (i % 241) as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        let response = Response::from_file_range(
+            StatusCode::Ok,
+            "application/octet-stream",
+            FileBody {
+                path: path.clone(),
+                start: 0,
+                length: contents.len() as u64,
+            },
+        );
+        let mut writer = ResponseWriter::new(TrickleWriter {
+            output: Vec::new(),
+            calls: 0,
+        });
+        writer.write_response(&response).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut expected = response.head();
+        expected.extend_from_slice(&contents);
+        assert_eq!(writer.get_ref().output, expected);
+
+        let response = Response::new(StatusCode::Ok, "text/plain", b"hello world".to_vec());
+        let mut writer = ResponseWriter::new(TrickleWriter {
+            output: Vec::new(),
+            calls: 0,
+        });
+        writer.write_response(&response).unwrap();
+        assert_eq!(writer.get_ref().output, response.to_bytes());
+    }
+
+    #[test]
+    fn test_write_truncated_file_body() {
+        // Test that a file shorter than announced fails the write.
+        let path =
+            std::env::temp_dir().join(format!("rust-webserver-{}-short.bin", std::process::id()));
+        std::fs::write(&path, b"short").unwrap();
+
+        let response = Response::from_file_range(
+            StatusCode::Ok,
+            "application/octet-stream",
+            FileBody {
+                path: path.clone(),
+                start: 0,
+                length: 100,
+            },
+        );
+        let mut writer = ResponseWriter::new(Vec::new());
+        let result = writer.write_response(&response);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -1,3 +1,12 @@
+pub mod clock;
+pub mod config;
+#[cfg(feature = "toml-config")]
+pub mod config_file;
+pub mod error;
+pub mod http;
+pub mod json;
+pub mod limit;
 pub mod logger;
 pub mod router;
+pub mod server;
 pub mod thread;
@@ -0,0 +1,248 @@
+/*
+* Limits on the connections a single client may hold open, on the
+* requests a route handles at once and on the memory requests buffer.
+*/
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+pub struct ConnectionLimits {
+    // ConnectionLimits counts the active connections of each client address.
+    limit: Option<usize>,
+    active: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConnectionLimits {
+    pub fn new(limit: Option<usize>) -> ConnectionLimits {
+        // Create limits allowing each address the given number of connections.
+        // Without a limit connections are still counted but never refused.
+        ConnectionLimits {
+            limit,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionGuard> {
+        /*
+        Count a new connection from an address.
+        Returns None if the address already holds the maximum number of
+        connections, otherwise a guard which releases the connection when dropped.
+         */
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(ip).or_insert(0);
+        if self.limit.is_some_and(|limit| *count >= limit) {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionGuard {
+            limits: Arc::clone(self),
+            ip,
+        })
+    }
+
+    pub fn active(&self, ip: IpAddr) -> usize {
+        // Get the number of active connections from an address.
+        self.active.lock().unwrap().get(&ip).copied().unwrap_or(0)
+    }
+}
+
+pub struct ConnectionGuard {
+    // ConnectionGuard holds a counted connection until it is dropped.
+    limits: Arc<ConnectionLimits>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        // Release the connection, forgetting addresses without connections
+        // so the map does not grow with every client ever seen.
+        let mut active = self.limits.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.ip);
+            }
+        }
+    }
+}
+
+pub struct ConcurrencyLimit {
+    // ConcurrencyLimit is a semaphore for the requests running at once.
+    limit: usize,
+    running: AtomicUsize,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(limit: usize) -> ConcurrencyLimit {
+        // Create a limit allowing the given number of requests at once.
+        ConcurrencyLimit {
+            limit,
+            running: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn try_acquire(self: &Arc<Self>) -> Option<ConcurrencyPermit> {
+        /*
+        Count a request as running.
+        Returns None if the limit is reached, otherwise a permit which ends
+        the request when dropped.
+         */
+        self.running
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+                (running < self.limit).then_some(running + 1)
+            })
+            .ok()
+            .map(|_| ConcurrencyPermit {
+                limit: Arc::clone(self),
+            })
+    }
+
+    pub fn running(&self) -> usize {
+        // Get the number of requests running.
+        self.running.load(Ordering::SeqCst)
+    }
+}
+
+pub struct ConcurrencyPermit {
+    // ConcurrencyPermit holds a running request until it is dropped.
+    limit: Arc<ConcurrencyLimit>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        self.limit.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct MemoryBudget {
+    // MemoryBudget counts the bytes buffered by all requests in flight.
+    limit: Option<usize>,
+    used: AtomicUsize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<usize>) -> MemoryBudget {
+        // Create a budget of the given number of bytes.
+        // Without a limit bytes are still counted but never refused.
+        MemoryBudget {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn reserve(&self) -> MemoryReservation<'_> {
+        // Create an empty reservation for a new request, which grows before
+        // each buffer of the request is allocated and releases them when dropped.
+        MemoryReservation {
+            budget: self,
+            bytes: 0,
+        }
+    }
+
+    pub fn try_reserve(&self, bytes: usize) -> Option<MemoryReservation<'_>> {
+        // Count bytes buffered by a new request.
+        // Returns None if they don't fit in the budget.
+        let mut reservation = self.reserve();
+        reservation.grow(bytes).then_some(reservation)
+    }
+
+    pub fn used(&self) -> usize {
+        // Get the number of bytes buffered by requests in flight.
+        self.used.load(Ordering::SeqCst)
+    }
+}
+
+pub struct MemoryReservation<'a> {
+    // MemoryReservation holds the bytes of a request until it is dropped.
+    budget: &'a MemoryBudget,
+    bytes: usize,
+}
+
+impl MemoryReservation<'_> {
+    pub fn grow(&mut self, bytes: usize) -> bool {
+        // Count more bytes, returning whether they fit in the budget.
+        // Nothing is counted if they don't.
+        let limit = self.budget.limit.unwrap_or(usize::MAX);
+        let grown = self
+            .budget
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|&used| used <= limit)
+            })
+            .is_ok();
+        if grown {
+            self.bytes += bytes;
+        }
+        grown
+    }
+}
+
+impl Drop for MemoryReservation<'_> {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_limits() {
+        // Test that an address is limited without affecting other addresses.
+        let limits = Arc::new(ConnectionLimits::new(Some(2)));
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+        let first = limits.acquire(client).unwrap();
+        let _second = limits.acquire(client).unwrap();
+        assert!(limits.acquire(client).is_none());
+        assert!(limits.acquire(other).is_some());
+        assert_eq!(limits.active(client), 2);
+
+        drop(first);
+        assert_eq!(limits.active(client), 1);
+        assert!(limits.acquire(client).is_some());
+        assert_eq!(limits.active(other), 0);
+        assert_eq!(limits.active.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_concurrency_limit() {
+        // Test that permits are refused at the limit until one is dropped.
+        let limit = Arc::new(ConcurrencyLimit::new(2));
+        let first = limit.try_acquire().unwrap();
+        let _second = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+        assert_eq!(limit.running(), 2);
+
+        drop(first);
+        assert_eq!(limit.running(), 1);
+        assert!(limit.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        // Test that bytes beyond the budget are refused until a reservation is dropped.
+        let budget = MemoryBudget::new(Some(100));
+        let mut first = budget.try_reserve(40).unwrap();
+        assert!(first.grow(20));
+        assert!(budget.try_reserve(50).is_none());
+        let second = budget.try_reserve(40).unwrap();
+        assert!(!first.grow(1));
+        assert_eq!(budget.used(), 100);
+
+        drop(first);
+        assert_eq!(budget.used(), 40);
+        assert!(budget.try_reserve(60).is_some());
+        drop(second);
+        assert_eq!(budget.used(), 0);
+        assert!(MemoryBudget::new(None).try_reserve(usize::MAX).is_some());
+    }
+}
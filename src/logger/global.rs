@@ -1,44 +1,140 @@
 use lazy_static::lazy_static;
-use std::sync::Mutex;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use super::log::Logger;
 
 // setup a global logger
 lazy_static! {
-    pub static ref LOGGER: Mutex<Logger> = Mutex::new(Logger::new());
+    pub static ref LOGGER: RwLock<Logger> = RwLock::new(Logger::new());
+}
+
+pub fn logger() -> RwLockReadGuard<'static, Logger> {
+    /*
+    Get the global logger for logging.
+    Logging only needs a shared lock, so threads format and send their lines
+    in parallel instead of waiting for each other on every log call.
+    A thread that panicked while logging poisons the lock, but the logger
+    holds no state a panic can break, so logging goes on instead of panicking.
+     */
+    LOGGER.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn logger_mut() -> RwLockWriteGuard<'static, Logger> {
+    /*
+    Get the global logger for configuring it, waiting for the log calls in progress.
+    Poisoning is ignored for the same reason as in logger.
+     */
+    LOGGER.write().unwrap_or_else(PoisonError::into_inner)
 }
 
 // define macros for logging
+// context fields go before the message, e.g. info!(method = "GET", path = "/"; "request")
 #[macro_export]
 macro_rules! info {
+    ($($key:ident = $value:expr),+ ; $($arg:tt)*) => {
+        $crate::logger::global::logger().log_fields(
+            $crate::logger::log::LogLevel::Info,
+            &format!($($arg)*),
+            &[$((stringify!($key), $value.to_string())),+],
+        );
+    };
     ($($arg:tt)*) => {
-        $crate::logger::global::LOGGER.lock().unwrap().info(&format!($($arg)*));
+        $crate::logger::global::logger().info(&format!($($arg)*));
     };
 }
 
 #[macro_export]
 macro_rules! warn {
+    ($($key:ident = $value:expr),+ ; $($arg:tt)*) => {
+        $crate::logger::global::logger().log_fields(
+            $crate::logger::log::LogLevel::Warning,
+            &format!($($arg)*),
+            &[$((stringify!($key), $value.to_string())),+],
+        );
+    };
     ($($arg:tt)*) => {
-        $crate::logger::global::LOGGER.lock().unwrap().warn(&format!($($arg)*));
+        $crate::logger::global::logger().warn(&format!($($arg)*));
     };
 }
 
 #[macro_export]
 macro_rules! error {
+    ($($key:ident = $value:expr),+ ; $($arg:tt)*) => {
+        $crate::logger::global::logger().log_fields(
+            $crate::logger::log::LogLevel::Error,
+            &format!($($arg)*),
+            &[$((stringify!($key), $value.to_string())),+],
+        );
+    };
     ($($arg:tt)*) => {
-        $crate::logger::global::LOGGER.lock().unwrap().error(&format!($($arg)*));
+        $crate::logger::global::logger().error(&format!($($arg)*));
     };
 }
 
 #[macro_export]
 macro_rules! debug {
+    ($($key:ident = $value:expr),+ ; $($arg:tt)*) => {
+        $crate::logger::global::logger().log_fields(
+            $crate::logger::log::LogLevel::Debug,
+            &format!($($arg)*),
+            &[$((stringify!($key), $value.to_string())),+],
+        );
+    };
     ($($arg:tt)*) => {
-        $crate::logger::global::LOGGER.lock().unwrap().debug(&format!($($arg)*));
+        $crate::logger::global::logger().debug(&format!($($arg)*));
     };
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::logger::log::capture_logs;
+
+    #[test]
+    fn test_capture_logs() {
+        // Test that the output of the global logger can be captured.
+        let output = capture_logs(|| {
+            info!("hello");
+            error!("failure");
+        });
+        assert!(output.contains("[INFO] hello\n"));
+        assert!(output.contains("[ERROR] failure\n"));
+
+        let output = capture_logs(|| {});
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn test_context_fields() {
+        // Test that context fields are appended to the message.
+        let path = "/index.html";
+        let output = capture_logs(|| {
+            info!(method = "GET", path = path, status = 200; "request {}", 1);
+            warn!(path = path; "slow");
+        });
+        assert!(output.contains("[INFO] request 1 method=GET path=/index.html status=200\n"));
+        assert!(output.contains("[WARNING] slow path=/index.html\n"));
+    }
+
+    #[test]
+    fn test_poisoned_logger() {
+        // Test that logging goes on after a thread panicked holding the logger.
+        let result = std::thread::spawn(|| {
+            let _logger = super::LOGGER.write().unwrap();
+            panic!("poison the logger");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(super::LOGGER.is_poisoned());
+
+        let output = capture_logs(|| {
+            info!("still logging");
+            warn!(path = "/"; "with fields");
+        });
+        assert!(output.contains("[INFO] still logging\n"));
+        assert!(output.contains("[WARNING] with fields path=/\n"));
+        super::LOGGER.clear_poison();
+    }
+
     #[test]
     fn test_logger() {
         debug!("debug message");
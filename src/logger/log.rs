@@ -1,43 +1,385 @@
+use std::cell::RefCell;
 use std::io::{self, Write};
-use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    mpsc, Arc, Mutex, PoisonError,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "syslog")]
+use super::syslog::Syslog;
+use crate::{
+    clock::{rfc3339, Clock, SystemClock, Timezone},
+    json::escape_json,
+};
+
+thread_local! {
+    // Buffer receiving the log lines of this thread while they are captured.
+    static CAPTURE: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+struct CaptureGuard(Option<Vec<u8>>);
+
+impl Drop for CaptureGuard {
+    fn drop(&mut self) {
+        // Restore the enclosing capture, even if the captured code panicked.
+        CAPTURE.with(|capture| *capture.borrow_mut() = self.0.take());
+    }
+}
+
+pub fn capture_logs<F: FnOnce()>(f: F) -> String {
+    /*
+    Capture the lines logged by the current thread while running f.
+    The captured lines are not written to the writers of the logger.
+    This is meant for tests asserting on log output, as the capture is per thread
+    and does not interfere with tests running in parallel.
+     */
+    let previous = CAPTURE.with(|capture| capture.borrow_mut().replace(Vec::new()));
+    let guard = CaptureGuard(previous);
+    f();
+    let output = CAPTURE.with(|capture| capture.borrow_mut().take().unwrap_or_default());
+    drop(guard);
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+type Output = Arc<Mutex<dyn Write + Send>>;
 
 pub struct Logger {
-    stdout: Arc<Mutex<dyn Write + Send>>,
-    stderr: Arc<Mutex<dyn Write + Send>>,
+    stdout: Output,
+    stderr: Output,
+    clock: Arc<dyn Clock>,
+    format: LogFormat,
+    timezone: Timezone,
+    // Syslog daemon receiving the log lines instead of stdout and stderr.
+    #[cfg(feature = "syslog")]
+    syslog: Option<Syslog>,
+    // Thread writing the log lines, so logging threads only format them.
+    background: Option<Background>,
+    flush_policy: FlushPolicy,
+    // When the outputs were last flushed by the interval policy.
+    last_flush: Mutex<Instant>,
+    // Lowest level of the lines written, lines below it are dropped.
+    level: LogLevel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FlushPolicy {
+    // Leave flushing to the writers, e.g. stdout flushes every line.
+    #[default]
+    Writer,
+    // Flush after every line, so no line is lost if the process crashes.
+    EveryLine,
+    // Flush after a line once the interval has passed since the last flush.
+    Interval(Duration),
+    // Flush after warnings and errors only.
+    Warnings,
+}
+
+impl FlushPolicy {
+    pub fn parse(value: &str) -> FlushPolicy {
+        // Parse "always", "warnings" or an interval in milliseconds,
+        // falling back to leaving flushing to the writers.
+        match value.trim().to_ascii_lowercase().as_str() {
+            "always" => FlushPolicy::EveryLine,
+            "warnings" => FlushPolicy::Warnings,
+            value => match value.parse() {
+                Ok(0) | Err(_) => FlushPolicy::Writer,
+                Ok(ms) => FlushPolicy::Interval(Duration::from_millis(ms)),
+            },
+        }
+    }
+}
+
+enum Record {
+    // A formatted line, the output it is written to and whether to flush it.
+    Line(Output, String, bool),
+    // A request to acknowledge once the lines sent before it are written.
+    Flush(mpsc::Sender<()>),
+}
+
+struct Background {
+    // Background writes the records it receives until its sender is dropped.
+    sender: Option<mpsc::Sender<Record>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Background {
+    fn start() -> Background {
+        // Start the thread writing the records.
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            for record in receiver {
+                match record {
+                    Record::Line(output, line, flush) => write_line(&output, &line, flush),
+                    Record::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        Background {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    fn send(&self, record: Record) -> bool {
+        // Hand a record to the thread, returning false if it is gone.
+        self.sender
+            .as_ref()
+            .is_some_and(|sender| sender.send(record).is_ok())
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        // Write the pending records before the thread stops, so none are lost.
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogFormat {
+    // Lines like "[2023-11-14T22:13:20Z] [INFO] request method=GET".
+    #[default]
+    Text,
+    // One JSON object per line with the context fields as members.
+    Json,
+    // Lines of key=value pairs like "ts=2023-11-14T22:13:20Z level=info msg=request method=GET".
+    Logfmt,
+}
+
+impl LogFormat {
+    pub fn parse(value: &str) -> LogFormat {
+        // Parse a log format name, falling back to text for unknown names.
+        match value.trim().to_ascii_lowercase().as_str() {
+            "json" => LogFormat::Json,
+            "logfmt" => LogFormat::Logfmt,
+            _ => LogFormat::Text,
+        }
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
 pub enum LogLevel {
+    #[default]
     Debug,
     Info,
     Warning,
     Error,
 }
 
+impl LogLevel {
+    pub fn parse(value: &str) -> LogLevel {
+        // Parse a level name, falling back to debug so unknown names hide no lines.
+        match value.trim().to_ascii_lowercase().as_str() {
+            "info" => LogLevel::Info,
+            "warn" | "warning" => LogLevel::Warning,
+            "error" => LogLevel::Error,
+            _ => LogLevel::Debug,
+        }
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Logger {
     pub fn new() -> Self {
         Logger {
             stdout: Arc::new(Mutex::new(io::stdout())),
             stderr: Arc::new(Mutex::new(io::stderr())),
+            clock: Arc::new(SystemClock),
+            format: LogFormat::Text,
+            timezone: Timezone::Utc,
+            #[cfg(feature = "syslog")]
+            syslog: None,
+            background: None,
+            flush_policy: FlushPolicy::Writer,
+            last_flush: Mutex::new(Instant::now()),
+            level: LogLevel::Debug,
+        }
+    }
+
+    pub fn set_writers(
+        &mut self,
+        stdout: Arc<Mutex<dyn Write + Send>>,
+        stderr: Arc<Mutex<dyn Write + Send>>,
+    ) {
+        self.stdout = stdout;
+        self.stderr = stderr;
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn set_format(&mut self, format: LogFormat) {
+        self.format = format;
+    }
+
+    pub fn set_timezone(&mut self, timezone: Timezone) {
+        self.timezone = timezone;
+    }
+
+    #[cfg(feature = "syslog")]
+    pub fn set_syslog(&mut self, syslog: Option<Syslog>) {
+        // Send log lines to a syslog daemon instead of stdout and stderr.
+        // The daemon timestamps the lines itself, so they are sent without a timestamp.
+        self.syslog = syslog;
+    }
+
+    pub fn set_background(&mut self, enabled: bool) {
+        // Write log lines on a background thread instead of the logging thread.
+        // Disabling it writes the pending lines before returning.
+        match (enabled, self.background.is_some()) {
+            (true, false) => self.background = Some(Background::start()),
+            (false, true) => self.background = None,
+            _ => (),
+        }
+    }
+
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    pub fn set_level(&mut self, level: LogLevel) {
+        self.level = level;
+    }
+
+    pub fn flush(&self) {
+        /*
+        Wait until every line logged so far is written and flush the outputs.
+        Call this before the process exits when logging in the background.
+         */
+        if let Some(background) = &self.background {
+            let (done, written) = mpsc::channel();
+            if background.send(Record::Flush(done)) {
+                let _ = written.recv();
+            }
+        }
+        for output in [&self.stdout, &self.stderr] {
+            if let Err(err) = output
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .flush()
+            {
+                eprintln!("Failed to flush output: {}", err);
+            }
         }
     }
 
     pub fn log(&self, level: LogLevel, message: &str) {
-        let (level_str, mut output) = match level {
-            LogLevel::Debug => ("DEBUG", self.stdout.lock().unwrap()),
-            LogLevel::Info => ("INFO", self.stdout.lock().unwrap()),
-            LogLevel::Warning => ("WARNING", self.stdout.lock().unwrap()),
-            LogLevel::Error => ("ERROR", self.stderr.lock().unwrap()),
+        self.log_fields(level, message, &[]);
+    }
+
+    pub fn log_fields(&self, level: LogLevel, message: &str, fields: &[(&str, String)]) {
+        if level < self.level {
+            return;
+        }
+        let flush = self.should_flush(&level);
+        let (level_str, output) = match level {
+            LogLevel::Debug => ("DEBUG", &self.stdout),
+            LogLevel::Info => ("INFO", &self.stdout),
+            LogLevel::Warning => ("WARNING", &self.stdout),
+            LogLevel::Error => ("ERROR", &self.stderr),
         };
 
-        let timestamp = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(duration) => duration.as_secs(),
-            Err(_) => 0,
+        let now = self.clock.now();
+        let timestamp = rfc3339(now, self.timezone.offset_at(now));
+        let mut text = message.to_string();
+        for (key, value) in fields {
+            text.push_str(&format!(" {}={}", key, quote_field(value)));
+        }
+        let line = match self.format {
+            LogFormat::Text => format!("[{}] [{}] {}\n", timestamp, level_str, text),
+            LogFormat::Json => {
+                let mut line = format!(
+                    "{{\"timestamp\":\"{}\",\"level\":\"{}\",\"message\":\"{}\"",
+                    timestamp,
+                    level_str,
+                    escape_json(message)
+                );
+                for (key, value) in fields {
+                    line.push_str(&format!(
+                        ",\"{}\":\"{}\"",
+                        escape_json(key),
+                        escape_json(value)
+                    ));
+                }
+                line + "}\n"
+            }
+            LogFormat::Logfmt => {
+                let mut line = format!(
+                    "ts={} level={} msg={}",
+                    timestamp,
+                    level_str.to_ascii_lowercase(),
+                    quote_field(message)
+                );
+                for (key, value) in fields {
+                    line.push_str(&format!(" {}={}", key, quote_field(value)));
+                }
+                line + "\n"
+            }
         };
 
-        match writeln!(output, "[{}] [{}] {}", timestamp, level_str, message) {
-            Ok(_) => (),
-            Err(err) => eprintln!("Failed to write to output: {}", err),
+        let captured = CAPTURE.with(|capture| match capture.borrow_mut().as_mut() {
+            Some(buffer) => {
+                buffer.extend_from_slice(line.as_bytes());
+                true
+            }
+            None => false,
+        });
+        if captured {
+            return;
+        }
+
+        #[cfg(feature = "syslog")]
+        if let Some(syslog) = &self.syslog {
+            let message = match self.format {
+                LogFormat::Text => text.as_str(),
+                LogFormat::Json | LogFormat::Logfmt => line.trim_end(),
+            };
+            if let Err(err) = syslog.send(&level, message) {
+                eprintln!("Failed to write to syslog: {}", err);
+            }
+            return;
+        }
+
+        match &self.background {
+            Some(background) => {
+                if !background.send(Record::Line(Arc::clone(output), line, flush)) {
+                    eprintln!("Failed to hand a log line to the logging thread.");
+                }
+            }
+            None => write_line(output, &line, flush),
+        }
+    }
+
+    fn should_flush(&self, level: &LogLevel) -> bool {
+        // Check whether a line of the level is flushed under the flush policy.
+        match self.flush_policy {
+            FlushPolicy::Writer => false,
+            FlushPolicy::EveryLine => true,
+            FlushPolicy::Warnings => matches!(level, LogLevel::Warning | LogLevel::Error),
+            FlushPolicy::Interval(interval) => {
+                let mut last_flush = self
+                    .last_flush
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                if last_flush.elapsed() < interval {
+                    return false;
+                }
+                *last_flush = Instant::now();
+                true
+            }
         }
     }
     pub fn info(&self, message: &str) {
@@ -57,9 +399,57 @@ impl Logger {
     }
 }
 
+pub struct LogSampler {
+    // LogSampler picks one in every n successful requests for the access log.
+    every: u64,
+    seen: AtomicU64,
+}
+
+impl LogSampler {
+    pub fn new(every: u64) -> LogSampler {
+        // Create a sampler logging one in every n successful requests,
+        // every request if n is 0 or 1.
+        LogSampler {
+            every: every.max(1),
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    pub fn sample(&self, success: bool) -> bool {
+        // Decide whether a request is logged, requests which did not succeed always are.
+        !success
+            || self
+                .seen
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.every)
+    }
+}
+
+fn write_line(output: &Output, line: &str, flush: bool) {
+    // Write a formatted line to an output, flushing it if asked to.
+    let mut output = output.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = match output.write_all(line.as_bytes()) {
+        Ok(()) if flush => output.flush(),
+        result => result,
+    };
+    if let Err(err) = result {
+        eprintln!("Failed to write to output: {}", err);
+    }
+}
+
+fn quote_field(value: &str) -> String {
+    // Quote a text field value if it would otherwise be ambiguous.
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::SystemTime;
 
     #[test]
     fn test_logger() {
@@ -69,4 +459,222 @@ mod tests {
         logger.warn("warn message");
         logger.error("error message");
     }
+
+    #[test]
+    fn test_logger_fixed_clock() {
+        use crate::clock::FixedClock;
+        use std::time::Duration;
+
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let stderr = Arc::new(Mutex::new(Vec::new()));
+        let mut logger = Logger::new();
+        logger.set_writers(stdout.clone(), stderr.clone());
+        logger.set_clock(Arc::new(FixedClock(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000),
+        )));
+
+        logger.info("info message");
+        logger.error("error message");
+
+        assert_eq!(
+            String::from_utf8(stdout.lock().unwrap().clone()).unwrap(),
+            "[2023-11-14T22:13:20Z] [INFO] info message\n"
+        );
+        assert_eq!(
+            String::from_utf8(stderr.lock().unwrap().clone()).unwrap(),
+            "[2023-11-14T22:13:20Z] [ERROR] error message\n"
+        );
+    }
+
+    #[test]
+    fn test_logger_fields() {
+        use crate::clock::FixedClock;
+        use std::time::Duration;
+
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let mut logger = Logger::new();
+        logger.set_writers(stdout.clone(), Arc::new(Mutex::new(Vec::new())));
+        logger.set_clock(Arc::new(FixedClock(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000),
+        )));
+        let fields = [
+            ("method", "GET".to_string()),
+            ("agent", "curl \"8\"".to_string()),
+        ];
+
+        logger.log_fields(LogLevel::Info, "request", &fields);
+        logger.set_format(LogFormat::Json);
+        logger.log_fields(LogLevel::Warning, "request", &fields);
+
+        assert_eq!(
+            String::from_utf8(stdout.lock().unwrap().clone()).unwrap(),
+            "[2023-11-14T22:13:20Z] [INFO] request method=GET agent=\"curl \\\"8\\\"\"\n\
+             {\"timestamp\":\"2023-11-14T22:13:20Z\",\"level\":\"WARNING\",\"message\":\"request\",\
+             \"method\":\"GET\",\"agent\":\"curl \\\"8\\\"\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_logfmt() {
+        use crate::clock::FixedClock;
+        use std::time::Duration;
+
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let mut logger = Logger::new();
+        logger.set_writers(stdout.clone(), Arc::new(Mutex::new(Vec::new())));
+        logger.set_clock(Arc::new(FixedClock(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000),
+        )));
+        logger.set_format(LogFormat::parse("LOGFMT"));
+
+        logger.log_fields(
+            LogLevel::Warning,
+            "slow request",
+            &[
+                ("path", "/".to_string()),
+                ("agent", "curl \"8\"".to_string()),
+            ],
+        );
+        logger.info("done");
+
+        assert_eq!(
+            String::from_utf8(stdout.lock().unwrap().clone()).unwrap(),
+            "ts=2023-11-14T22:13:20Z level=warning msg=\"slow request\" path=/ agent=\"curl \\\"8\\\"\"\n\
+             ts=2023-11-14T22:13:20Z level=info msg=done\n"
+        );
+    }
+
+    #[test]
+    fn test_background_logging() {
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let mut logger = Logger::new();
+        logger.set_writers(stdout.clone(), Arc::new(Mutex::new(Vec::new())));
+        logger.set_background(true);
+        let logger = Arc::new(Mutex::new(logger));
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let logger = Arc::clone(&logger);
+                thread::spawn(move || {
+                    for j in 0..50 {
+                        logger.lock().unwrap().info(&format!("message {}-{}", i, j));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        logger.lock().unwrap().flush();
+
+        let output = String::from_utf8(stdout.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 400);
+        for i in 0..8 {
+            for j in 0..50 {
+                assert!(output.contains(&format!("[INFO] message {}-{}\n", i, j)));
+            }
+        }
+
+        // lines still pending when the logger is dropped are written
+        logger.lock().unwrap().info("last message");
+        drop(logger);
+        let output = String::from_utf8(stdout.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with("[INFO] last message\n"));
+    }
+
+    #[test]
+    fn test_flush_policy() {
+        // a buffered writer keeps the lines until it is flushed
+        #[derive(Clone)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let written = |policy: FlushPolicy, log: &dyn Fn(&Logger)| {
+            let shared = Shared(Arc::new(Mutex::new(Vec::new())));
+            let buffered = Arc::new(Mutex::new(io::BufWriter::new(shared.clone())));
+            let mut logger = Logger::new();
+            logger.set_writers(buffered.clone(), buffered.clone());
+            logger.set_flush_policy(policy);
+            log(&logger);
+            // simulate a crash, nothing is flushed on the way out
+            std::mem::forget(logger);
+            std::mem::forget(buffered);
+            let output = shared.0.lock().unwrap().clone();
+            String::from_utf8(output).unwrap()
+        };
+
+        let output = written(FlushPolicy::EveryLine, &|logger| logger.info("saved"));
+        assert!(output.ends_with("[INFO] saved\n"));
+        let output = written(FlushPolicy::Writer, &|logger| logger.info("lost"));
+        assert_eq!(output, "");
+
+        let output = written(FlushPolicy::Warnings, &|logger| {
+            logger.info("first");
+            logger.warn("second");
+            logger.info("third");
+        });
+        assert!(output.contains("[INFO] first\n"));
+        assert!(output.ends_with("[WARNING] second\n"));
+
+        assert_eq!(FlushPolicy::parse("always"), FlushPolicy::EveryLine);
+        assert_eq!(
+            FlushPolicy::parse("500"),
+            FlushPolicy::Interval(Duration::from_millis(500))
+        );
+        assert_eq!(FlushPolicy::parse(""), FlushPolicy::Writer);
+    }
+
+    #[test]
+    fn test_log_level() {
+        // Test that lines below the level of the logger are dropped.
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let stderr = Arc::new(Mutex::new(Vec::new()));
+        let mut logger = Logger::new();
+        logger.set_writers(stdout.clone(), stderr.clone());
+        logger.set_level(LogLevel::parse("WARN"));
+
+        logger.debug("debug message");
+        logger.info("info message");
+        logger.warn("warn message");
+        logger.error("error message");
+
+        let output = String::from_utf8(stdout.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.ends_with("[WARNING] warn message\n"));
+        let output = String::from_utf8(stderr.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with("[ERROR] error message\n"));
+
+        assert_eq!(LogLevel::parse("info"), LogLevel::Info);
+        assert_eq!(LogLevel::parse("error"), LogLevel::Error);
+        assert_eq!(LogLevel::parse("verbose"), LogLevel::Debug);
+    }
+
+    #[test]
+    fn test_logger_timezone() {
+        use crate::clock::FixedClock;
+        use std::time::Duration;
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000);
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let mut logger = Logger::new();
+        logger.set_writers(stdout.clone(), Arc::new(Mutex::new(Vec::new())));
+        logger.set_clock(Arc::new(FixedClock(time)));
+        logger.set_timezone(Timezone::Local);
+
+        logger.info("info message");
+
+        assert_eq!(
+            String::from_utf8(stdout.lock().unwrap().clone()).unwrap(),
+            format!(
+                "[{}] [INFO] info message\n",
+                rfc3339(time, Timezone::Local.offset_at(time))
+            )
+        );
+    }
 }
@@ -1,2 +1,4 @@
 pub mod global;
-pub mod log;
\ No newline at end of file
+pub mod log;
+#[cfg(feature = "syslog")]
+pub mod syslog;
@@ -0,0 +1,148 @@
+/*
+* Sending of log lines to a syslog daemon over UDP or the local socket.
+*/
+
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+use super::log::LogLevel;
+
+// Name the messages of the webserver are tagged with.
+const TAG: &str = "rust-webserver";
+
+enum Transport {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+pub struct Syslog {
+    // Syslog sends messages in the BSD syslog format, e.g. "<11>rust-webserver[42]: failed".
+    transport: Transport,
+    facility: u8,
+}
+
+impl Syslog {
+    pub fn connect(target: &str, facility: u8) -> io::Result<Syslog> {
+        /*
+        Connect to a syslog daemon, e.g. "udp://127.0.0.1:514" or "unix:///dev/log".
+        "local" connects to the local socket at /dev/log.
+         */
+        let transport = if let Some(address) = target.strip_prefix("udp://") {
+            let address = address
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))?;
+            let bind = if address.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(bind)?;
+            socket.connect(address)?;
+            Transport::Udp(socket)
+        } else {
+            #[cfg(unix)]
+            {
+                let path = match target {
+                    "local" => "/dev/log",
+                    target => target.strip_prefix("unix://").unwrap_or(target),
+                };
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Transport::Unix(socket)
+            }
+            #[cfg(not(unix))]
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only udp:// syslog targets are supported on this platform",
+            ));
+        };
+        Ok(Syslog {
+            transport,
+            facility,
+        })
+    }
+
+    pub fn send(&self, level: &LogLevel, message: &str) -> io::Result<()> {
+        // Send a message with the priority of its level.
+        let priority = u16::from(self.facility) * 8 + u16::from(severity(level));
+        let datagram = format!("<{}>{}[{}]: {}", priority, TAG, std::process::id(), message);
+        match &self.transport {
+            Transport::Udp(socket) => socket.send(datagram.as_bytes()).map(|_| ()),
+            #[cfg(unix)]
+            Transport::Unix(socket) => socket.send(datagram.as_bytes()).map(|_| ()),
+        }
+    }
+}
+
+pub fn severity(level: &LogLevel) -> u8 {
+    // Get the syslog severity of a log level.
+    match level {
+        LogLevel::Debug => 7,
+        LogLevel::Info => 6,
+        LogLevel::Warning => 4,
+        LogLevel::Error => 3,
+    }
+}
+
+pub fn parse_facility(name: &str) -> u8 {
+    /*
+    Parse a syslog facility name, e.g. "daemon" or "local3".
+    Unknown names fall back to the user facility.
+     */
+    match name.trim().to_ascii_lowercase().as_str() {
+        "kern" => 0,
+        "daemon" => 3,
+        "auth" => 4,
+        "local0" => 16,
+        "local1" => 17,
+        "local2" => 18,
+        "local3" => 19,
+        "local4" => 20,
+        "local5" => 21,
+        "local6" => 22,
+        "local7" => 23,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error, logger::global::logger_mut};
+    use std::time::Duration;
+
+    #[test]
+    fn test_syslog_error() {
+        // Test that an error is sent to syslog with the error severity.
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let target = format!("udp://{}", listener.local_addr().unwrap());
+        let syslog = Syslog::connect(&target, parse_facility("daemon")).unwrap();
+
+        logger_mut().set_syslog(Some(syslog));
+        error!("syslog test {}", std::process::id());
+        logger_mut().set_syslog(None);
+
+        // other tests may log while the syslog is set, so look for this message
+        let expected = format!(
+            "<27>rust-webserver[{0}]: syslog test {0}",
+            std::process::id()
+        );
+        let mut buf = [0; 1024];
+        loop {
+            let read = listener.recv(&mut buf).unwrap();
+            if String::from_utf8_lossy(&buf[..read]) == expected {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+/*
+* Middleware which is run on a request before it is routed or on its response afterwards.
+*/
+
+use crate::http::{request::Request, response::Response, url::parse_query};
+
+// A middleware may modify the request before it is routed.
+// Returning a response stops processing and sends the response to the client.
+pub type Middleware = fn(&mut Request) -> Option<Response>;
+
+// A response middleware may modify the response to a request before it is written,
+// e.g. to add headers to every response.
+pub type ResponseMiddleware = fn(&Request, &mut Response);
+
+const OVERRIDABLE_METHODS: [&str; 3] = ["PUT", "PATCH", "DELETE"];
+
+pub fn method_override(request: &mut Request) -> Option<Response> {
+    /*
+    Rewrite the method of a POST request from the X-HTTP-Method-Override header
+    or the _method field of a url encoded form.
+    Only POST requests may be overridden and only to PUT, PATCH or DELETE.
+     */
+    if request.method != "POST" {
+        return None;
+    }
+
+    let method = match request.header("X-HTTP-Method-Override") {
+        Some(method) => Some(method.to_string()),
+        None => form_method(request),
+    };
+
+    if let Some(method) = method {
+        let method = method.trim().to_ascii_uppercase();
+        if OVERRIDABLE_METHODS.contains(&method.as_str()) {
+            request.method = method;
+        }
+    }
+    None
+}
+
+fn form_method(request: &Request) -> Option<String> {
+    /*
+    Get the _method field from a url encoded form body.
+     */
+    let content_type = request.header("Content-Type")?;
+    if !content_type
+        .trim()
+        .starts_with("application/x-www-form-urlencoded")
+    {
+        return None;
+    }
+
+    let body = std::str::from_utf8(&request.body).ok()?;
+    parse_query(body)
+        .into_iter()
+        .find(|(key, _)| key == "_method")
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_request(method: &str, headers: &[(&str, &str)], body: &str) -> Request {
+        // Create a request with the given method, headers and body.
+        Request {
+            method: method.to_string(),
+            uri: "/items".to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: body.as_bytes().to_vec(),
+            extensions: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_method_override_header() {
+        // Test overriding the method with the header.
+        let mut request = make_request("POST", &[("X-HTTP-Method-Override", "delete")], "");
+        assert!(method_override(&mut request).is_none());
+        assert_eq!(request.method, "DELETE");
+    }
+
+    #[test]
+    fn test_method_override_form() {
+        // Test overriding the method with a form field.
+        let mut request = make_request(
+            "POST",
+            &[("Content-Type", "application/x-www-form-urlencoded")],
+            "name=x&_method=PUT",
+        );
+        method_override(&mut request);
+        assert_eq!(request.method, "PUT");
+    }
+
+    #[test]
+    fn test_method_override_restricted() {
+        // Test that only POST requests are overridden and only to allowed methods.
+        let mut request = make_request("GET", &[("X-HTTP-Method-Override", "DELETE")], "");
+        method_override(&mut request);
+        assert_eq!(request.method, "GET");
+
+        let mut request = make_request("POST", &[("X-HTTP-Method-Override", "CONNECT")], "");
+        method_override(&mut request);
+        assert_eq!(request.method, "POST");
+    }
+}
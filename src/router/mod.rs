@@ -1 +1,3 @@
+pub mod middleware;
+#[allow(clippy::module_inception)]
 pub mod router;
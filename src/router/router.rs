@@ -2,19 +2,155 @@
 * A simple http router on which routes can be configured.
 */
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use super::middleware::{Middleware, ResponseMiddleware};
+use crate::{
+    error::Error,
+    http::{
+        file::{escapes_root, resolve_path},
+        request::Request,
+        response::{PrecomputedResponse, Response},
+        status::StatusCode,
+    },
+    json::escape_json,
+    limit::ConcurrencyLimit,
+};
 
 struct Route<'a> {
     // Route is a simple container for a route.
     method: &'a str,
-    handler: fn() -> Option<String>,
+    handler: Handler,
+    // Media type the body of requests must have, e.g. application/json.
+    content_type: Option<&'a str>,
+    // Values substituted into the placeholders of the served file.
+    template_context: Option<TemplateContext>,
+    // Requests the route handles at once, others are answered with 503.
+    concurrency: Option<Arc<ConcurrencyLimit>>,
+    // Whether the route shares the workers left to low priority requests.
+    priority: Priority,
+    // Whether uploaded files of multipart bodies are streamed to disk.
+    stream_uploads: bool,
+}
+
+// A handler result is the response to send or the status whose error page is sent instead.
+pub type HandlerResult = Result<Response, StatusCode>;
+
+// Handlers are shared closures, so they can capture state such as a counter
+// or a connection pool. Plain functions work as well.
+pub type FileHandler = Arc<dyn Fn() -> Option<String> + Send + Sync>;
+pub type ResponseHandler = Arc<dyn Fn() -> HandlerResult + Send + Sync>;
+pub type BytesHandler = Arc<dyn Fn() -> Option<(&'static str, Vec<u8>)> + Send + Sync>;
+pub type RequestHandler = Arc<dyn Fn(&Request) -> HandlerResult + Send + Sync>;
+
+#[derive(Clone)]
+pub enum Handler {
+    // Handler names the file of the resources directory to serve, if any.
+    File(FileHandler),
+    // Handler answers with a response of its own or an error status, e.g. a
+    // redirect or 403 Forbidden.
+    Response(ResponseHandler),
+    // Handler computes the body with its content type, if any.
+    Bytes(BytesHandler),
+    // Handler answers like a response handler, reading the request, e.g. its
+    // parameters or deadline.
+    Request(RequestHandler),
+}
+
+// A template context provides the values for the {{key}} placeholders of a served file.
+pub type TemplateContext = fn() -> HashMap<String, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Priority {
+    // Requests may use any worker.
+    #[default]
+    Normal,
+    // Requests, e.g. to slow reports, may only use the workers not reserved
+    // for normal requests, so those are not held up behind them.
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteListFormat {
+    // RouteListFormat selects how the route listing endpoint renders its body.
+    Text,
+    Json,
+}
+
+impl RouteListFormat {
+    pub fn content_type(&self) -> &'static str {
+        // Get the content type of a rendered route listing.
+        match self {
+            RouteListFormat::Text => "text/plain; charset=UTF-8",
+            RouteListFormat::Json => "application/json",
+        }
+    }
+}
+
+struct MiddlewareEntry {
+    // MiddlewareEntry is a middleware together with its name and the path prefix
+    // of the requests it runs for.
+    // Middleware added directly to a router uses an empty prefix and
+    // unnamed middleware an empty name.
+    name: String,
+    prefix: String,
+    middleware: Middleware,
 }
 
 pub struct Router<'a> {
     // Router is a simple router that holds a map of routes.
-    // A route is identified by its path.
-    // The hashmap is used to store the path and respective route.
-    routes: HashMap<String, Route<'a>>,
+    // A route is identified by its path and method.
+    // The hashmap is used to store the path and the routes for each method.
+    // Routes scoped to a host are stored under the host followed by the path,
+    // which can't collide with plain paths as those start with a slash.
+    routes: HashMap<String, Vec<Route<'a>>>,
+    // Optional path on which the registered routes are listed.
+    routes_endpoint: Option<(&'a str, RouteListFormat)>,
+    // Middleware run before routing, in the order it was added.
+    middleware: Vec<MiddlewareEntry>,
+    // Middleware run on responses by path prefix, in the order it was added.
+    response_middleware: Vec<(String, ResponseMiddleware)>,
+    // Directories whose files are served below a path prefix.
+    static_dirs: Vec<(String, PathBuf)>,
+    // Hosts with routes of their own, lookups skip building host keys if there are none.
+    hosts: HashSet<String>,
+    // Responses serialized once and written as is, by path and method.
+    precomputed: HashMap<String, Vec<(&'a str, PrecomputedResponse)>>,
+    // Low priority requests handled at once, shared by all low priority routes.
+    low_priority: Option<Arc<ConcurrencyLimit>>,
+}
+
+impl Handler {
+    fn file(&self) -> Option<&FileHandler> {
+        // Get the handler if it names a file to serve.
+        match self {
+            Handler::File(handler) => Some(handler),
+            Handler::Response(_) | Handler::Bytes(_) | Handler::Request(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for Handler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Closures can't be printed, so only the kind of the handler is.
+        match self {
+            Handler::File(_) => write!(f, "Handler::File"),
+            Handler::Response(_) => write!(f, "Handler::Response"),
+            Handler::Bytes(_) => write!(f, "Handler::Bytes"),
+            Handler::Request(_) => write!(f, "Handler::Request"),
+        }
+    }
+}
+
+impl<'a> Default for Router<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> Router<'a> {
@@ -23,35 +159,691 @@ impl<'a> Router<'a> {
         // Create a new router.
         Router {
             routes: HashMap::new(),
+            routes_endpoint: None,
+            middleware: Vec::new(),
+            response_middleware: Vec::new(),
+            static_dirs: Vec::new(),
+            hosts: HashSet::new(),
+            precomputed: HashMap::new(),
+            low_priority: None,
         }
     }
 
-    pub fn add_route(&mut self, method: &'a str, path: &'a str, handler: fn() -> Option<String>) {
+    pub fn add_route<F>(&mut self, method: &'a str, path: &'a str, handler: F)
+    where
+        F: Fn() -> Option<String> + Send + Sync + 'static,
+    {
         // Add a route to the router.
-        // The route is identified by its path.
-        // The handler is a function that is called when the route is matched.
+        // The route is identified by its path and method.
+        // The handler is a function or closure that is called when the route is matched.
+        // Adding a route for an existing path and method replaces the handler.
+        self.insert_route(path.to_string(), method, Handler::File(Arc::new(handler)));
+    }
+
+    pub fn add_response_route<F>(&mut self, method: &'a str, path: &'a str, handler: F)
+    where
+        F: Fn() -> HandlerResult + Send + Sync + 'static,
+    {
+        // Add a route whose handler produces the response itself.
+        // A handler returning an error status is answered with the page of the status.
+        self.insert_route(
+            path.to_string(),
+            method,
+            Handler::Response(Arc::new(handler)),
+        );
+    }
+
+    pub fn add_bytes_route<F>(&mut self, method: &'a str, path: &'a str, handler: F)
+    where
+        F: Fn() -> Option<(&'static str, Vec<u8>)> + Send + Sync + 'static,
+    {
+        // Add a route whose handler computes the body, e.g. an image, and names
+        // its content type. A handler returning None is answered like one
+        // without a file to serve.
+        self.insert_route(path.to_string(), method, Handler::Bytes(Arc::new(handler)));
+    }
+
+    pub fn add_request_route<F>(&mut self, method: &'a str, path: &'a str, handler: F)
+    where
+        F: Fn(&Request) -> HandlerResult + Send + Sync + 'static,
+    {
+        // Add a route whose handler reads the request to produce the response.
+        // The handler gets the request after middleware ran on it.
+        self.insert_route(
+            path.to_string(),
+            method,
+            Handler::Request(Arc::new(handler)),
+        );
+    }
+
+    fn insert_route(&mut self, key: String, method: &'a str, handler: Handler) {
+        // Insert a route under the given key, replacing the handler of an existing route.
+        if let Some(index) = key.find('/').filter(|index| *index > 0) {
+            self.hosts.insert(key[..index].to_string());
+        }
+        let routes = self.routes.entry(key).or_default();
+        match routes.iter_mut().find(|route| route.method == method) {
+            Some(route) => route.handler = handler,
+            None => routes.push(Route {
+                method,
+                handler,
+                content_type: None,
+                template_context: None,
+                concurrency: None,
+                priority: Priority::Normal,
+                stream_uploads: false,
+            }),
+        }
+    }
+
+    fn find_route(&self, method: &str, key: &str) -> Option<&Route<'a>> {
+        // Find the route for a method under the given key.
         self.routes
-            .insert(path.to_string(), Route { method, handler });
+            .get(key)
+            .and_then(|routes| routes.iter().find(|route| route.method == method))
+    }
+
+    fn find_route_mut(&mut self, method: &str, key: &str) -> Option<&mut Route<'a>> {
+        // Find the route for a method under the given key to change it.
+        self.routes
+            .get_mut(key)
+            .and_then(|routes| routes.iter_mut().find(|route| route.method == method))
+    }
+
+    fn find_route_for_host(
+        &self,
+        host: Option<&str>,
+        method: &str,
+        path: &str,
+    ) -> Option<&Route<'a>> {
+        // Find the route a request to the given host is routed to.
+        // Routes scoped to the host take precedence over host-agnostic routes.
+        let host_route = host
+            .filter(|_| !self.hosts.is_empty())
+            .and_then(|host| self.find_route(method, &host_key(host, path)));
+        host_route.or_else(|| self.find_route(method, path))
+    }
+
+    pub fn require_content_type(&mut self, method: &str, path: &str, content_type: &'a str) {
+        // Only accept requests to a route whose body has the given media type,
+        // e.g. application/json, others are answered with 415.
+        // Has no effect if no route for the path and method was added.
+        if let Some(route) = self.find_route_mut(method, path) {
+            route.content_type = Some(content_type);
+        }
+    }
+
+    pub fn get_required_content_type(
+        &self,
+        host: Option<&str>,
+        method: &str,
+        path: &str,
+    ) -> Option<&'a str> {
+        // Get the media type required by the route a request is routed to, if any.
+        self.find_route_for_host(host, method, path)
+            .and_then(|route| route.content_type)
+    }
+
+    pub fn set_template_context(&mut self, method: &str, path: &str, context: TemplateContext) {
+        // Render the file served by a route as a template, replacing {{key}}
+        // placeholders with the values of the context.
+        // Has no effect if no route for the path and method was added.
+        if let Some(route) = self.find_route_mut(method, path) {
+            route.template_context = Some(context);
+        }
+    }
+
+    pub fn get_template_context(
+        &self,
+        host: Option<&str>,
+        method: &str,
+        path: &str,
+    ) -> Option<TemplateContext> {
+        // Get the template context of the route a request is routed to, if any.
+        self.find_route_for_host(host, method, path)
+            .and_then(|route| route.template_context)
+    }
+
+    pub fn limit_concurrency(&mut self, method: &str, path: &str, limit: usize) {
+        // Let at most the given number of requests to a route run at once,
+        // e.g. for an expensive endpoint, further ones are answered with 503.
+        // Has no effect if no route for the path and method was added.
+        if let Some(route) = self.find_route_mut(method, path) {
+            route.concurrency = Some(Arc::new(ConcurrencyLimit::new(limit)));
+        }
+    }
+
+    pub fn get_concurrency_limit(
+        &self,
+        host: Option<&str>,
+        method: &str,
+        path: &str,
+    ) -> Option<Arc<ConcurrencyLimit>> {
+        // Get the concurrency limit of the route a request is routed to, if any.
+        self.find_route_for_host(host, method, path)
+            .and_then(|route| route.concurrency.clone())
+    }
+
+    pub fn set_priority(&mut self, method: &str, path: &str, priority: Priority) {
+        // Set the priority of a route, e.g. low for a slow endpoint.
+        // Has no effect if no route for the path and method was added.
+        if let Some(route) = self.find_route_mut(method, path) {
+            route.priority = priority;
+        }
+    }
+
+    pub fn stream_uploads(&mut self, method: &str, path: &str) {
+        // Stream the files of multipart/form-data bodies sent to a route to
+        // temporary files instead of reading the body into memory. The handler
+        // finds them in the uploads of the request.
+        // Has no effect if no route for the path and method was added.
+        if let Some(route) = self.find_route_mut(method, path) {
+            route.stream_uploads = true;
+        }
+    }
+
+    pub fn streams_uploads(&self, host: Option<&str>, method: &str, path: &str) -> bool {
+        // Check whether the route a request is routed to streams uploads to disk.
+        self.find_route_for_host(host, method, path)
+            .is_some_and(|route| route.stream_uploads)
+    }
+
+    pub fn limit_low_priority(&mut self, limit: usize) {
+        // Let at most the given number of low priority requests run at once,
+        // further ones are answered with 503. Given the number of workers less
+        // some, the remaining workers are kept free for normal requests.
+        self.low_priority = Some(Arc::new(ConcurrencyLimit::new(limit)));
     }
 
-    pub fn get_route(&self, method: &str, path: &str) -> Option<fn() -> Option<String>> {
+    pub fn get_priority_limit(
+        &self,
+        host: Option<&str>,
+        method: &str,
+        path: &str,
+    ) -> Option<Arc<ConcurrencyLimit>> {
+        // Get the limit shared by low priority requests if a request is routed
+        // to a low priority route.
+        self.find_route_for_host(host, method, path)
+            .filter(|route| route.priority == Priority::Low)
+            .and(self.low_priority.clone())
+    }
+
+    pub fn get_route(&self, method: &str, path: &str) -> Option<&FileHandler> {
         // Get a route from the router.
-        // The route is identified by its path.
+        // The route is identified by its path and method.
         // If the route is found, return the handler function.
         // If the route is not found, return None.
-        self.routes.get(path).and_then(|route| {
-            if route.method == method {
-                Some(route.handler)
-            } else {
-                None
+        // Routes producing their own response are not returned, see get_handler_for_host.
+        self.find_route(method, path)
+            .and_then(|route| route.handler.file())
+    }
+
+    pub fn has_host_routes(&self) -> bool {
+        // Check whether any route is scoped to a host, i.e. virtual hosts are used.
+        !self.hosts.is_empty()
+    }
+
+    pub fn is_known_host(&self, host: &str) -> bool {
+        // Check whether a Host header value names a host with routes of its own.
+        self.hosts.contains(&normalize_host(host))
+    }
+
+    pub fn add_host_route<F>(&mut self, host: &str, method: &'a str, path: &str, handler: F)
+    where
+        F: Fn() -> Option<String> + Send + Sync + 'static,
+    {
+        // Add a route which only matches requests for the given host.
+        // Hosts are compared case-insensitively and without a port.
+        self.insert_route(
+            host_key(host, path),
+            method,
+            Handler::File(Arc::new(handler)),
+        );
+    }
+
+    pub fn get_route_for_host(
+        &self,
+        host: Option<&str>,
+        method: &str,
+        path: &str,
+    ) -> Option<&FileHandler> {
+        // Get a route for a request to the given host.
+        // Routes scoped to the host take precedence over host-agnostic routes.
+        self.find_route_for_host(host, method, path)
+            .and_then(|route| route.handler.file())
+    }
+
+    pub fn get_handler_for_host(
+        &self,
+        host: Option<&str>,
+        method: &str,
+        path: &str,
+    ) -> Option<Handler> {
+        // Get the handler of any kind for a request to the given host.
+        self.find_route_for_host(host, method, path)
+            .map(|route| route.handler.clone())
+    }
+
+    pub fn add_middleware(&mut self, middleware: Middleware) {
+        // Add a middleware which is run on every request before routing.
+        // Middleware runs in the order it was added.
+        self.add_named_middleware("", middleware);
+    }
+
+    pub fn add_named_middleware(&mut self, name: &str, middleware: Middleware) {
+        // Add a middleware under a name, so it can be listed and removed later.
+        // Adding a middleware under an existing name replaces it in its position.
+        if !name.is_empty() {
+            if let Some(entry) = self
+                .middleware
+                .iter_mut()
+                .find(|entry| entry.prefix.is_empty() && entry.name == name)
+            {
+                entry.middleware = middleware;
+                return;
             }
+        }
+        self.middleware.push(MiddlewareEntry {
+            name: name.to_string(),
+            prefix: String::new(),
+            middleware,
+        });
+    }
+
+    pub fn remove_middleware(&mut self, name: &str) -> bool {
+        // Remove the middleware with the given name, including middleware of that
+        // name from mounted routers.
+        // Returns whether any middleware was removed.
+        let count = self.middleware.len();
+        self.middleware
+            .retain(|entry| entry.name.is_empty() || entry.name != name);
+        self.middleware.len() != count
+    }
+
+    pub fn middleware_stack(&self) -> Vec<(&str, &str)> {
+        // Get the active middleware as (prefix, name) pairs in the order it runs.
+        self.middleware
+            .iter()
+            .map(|entry| (entry.prefix.as_str(), entry.name.as_str()))
+            .collect()
+    }
+
+    pub fn middleware_for(&self, path: &str) -> Vec<Middleware> {
+        // Get the middleware which runs for a request path in the order it runs.
+        self.middleware
+            .iter()
+            .filter(|entry| path_has_prefix(path, &entry.prefix))
+            .map(|entry| entry.middleware)
+            .collect()
+    }
+
+    pub fn add_response_middleware(&mut self, middleware: ResponseMiddleware) {
+        // Add a middleware which is run on every response before it is written,
+        // including error responses. It runs in the order it was added, after
+        // the handler and before conditional requests are evaluated.
+        self.response_middleware.push((String::new(), middleware));
+    }
+
+    pub fn response_middleware_for(&self, path: &str) -> Vec<ResponseMiddleware> {
+        // Get the response middleware which runs for a request path in the order it runs.
+        self.response_middleware
+            .iter()
+            .filter(|(prefix, _)| path_has_prefix(path, prefix))
+            .map(|(_, middleware)| *middleware)
+            .collect()
+    }
+
+    pub fn add_precomputed_response(
+        &mut self,
+        method: &'a str,
+        path: &str,
+        response: PrecomputedResponse,
+    ) {
+        // Answer requests to a path with a response serialized ahead of time,
+        // e.g. a health check, without running a handler or reading files.
+        // Requests are matched by their path, whatever their query string.
+        // Precomputed responses take precedence over routes, though requests
+        // must still pass authentication. Being written as they are, they skip
+        // the request and response middleware, the memory budget and the
+        // Date, Keep-Alive, Server-Timing and header order of other responses.
+        let responses = self.precomputed.entry(path.to_string()).or_default();
+        responses.retain(|(existing, _)| *existing != method);
+        responses.push((method, response));
+    }
+
+    pub fn get_precomputed_response(
+        &self,
+        method: &str,
+        path: &str,
+    ) -> Option<&PrecomputedResponse> {
+        // Get the precomputed response for a path and method, if any.
+        self.precomputed.get(path).and_then(|responses| {
+            responses
+                .iter()
+                .find(|(existing, _)| *existing == method)
+                .map(|(_, response)| response)
         })
     }
+
+    pub fn serve_dir<P: Into<PathBuf>>(&mut self, prefix: &str, dir: P) {
+        // Serve the files below a directory at a path prefix,
+        // e.g. "/assets/app.css" from "res/assets/app.css" for serve_dir("/assets", "res/assets").
+        // Routes take precedence over files of the same path.
+        // Directories served at the same prefix are searched in the order they
+        // were added, e.g. a directory of overrides before the defaults.
+        let prefix = prefix.trim_end_matches('/').to_string();
+        self.static_dirs.push((prefix, dir.into()));
+    }
+
+    pub fn get_static_file(&self, path: &str) -> Option<PathBuf> {
+        /*
+        Get the file a request path maps to in a served directory.
+        The directories with the longest matching prefix are used and the first
+        of them containing the file wins. If none contains it, the path in the
+        first directory is returned, so the request is answered with 404.
+        Returns None if no directory matches or the path escapes the directories,
+        through ".." or a symlink.
+         */
+        let longest = self
+            .static_dirs
+            .iter()
+            .filter(|(prefix, _)| path_has_prefix(path, prefix))
+            .map(|(prefix, _)| prefix.len())
+            .max()?;
+        let rest = &path[longest..];
+        if !rest.starts_with('/') {
+            return None;
+        }
+        let mut candidates = self
+            .static_dirs
+            .iter()
+            .filter(|(prefix, _)| prefix.len() == longest && path_has_prefix(path, prefix))
+            .filter_map(|(_, dir)| resolve_path(dir, rest).filter(|file| !escapes_root(dir, file)));
+        let first = candidates.next()?;
+        if first.is_file() {
+            return Some(first);
+        }
+        candidates.find(|file| file.is_file()).or(Some(first))
+    }
+
+    pub fn mount(&mut self, prefix: &str, router: Router<'a>) {
+        // Mount the routes of another router below a prefix.
+        // A route "/x" of the mounted router becomes available at "/prefix/x".
+        // Middleware of the parent wraps the mounted routes while middleware
+        // of the mounted router only runs for requests below the prefix.
+        // The route listing endpoint of the mounted router is not carried over.
+        let prefix = prefix.trim_end_matches('/');
+
+        for (key, routes) in router.routes {
+            let key = match key.find('/') {
+                // host routes keep their host in front of the prefixed path
+                Some(index) => prefix_path(&key[..index], prefix, &key[index..]),
+                None => continue,
+            };
+            for route in routes {
+                self.insert_route(key.clone(), route.method, route.handler);
+                if let Some(mounted) = self.find_route_mut(route.method, &key) {
+                    mounted.content_type = route.content_type;
+                    mounted.template_context = route.template_context;
+                    mounted.concurrency = route.concurrency;
+                    mounted.priority = route.priority;
+                    mounted.stream_uploads = route.stream_uploads;
+                }
+            }
+        }
+
+        for (path, responses) in router.precomputed {
+            for (method, response) in responses {
+                self.add_precomputed_response(method, &prefix_path("", prefix, &path), response);
+            }
+        }
+
+        for entry in router.middleware {
+            self.middleware.push(MiddlewareEntry {
+                prefix: format!("{}{}", prefix, entry.prefix),
+                ..entry
+            });
+        }
+
+        for (middleware_prefix, middleware) in router.response_middleware {
+            self.response_middleware
+                .push((format!("{}{}", prefix, middleware_prefix), middleware));
+        }
+
+        for (dir_prefix, dir) in router.static_dirs {
+            self.static_dirs
+                .push((format!("{}{}", prefix, dir_prefix), dir));
+        }
+    }
+
+    pub fn merge(&mut self, mut router: Router<'a>) -> Result<(), Error> {
+        /*
+        Add the routes, middleware and served directories of another router,
+        e.g. one built in a module of its own, at the same paths.
+        Routes, precomputed responses or route listings of both routers for the
+        same path and method conflict, which is reported without merging anything.
+         */
+        let mut conflicts: Vec<String> = router
+            .routes
+            .iter()
+            .flat_map(|(key, routes)| routes.iter().map(move |route| (route.method, key)))
+            .filter(|(method, key)| self.find_route(method, key).is_some())
+            .map(|(method, key)| format!("{} {}", method, key))
+            .collect();
+        conflicts.extend(
+            router
+                .precomputed
+                .iter()
+                .flat_map(|(path, responses)| {
+                    responses.iter().map(move |(method, _)| (*method, path))
+                })
+                .filter(|(method, path)| self.get_precomputed_response(method, path).is_some())
+                .map(|(method, path)| format!("{} {}", method, path)),
+        );
+        if let (Some((path, _)), Some(_)) = (router.routes_endpoint, self.routes_endpoint) {
+            conflicts.push(format!("GET {}", path));
+        }
+        if !conflicts.is_empty() {
+            conflicts.sort();
+            return Err(Error::Route(format!(
+                "conflicting routes: {}",
+                conflicts.join(", ")
+            )));
+        }
+
+        if let Some(endpoint) = router.routes_endpoint.take() {
+            self.routes_endpoint = Some(endpoint);
+        }
+        self.mount("", router);
+        Ok(())
+    }
+
+    pub fn routes(&self) -> Vec<(&str, &str)> {
+        // Get all registered routes as (method, path) pairs.
+        // The pairs are sorted by path so the listing is deterministic.
+        let mut routes: Vec<(&str, &str)> = self
+            .routes
+            .iter()
+            .flat_map(|(path, routes)| {
+                routes
+                    .iter()
+                    .map(move |route| (route.method, path.as_str()))
+            })
+            .collect();
+        if let Some((path, _)) = self.routes_endpoint {
+            routes.push(("GET", path));
+        }
+        routes.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+        routes
+    }
+
+    pub fn methods(&self) -> Vec<&str> {
+        // Get the methods of all registered routes, sorted and without duplicates.
+        // OPTIONS is always included as the server answers it for any path.
+        let mut methods: Vec<&str> = self
+            .routes()
+            .into_iter()
+            .map(|(method, _)| method)
+            .collect();
+        methods.push("OPTIONS");
+        if !self.static_dirs.is_empty() {
+            methods.extend(["GET", "HEAD"]);
+        }
+        methods.sort();
+        methods.dedup();
+        methods
+    }
+
+    pub fn add_routes_endpoint(&mut self, path: &'a str, format: RouteListFormat) {
+        // Register an endpoint which lists all registered routes.
+        // The endpoint answers GET requests only.
+        self.routes_endpoint = Some((path, format));
+    }
+
+    pub fn get_routes_endpoint(&self, method: &str, path: &str) -> Option<RouteListFormat> {
+        // Get the format of the route listing if the request targets the listing endpoint.
+        match self.routes_endpoint {
+            Some((endpoint, format)) if method == "GET" && endpoint == path => Some(format),
+            _ => None,
+        }
+    }
+
+    pub fn render_routes(&self, format: RouteListFormat) -> String {
+        // Render all registered routes in the given format.
+        let routes = self.routes();
+        match format {
+            RouteListFormat::Text => routes
+                .iter()
+                .map(|(method, path)| format!("{} {}\n", method, path))
+                .collect(),
+            RouteListFormat::Json => {
+                let entries: Vec<String> = routes
+                    .iter()
+                    .map(|(method, path)| {
+                        format!(
+                            "{{\"method\":\"{}\",\"path\":\"{}\"}}",
+                            escape_json(method),
+                            escape_json(path)
+                        )
+                    })
+                    .collect();
+                format!("[{}]", entries.join(","))
+            }
+        }
+    }
+}
+
+fn host_key(host: &str, path: &str) -> String {
+    // Build the key of a host-scoped route with a single allocation.
+    let host = strip_port(host);
+    let mut key = String::with_capacity(host.len() + path.len());
+    key.extend(host.chars().map(|c| c.to_ascii_lowercase()));
+    key.push_str(path);
+    key
+}
+
+fn prefix_path(host: &str, prefix: &str, path: &str) -> String {
+    // Join a host, a mount prefix and a path of a mounted route.
+    if path == "/" && !prefix.is_empty() {
+        format!("{}{}", host, prefix)
+    } else {
+        format!("{}{}{}", host, prefix, path)
+    }
+}
+
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    // Check whether a path lies below a prefix, e.g. "/admin/x" below "/admin".
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.is_empty() || rest.is_empty() || rest.starts_with(['/', '?']),
+        None => false,
+    }
+}
+
+pub fn normalize_host(host: &str) -> String {
+    // Normalize a Host header value by removing the port and lowercasing it.
+    strip_port(host).to_ascii_lowercase()
+}
+
+fn strip_port(host: &str) -> &str {
+    // Remove the surrounding whitespace and the port of a Host header value.
+    let host = host.trim();
+    match host.rfind(':') {
+        // an IPv6 literal contains colons inside its brackets
+        Some(index) if !host[index..].contains(']') => &host[..index],
+        _ => host,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::http::{
+        request::{parse_request, Request},
+        response::Response,
+        status::StatusCode,
+    };
+    use std::fs;
+
+    #[test]
+    fn test_methods() {
+        // Test that the methods of all routes are aggregated.
+        let mut router = Router::new();
+        assert_eq!(router.methods(), vec!["OPTIONS"]);
+
+        router.add_route("POST", "/items", || None);
+        router.add_route("GET", "/items", || None);
+        router.add_host_route("api.example.com", "DELETE", "/items", || None);
+        router.add_route("GET", "/", || None);
+
+        assert_eq!(router.methods(), vec!["DELETE", "GET", "OPTIONS", "POST"]);
+    }
+
+    #[test]
+    fn test_static_dirs() {
+        // Test mapping request paths onto served directories.
+        let mut router = Router::new();
+        router.serve_dir("/assets/", "res/assets");
+        router.serve_dir("/assets/fonts", "res/fonts");
+
+        assert_eq!(
+            router.get_static_file("/assets/app.css?v=2"),
+            Some(PathBuf::from("res/assets/app.css"))
+        );
+        assert_eq!(
+            router.get_static_file("/assets/fonts/a.woff2"),
+            Some(PathBuf::from("res/fonts/a.woff2"))
+        );
+        assert_eq!(router.get_static_file("/assets/../secret.txt"), None);
+        assert_eq!(router.get_static_file("/assetsx/app.css"), None);
+        assert_eq!(router.get_static_file("/other.css"), None);
+    }
+
+    #[test]
+    fn test_static_dir_fallback() {
+        // Test that directories at the same prefix are searched in the order they were added.
+        let root =
+            std::env::temp_dir().join(format!("rust-webserver-{}-fallback", std::process::id()));
+        let (overrides, defaults) = (root.join("overrides"), root.join("defaults"));
+        fs::create_dir_all(&overrides).unwrap();
+        fs::create_dir_all(&defaults).unwrap();
+        fs::write(overrides.join("theme.css"), "custom").unwrap();
+        fs::write(defaults.join("theme.css"), "default").unwrap();
+        fs::write(defaults.join("app.js"), "app").unwrap();
+
+        let mut router = Router::new();
+        router.serve_dir("/static", &overrides);
+        router.serve_dir("/static", &defaults);
+        let theme = router.get_static_file("/static/theme.css");
+        let app = router.get_static_file("/static/app.js");
+        let missing = router.get_static_file("/static/missing.js");
+        let escape = router.get_static_file("/static/../defaults/app.js");
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(theme, Some(overrides.join("theme.css")));
+        assert_eq!(app, Some(defaults.join("app.js")));
+        assert_eq!(missing, Some(overrides.join("missing.js")));
+        assert_eq!(escape, None);
+    }
 
     #[test]
     fn test_router() {
@@ -61,10 +853,10 @@ mod tests {
         router.add_route("GET", "/about", || Some("about.html".to_string()));
 
         let handler = router.get_route("GET", "/");
-        assert_eq!(handler.is_some(), true);
+        assert!(handler.is_some());
         assert_eq!(handler.unwrap()().unwrap(), "index.html");
 
-        assert_eq!(router.get_route("GET", "/contact"), None);
+        assert!(router.get_route("GET", "/contact").is_none());
     }
 
     #[test]
@@ -74,13 +866,226 @@ mod tests {
 
         router.add_route("POST", "/contact", || Some("contact.html".to_string()));
 
-        assert_eq!(router.get_route("GET", "/contact"), None);
+        assert!(router.get_route("GET", "/contact").is_none());
         assert_eq!(
             router.get_route("POST", "/contact").unwrap()().unwrap(),
             "contact.html"
         );
     }
 
+    #[test]
+    fn test_same_path_different_methods() {
+        // Test the router with several methods on the same path.
+        let mut router = Router::new();
+
+        router.add_route("GET", "/items", || Some("list.html".to_string()));
+        router.add_route("DELETE", "/items", || Some("deleted.html".to_string()));
+
+        assert_eq!(
+            router.get_route("GET", "/items").unwrap()().unwrap(),
+            "list.html"
+        );
+        assert_eq!(
+            router.get_route("DELETE", "/items").unwrap()().unwrap(),
+            "deleted.html"
+        );
+        assert!(router.get_route("POST", "/items").is_none());
+    }
+
+    #[test]
+    fn test_host_routes() {
+        // Test that the same path under two hosts resolves to two handlers.
+        let mut router = Router::new();
+
+        router.add_host_route("api.example.com", "GET", "/users", || {
+            Some("api.html".to_string())
+        });
+        router.add_host_route("www.example.com", "GET", "/users", || {
+            Some("www.html".to_string())
+        });
+        router.add_route("GET", "/users", || Some("users.html".to_string()));
+
+        assert_eq!(
+            router
+                .get_route_for_host(Some("api.example.com"), "GET", "/users")
+                .unwrap()()
+            .unwrap(),
+            "api.html"
+        );
+        assert_eq!(
+            router
+                .get_route_for_host(Some("WWW.example.com:8080"), "GET", "/users")
+                .unwrap()()
+            .unwrap(),
+            "www.html"
+        );
+        assert!(router.has_host_routes());
+        assert!(router.is_known_host("API.example.com:443"));
+        assert!(!router.is_known_host("other.example.com"));
+        assert_eq!(
+            router
+                .get_route_for_host(Some("other.example.com"), "GET", "/users")
+                .unwrap()()
+            .unwrap(),
+            "users.html"
+        );
+        assert_eq!(
+            router.get_route_for_host(None, "GET", "/users").unwrap()().unwrap(),
+            "users.html"
+        );
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        // Test normalizing host header values.
+        assert_eq!(normalize_host("Example.com"), "example.com");
+        assert_eq!(normalize_host("example.com:8080"), "example.com");
+        assert_eq!(normalize_host("[::1]:8080"), "[::1]");
+        assert_eq!(normalize_host("[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn test_mount() {
+        // Test that a mounted router's routes resolve below the prefix.
+        let mut admin = Router::new();
+        admin.add_route("GET", "/", || Some("admin.html".to_string()));
+        admin.add_route("GET", "/x", || Some("x.html".to_string()));
+        admin.add_host_route("example.com", "GET", "/y", || Some("y.html".to_string()));
+        admin.add_route("POST", "/upload", || Some("upload.html".to_string()));
+        admin.stream_uploads("POST", "/upload");
+
+        let mut router = Router::new();
+        router.add_route("GET", "/x", || Some("root.html".to_string()));
+        router.mount("/admin/", admin);
+
+        assert!(router.streams_uploads(None, "POST", "/admin/upload"));
+        assert!(!router.streams_uploads(None, "GET", "/admin/x"));
+
+        assert_eq!(
+            router.get_route("GET", "/admin/x").unwrap()().unwrap(),
+            "x.html"
+        );
+        assert_eq!(
+            router.get_route("GET", "/admin").unwrap()().unwrap(),
+            "admin.html"
+        );
+        assert_eq!(
+            router
+                .get_route_for_host(Some("example.com"), "GET", "/admin/y")
+                .unwrap()()
+            .unwrap(),
+            "y.html"
+        );
+        assert_eq!(
+            router.get_route("GET", "/x").unwrap()().unwrap(),
+            "root.html"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        // Test that merged routes resolve at their paths and conflicts are reported.
+        fn logging(_: &mut Request) -> Option<Response> {
+            None
+        }
+
+        let mut api = Router::new();
+        api.add_route("GET", "/users", || Some("users.html".to_string()));
+        api.add_route("POST", "/x", || Some("post.html".to_string()));
+        api.add_host_route("example.com", "GET", "/y", || Some("y.html".to_string()));
+        api.add_middleware(logging);
+        api.stream_uploads("POST", "/x");
+
+        let mut router = Router::new();
+        router.add_route("GET", "/x", || Some("x.html".to_string()));
+        router.merge(api).unwrap();
+
+        assert!(router.streams_uploads(None, "POST", "/x"));
+        assert!(!router.streams_uploads(None, "GET", "/x"));
+
+        assert_eq!(
+            router.get_route("GET", "/users").unwrap()().unwrap(),
+            "users.html"
+        );
+        assert_eq!(
+            router.get_route("POST", "/x").unwrap()().unwrap(),
+            "post.html"
+        );
+        assert_eq!(router.get_route("GET", "/x").unwrap()().unwrap(), "x.html");
+        assert_eq!(
+            router
+                .get_route_for_host(Some("example.com"), "GET", "/y")
+                .unwrap()()
+            .unwrap(),
+            "y.html"
+        );
+        assert_eq!(router.middleware_for("/").len(), 1);
+
+        let mut other = Router::new();
+        other.add_route("GET", "/x", || Some("other.html".to_string()));
+        other.add_route("GET", "/z", || Some("z.html".to_string()));
+        let error = router.merge(other).unwrap_err();
+        assert_eq!(error.to_string(), "route error: conflicting routes: GET /x");
+        assert!(router.get_route("GET", "/z").is_none());
+        assert_eq!(router.get_route("GET", "/x").unwrap()().unwrap(), "x.html");
+    }
+
+    #[test]
+    fn test_mount_middleware() {
+        // Test that mounted middleware only runs below the prefix.
+        fn parent(_: &mut Request) -> Option<Response> {
+            None
+        }
+        fn child(_: &mut Request) -> Option<Response> {
+            None
+        }
+
+        let mut admin = Router::new();
+        admin.add_middleware(child);
+
+        let mut router = Router::new();
+        router.add_middleware(parent);
+        router.mount("/admin", admin);
+
+        assert_eq!(router.middleware_for("/admin/x").len(), 2);
+        assert_eq!(router.middleware_for("/admin").len(), 2);
+        assert_eq!(router.middleware_for("/administrator").len(), 1);
+        assert_eq!(router.middleware_for("/").len(), 1);
+    }
+
+    #[test]
+    fn test_named_middleware() {
+        // Test listing, replacing and removing middleware by name.
+        fn first(_: &mut Request) -> Option<Response> {
+            None
+        }
+        fn second(_: &mut Request) -> Option<Response> {
+            Some(Response::from_status(StatusCode::Unauthorized))
+        }
+
+        let mut admin = Router::new();
+        admin.add_named_middleware("auth", second);
+
+        let mut router = Router::new();
+        router.add_named_middleware("auth", first);
+        router.add_middleware(first);
+        router.add_named_middleware("log", second);
+        router.add_named_middleware("auth", second);
+        router.mount("/admin", admin);
+
+        assert_eq!(
+            router.middleware_stack(),
+            vec![("", "auth"), ("", ""), ("", "log"), ("/admin", "auth")]
+        );
+        let mut request = parse_request(&mut "GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert!(router.middleware_for("/")[0](&mut request).is_some());
+
+        assert!(router.remove_middleware("auth"));
+        assert!(!router.remove_middleware("auth"));
+        assert!(!router.remove_middleware(""));
+        assert_eq!(router.middleware_stack(), vec![("", ""), ("", "log")]);
+    }
+
     #[test]
     fn test_case_sensitivity() {
         // Test the router with case sensitivity.
@@ -92,7 +1097,7 @@ mod tests {
             router.get_route("GET", "/contact").unwrap()().unwrap(),
             "contact.html"
         );
-        assert_eq!(router.get_route("GET", "/Contact"), None);
+        assert!(router.get_route("GET", "/Contact").is_none());
     }
 
     #[test]
@@ -107,4 +1112,50 @@ mod tests {
             "contact.html"
         );
     }
+
+    #[test]
+    fn test_routes_listing() {
+        // Test that the listing includes every route that was added.
+        let mut router = Router::new();
+
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        router.add_route("POST", "/contact", || Some("contact.html".to_string()));
+        router.add_route("GET", "/about", || Some("about.html".to_string()));
+        router.add_routes_endpoint("/_routes", RouteListFormat::Text);
+
+        assert_eq!(
+            router.routes(),
+            vec![
+                ("GET", "/"),
+                ("GET", "/_routes"),
+                ("GET", "/about"),
+                ("POST", "/contact"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_routes_endpoint() {
+        // Test the route listing endpoint in both formats.
+        let mut router = Router::new();
+
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        router.add_routes_endpoint("/_routes", RouteListFormat::Json);
+
+        assert_eq!(
+            router.get_routes_endpoint("GET", "/_routes"),
+            Some(RouteListFormat::Json)
+        );
+        assert_eq!(router.get_routes_endpoint("POST", "/_routes"), None);
+        assert_eq!(router.get_routes_endpoint("GET", "/"), None);
+
+        assert_eq!(
+            router.render_routes(RouteListFormat::Text),
+            "GET /\nGET /_routes\n"
+        );
+        assert_eq!(
+            router.render_routes(RouteListFormat::Json),
+            "[{\"method\":\"GET\",\"path\":\"/\"},{\"method\":\"GET\",\"path\":\"/_routes\"}]"
+        );
+    }
 }
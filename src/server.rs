@@ -0,0 +1,3777 @@
+/*
+* Handling of client connections.
+*/
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{
+        socket_address, Config, FaviconResponse, GetBody, NotFoundResponse, OversizedResponse,
+        QueryLogging, RootResponse, UnknownHostResponse,
+    },
+    debug, error,
+    error::{Error, HandlerError},
+    http::{
+        auth::is_authorized,
+        cache::apply_cache_headers,
+        conditional::is_not_modified,
+        connection::Connection,
+        counting::{ByteCounts, CountingStream},
+        file::{
+            escapes_root, has_allowed_extension, http_date, is_hidden, resolve_path, serve_file,
+        },
+        forwarded::client_ip,
+        listing::directory_listing,
+        mime::{content_type, media_type_matches},
+        multipart::{boundary, stream_multipart, Uploads},
+        request::{
+            is_timeout, parse_request_head, read_request_body, skip_empty_lines, validate_host,
+            validate_uri_length, Deadline, HTTPError, HeadLimits, Request,
+        },
+        response::{generated_status_page, PrecomputedResponse, Response},
+        status::StatusCode,
+        template::render,
+        url::{decode_path, split_uri},
+        writer::{is_unreadable_body, ResponseWriter},
+    },
+    info,
+    json::escape_json,
+    limit::{ConcurrencyPermit, ConnectionLimits, MemoryReservation},
+    logger::{global::logger, log::LogLevel},
+    router::router::{Handler, Router},
+    thread::{run_with_timeout, Executor, ThreadPerJob, ThreadPool},
+    warn,
+};
+
+const HTML_CONTENT_TYPE: &str = "text/html; charset=UTF-8";
+// Pause of the accept loop after accepting failed for lack of resources.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// A transparent 1x1 icon served for /favicon.ico if enabled without a file.
+const DEFAULT_FAVICON: [u8; 70] = [
+    0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x01, 0x00, 0x00, 0x01, 0x00, 0x20, 0x00, 0x30, 0x00,
+    0x00, 0x00, 0x16, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00,
+    0x00, 0x00, 0x01, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+pub fn status_page(path_to_resources: &Path, status: StatusCode) -> String {
+    /*
+    Get the page for a status from the resources directory, e.g. 404.html.
+    Statuses without a readable page get a generated page.
+     */
+    let file = format!("{}.html", status.as_u16());
+    match fs::read_to_string(path_to_resources.join(&file)) {
+        Ok(contents) => contents,
+        Err(e) => {
+            debug!("No page for status {}: {}", status, e);
+            generated_status_page(status)
+        }
+    }
+}
+
+pub fn status_response(config: &Config, status: StatusCode) -> Response {
+    /*
+    Get the response for any status with its page as the body.
+    A 404 is answered as configured, which may be a redirect or a JSON body.
+     */
+    if status == StatusCode::NotFound {
+        match &config.not_found {
+            NotFoundResponse::Page => (),
+            NotFoundResponse::Redirect(location) => {
+                return Response::redirect(StatusCode::Found, location);
+            }
+            NotFoundResponse::Json => {
+                let body = format!(
+                    "{{\"status\":{},\"error\":\"{}\"}}",
+                    status.as_u16(),
+                    escape_json(status.reason_phrase())
+                );
+                return Response::new(status, "application/json", body.into_bytes());
+            }
+        }
+    }
+
+    let contents = status_page(&config.path_to_resources, status);
+    Response::new(status, HTML_CONTENT_TYPE, contents.into_bytes())
+}
+
+pub fn error_response(config: &Config, error: HTTPError) -> Response {
+    /*
+    Get the response with the error page for the given error.
+     */
+    status_response(config, error.status_code())
+}
+
+#[derive(Debug, Default)]
+struct Timings {
+    // Time spent reading and parsing the request.
+    read: Duration,
+    // Time spent answering the request besides running its handler, e.g. running
+    // the middleware, matching the route and reading the served file.
+    route: Duration,
+    // Time the handler of the route ran, if the request has one.
+    handler: Option<Duration>,
+    // Time spent writing the response, known once it was written.
+    write: Option<Duration>,
+}
+
+impl Timings {
+    fn format(&self) -> String {
+        // Format the known phases as a Server-Timing header value, e.g.
+        // "read;dur=0.052, route;dur=0.310, handler;dur=12.004" in milliseconds.
+        [
+            ("read", Some(self.read)),
+            ("route", Some(self.route)),
+            ("handler", self.handler),
+            ("write", self.write),
+        ]
+        .into_iter()
+        .filter_map(|(phase, duration)| {
+            duration.map(|duration| format!("{};dur={:.3}", phase, duration.as_secs_f64() * 1000.0))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+pub fn handle_connection<S: Connection>(
+    stream: S,
+    peer: Option<IpAddr>,
+    config: &Config,
+    router: &Router,
+) -> Result<ByteCounts, Error> {
+    /*
+    Handle a connection from a client.
+    The peer is the address the connection comes from, if known.
+    Requests are served until the client closes the connection, asks for it to be
+    closed or the maximum number of requests per connection is reached.
+    Returns the number of bytes read from and written to the client.
+    The client is sent an error response before an invalid request or a failed
+    handler is returned as an error, which also closes the connection.
+    Waiting for a request is bounded by the keep-alive timeout and reading a
+    request once it started by the request timeout. A client that stops reading
+    is dropped once a write blocks for longer than the write timeout.
+    The time spent in each phase of a request is logged at debug level and, if
+    enabled, sent in a Server-Timing header, except for precomputed responses.
+    The read buffer lives as long as the connection, so pipelined requests which
+    arrived along with an earlier one are served from it in order.
+     */
+    let mut reader = BufReader::with_capacity(config.read_buffer_size, CountingStream::new(stream));
+    let mut served = 0;
+    reader.get_mut().set_write_timeout(config.write_timeout)?;
+
+    loop {
+        // wait for the next request, which may never come on a kept alive connection
+        reader
+            .get_mut()
+            .set_read_timeout(config.keep_alive_timeout)?;
+        match skip_empty_lines(&mut reader) {
+            Ok(true) => reader.get_mut().set_read_timeout(config.request_timeout)?,
+            Ok(false) => {
+                if served == 0 {
+                    debug!("Connection closed without a request.");
+                }
+                return Ok(reader.get_ref().counts());
+            }
+            Err(e) if is_timeout(&e) => {
+                debug!("Idle connection timed out.");
+                return Ok(reader.get_ref().counts());
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let start = Instant::now();
+        // the bodies of the request and its response count against the memory
+        // budget until the response is written, the request body before it is read
+        let mut reservation = config.memory_budget.reserve();
+        let limits = HeadLimits {
+            max_uri_length: config.max_uri_length,
+            max_header_value: config.max_header_value_length,
+            max_headers: config.max_headers,
+            max_header_bytes: config.max_header_bytes,
+        };
+        let request = parse_request_head(&mut reader, &limits).and_then(|mut request| {
+            validate_uri_length(&request, config.max_uri_length)?;
+            validate_host(&request)?;
+            match upload_boundary(router, &request) {
+                Some(boundary) => receive_uploads(&mut reader, &mut request, &boundary, config)?,
+                None => read_request_body(&mut reader, &mut request, &mut |bytes| {
+                    usize::try_from(bytes).is_ok_and(|bytes| reservation.grow(bytes))
+                })?,
+            }
+            handle_get_body(&mut request, config.get_body)?;
+            Ok(request)
+        });
+        served += 1;
+        let mut timings = Timings {
+            read: start.elapsed(),
+            ..Timings::default()
+        };
+
+        // write precomputed responses as they are, without building a response
+        if let Some((request, precomputed)) = request
+            .as_ref()
+            .ok()
+            .and_then(|request| Some((request, precomputed_response(request, config, router)?)))
+        {
+            let close = close_after(Some(request), served, config);
+            let written = reader
+                .get_mut()
+                .write_all(precomputed.bytes(close, request.method == "HEAD"))
+                .and_then(|()| reader.get_mut().flush());
+            if let Err(e) = written {
+                return write_failed(e, reader.get_ref().counts());
+            }
+            log_request(
+                config,
+                peer,
+                Some(request),
+                precomputed.response(),
+                start.elapsed(),
+            );
+            if close {
+                return Ok(reader.get_ref().counts());
+            }
+            continue;
+        }
+
+        let (request, mut response, failure) = match request {
+            Ok(mut request) => {
+                let responding = Instant::now();
+                let (response, failure) =
+                    match respond(&mut request, config, router, &mut timings, &mut reservation) {
+                        Ok(response) => (response, None),
+                        Err(e) => (status_response(config, e.status_code()), Some(e)),
+                    };
+                let mut response = finish_response(config, router, &request, response);
+                if !reservation.grow(response.body.len()) {
+                    warn!(
+                        "Memory budget exhausted by the response to {} {}.",
+                        request.method, request.uri
+                    );
+                    response = retry_later(config);
+                }
+                timings.route = responding
+                    .elapsed()
+                    .saturating_sub(timings.handler.unwrap_or_default());
+                (Some(request), response, failure)
+            }
+            Err(HTTPError::ServiceUnavailable) => {
+                // the body was left unread, so the connection is closed
+                warn!("Memory budget exhausted; refusing a request without reading its body.");
+                let e = HTTPError::ServiceUnavailable;
+                (None, retry_later(config), Some(Error::Parse(e)))
+            }
+            Err(e) => {
+                warn!("Error validating request: {:?}", e);
+                (None, error_response(config, e), Some(Error::Parse(e)))
+            }
+        };
+
+        let close = failure.is_some() || close_after(request.as_ref(), served, config);
+        response.add_header("Date", &http_date(config.clock.now()));
+        if close {
+            response.add_header("Connection", "close");
+        } else if let Some(value) = keep_alive_value(config, served) {
+            response.add_header("Keep-Alive", &value);
+        }
+        if config.server_timing {
+            response.add_header("Server-Timing", &timings.format());
+        }
+        response.order_headers(&config.header_order);
+        let writing = Instant::now();
+        let mut writer = ResponseWriter::new(reader.get_mut());
+        let mut written = match &request {
+            Some(request) if request.method == "HEAD" => writer.write_head(&response),
+            _ => writer.write_response(&response),
+        };
+        // nothing was sent if the file body could not be read, so answer with 500 instead
+        if let Some(e) = written.as_ref().err().filter(|e| is_unreadable_body(e)) {
+            error!("Error reading file: {}", e);
+            let mut error = error_response(config, HTTPError::InternalServerError);
+            error.headers.extend(
+                response
+                    .headers
+                    .iter()
+                    .filter(|(name, _)| {
+                        ["Date", "Connection", "Keep-Alive", "Server-Timing"]
+                            .contains(&name.as_str())
+                    })
+                    .cloned(),
+            );
+            response = error;
+            written = writer.write_response(&response);
+        }
+        if let Err(e) = written {
+            return write_failed(e, reader.get_ref().counts());
+        }
+        timings.write = Some(writing.elapsed());
+        if let Some(request) = &request {
+            debug!(
+                "Timings of {} {}: {}",
+                request.method,
+                log_uri(&request.uri, config.log_query),
+                timings.format()
+            );
+        }
+        log_request(config, peer, request.as_ref(), &response, start.elapsed());
+
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        if close {
+            return Ok(reader.get_ref().counts());
+        }
+    }
+}
+
+fn write_failed(error: io::Error, counts: ByteCounts) -> Result<ByteCounts, Error> {
+    /*
+    Give up on a connection whose response could not be written.
+    A write that timed out means the client stopped reading, which is its
+    choice to make rather than an error of the server.
+     */
+    if is_timeout(&error) {
+        debug!("Client stopped reading; dropping the connection.");
+        return Ok(counts);
+    }
+    Err(error.into())
+}
+
+fn route_path(uri: &str) -> String {
+    // Get the path a request is routed by, decoded and without the query string,
+    // e.g. "/search" for "/search?q=hello".
+    let (path, _) = split_uri(uri);
+    decode_path(path).unwrap_or_else(|| path.to_string())
+}
+
+fn upload_boundary(router: &Router, request: &Request) -> Option<String> {
+    // Get the boundary of a multipart body with a known length sent to a route
+    // streaming uploads, if the request has one.
+    if request.header("Content-Length").is_none()
+        || !router.streams_uploads(
+            request.header("Host"),
+            &request.method,
+            &route_path(&request.uri),
+        )
+    {
+        return None;
+    }
+    boundary(request.header("Content-Type")?).map(str::to_string)
+}
+
+fn receive_uploads<R: BufRead>(
+    reader: &mut R,
+    request: &mut Request,
+    boundary: &str,
+    config: &Config,
+) -> Result<(), HTTPError> {
+    /*
+    Stream the files of a multipart body to temporary files in the upload directory,
+    keeping the parts in the extensions of the request for its handler.
+    Bytes after the closing boundary are skipped, so the next request on the
+    connection starts after the body.
+     */
+    let length = request.content_length()?.ok_or(HTTPError::InvalidRequest)?;
+    let mut body = reader.take(length);
+    let parts = stream_multipart(
+        &mut body,
+        boundary,
+        &config.upload_dir,
+        config.max_upload_size,
+    )?;
+    let uploads = Uploads(parts);
+    match io::copy(&mut body, &mut io::sink()) {
+        Ok(_) if body.limit() == 0 => (),
+        _ => return Err(HTTPError::InvalidRequest),
+    }
+    debug!("Streamed {} uploaded parts to disk.", uploads.0.len());
+    request.extensions.insert(uploads);
+    Ok(())
+}
+
+fn handle_get_body(request: &mut Request, policy: GetBody) -> Result<(), HTTPError> {
+    /*
+    Drop or reject the body of a GET or HEAD request.
+    The body was read along with the request either way, so the next request
+    on the connection starts after it.
+     */
+    let has_body = !request.body.is_empty()
+        || request
+            .header("Content-Length")
+            .is_some_and(|length| length.trim() != "0")
+        || request.header("Transfer-Encoding").is_some();
+    if !matches!(request.method.as_str(), "GET" | "HEAD") || !has_body {
+        return Ok(());
+    }
+    match policy {
+        GetBody::Discard => {
+            debug!("Discarding the body of a {} request.", request.method);
+            request.body.clear();
+            Ok(())
+        }
+        GetBody::Reject => Err(HTTPError::InvalidRequest),
+    }
+}
+
+fn finish_response(
+    config: &Config,
+    router: &Router,
+    request: &Request,
+    mut response: Response,
+) -> Response {
+    /*
+    Run the response middleware on the response to a request, then answer
+    conditional requests and enforce the body limit on the result.
+    Middleware is chosen by the decoded path, like the route.
+     */
+    for middleware in router.response_middleware_for(&route_path(&request.uri)) {
+        middleware(request, &mut response);
+    }
+    limit_body(config, request, revalidate(request, response))
+}
+
+fn revalidate(request: &Request, response: Response) -> Response {
+    /*
+    Answer with 304 if the ETag or Last-Modified of a response shows that the copy
+    of the client is current, e.g. validators a middleware set on a generated response.
+    Served files already answer a matching If-None-Match themselves.
+     */
+    if response.status != StatusCode::Ok
+        || !is_not_modified(
+            request,
+            response.header("ETag"),
+            response.header("Last-Modified"),
+        )
+    {
+        return response;
+    }
+    let mut not_modified =
+        Response::new(StatusCode::NotModified, &response.content_type, Vec::new());
+    not_modified.headers = response.headers;
+    not_modified
+}
+
+fn limit_body(config: &Config, request: &Request, mut response: Response) -> Response {
+    /*
+    Enforce the maximum size of a body held in memory, truncating the body or
+    answering with 500 as configured. Bodies streamed from files are not limited.
+     */
+    let max = match config.max_response_body {
+        Some(max) if response.body.len() as u64 > max => max,
+        _ => return response,
+    };
+    warn!(
+        "Response to {} {} has a body of {} bytes, more than the limit of {}.",
+        request.method,
+        request.uri,
+        response.body.len(),
+        max
+    );
+    match config.oversized_response {
+        OversizedResponse::Truncate => {
+            response.body.truncate(max as usize);
+            response
+        }
+        OversizedResponse::Error => error_response(config, HTTPError::InternalServerError),
+    }
+}
+
+fn keep_alive_value(config: &Config, served: usize) -> Option<String> {
+    /*
+    Get the Keep-Alive header value advertising how long the connection is kept
+    idle and how many more requests it serves, e.g. "timeout=5, max=99".
+    Returns None if disabled or neither is limited.
+     */
+    if !config.keep_alive_header {
+        return None;
+    }
+    let mut parameters = Vec::new();
+    if let Some(timeout) = config.keep_alive_timeout {
+        parameters.push(format!("timeout={}", timeout.as_secs()));
+    }
+    if let Some(max) = config.max_requests_per_connection {
+        parameters.push(format!("max={}", max.saturating_sub(served)));
+    }
+    Some(parameters.join(", ")).filter(|value| !value.is_empty())
+}
+
+fn close_after(request: Option<&Request>, served: usize, config: &Config) -> bool {
+    // Check whether a connection is closed after the response to a request,
+    // because the client asked for it or the connection served its last request.
+    config
+        .max_requests_per_connection
+        .is_some_and(|max| served >= max)
+        || request.is_some_and(|request| !request.keep_alive())
+}
+
+fn precomputed_response<'r>(
+    request: &Request,
+    config: &Config,
+    router: &'r Router,
+) -> Option<&'r PrecomputedResponse> {
+    // Get the precomputed response for an authorized request, if any.
+    // A HEAD request is answered like a GET unless it has its own response.
+    let path = route_path(&request.uri);
+    let response = router
+        .get_precomputed_response(&request.method, &path)
+        .or_else(|| match request.method.as_str() {
+            "HEAD" => router.get_precomputed_response("GET", &path),
+            _ => None,
+        })?;
+    is_authorized(&config.auth, request).then_some(response)
+}
+
+fn log_request(
+    config: &Config,
+    peer: Option<IpAddr>,
+    request: Option<&Request>,
+    response: &Response,
+    elapsed: Duration,
+) {
+    // Warn about a slow request and write its access log line if enabled and
+    // the request is sampled.
+    if let (Some(threshold), Some(request)) = (config.slow_request_threshold, request) {
+        if elapsed > threshold {
+            warn!(
+                "Slow request: {} {} took {}ms",
+                request.method,
+                log_uri(&request.uri, config.log_query),
+                elapsed.as_millis()
+            );
+        }
+    }
+
+    if config.log_requests
+        && config
+            .access_log_sampler
+            .sample(response.status.is_success())
+    {
+        let client = match (peer, request) {
+            (Some(peer), Some(request)) => Some(client_ip(peer, request, &config.trusted_proxies)),
+            _ => peer,
+        };
+        info!(
+            "{}",
+            access_log_line(client, request, response, elapsed, config.log_query)
+        );
+    }
+}
+
+pub fn access_log_line(
+    client: Option<IpAddr>,
+    request: Option<&Request>,
+    response: &Response,
+    duration: Duration,
+    query: QueryLogging,
+) -> String {
+    /*
+    Format the access log line of a request, e.g. "127.0.0.1 GET / 200 3ms".
+    An unknown client and requests which could not be parsed are logged with dashes.
+    The query string is logged, left out or redacted as configured, as it
+    may carry secrets, e.g. tokens.
+     */
+    let client = client.map_or("-".to_string(), |client| client.to_string());
+    let (method, uri) = match request {
+        Some(request) => (request.method.as_str(), log_uri(&request.uri, query)),
+        None => ("-", "-".to_string()),
+    };
+    format!(
+        "{} {} {} {} {}ms",
+        client,
+        method,
+        uri,
+        response.status.as_u16(),
+        duration.as_millis()
+    )
+}
+
+fn log_uri(uri: &str, query: QueryLogging) -> String {
+    // Get the uri of a request as it is written to the access log.
+    let (path, Some(params)) = split_uri(uri) else {
+        return uri.to_string();
+    };
+    match query {
+        QueryLogging::Full => uri.to_string(),
+        QueryLogging::Strip => path.to_string(),
+        QueryLogging::Redact => {
+            let redacted: Vec<String> = params
+                .split('&')
+                .map(|pair| match pair.split_once('=') {
+                    Some((name, _)) => format!("{}=***", name),
+                    None => pair.to_string(),
+                })
+                .collect();
+            format!("{}?{}", path, redacted.join("&"))
+        }
+    }
+}
+
+fn respond(
+    request: &mut Request,
+    config: &Config,
+    router: &Router,
+    timings: &mut Timings,
+    reservation: &mut MemoryReservation,
+) -> Result<Response, Error> {
+    /*
+    Get the response to a valid request, recording how long its handler ran.
+    Copies of the request body count against the memory budget.
+    Returns an error if the handler failed to produce a response.
+     */
+    debug!(
+        "Request: {} {} {}",
+        request.method, request.uri, request.version
+    );
+
+    // reject requests without accepted credentials
+    if !is_authorized(&config.auth, request) {
+        let mut response = status_response(config, StatusCode::Unauthorized);
+        if let Some(challenge) = config.auth.challenge() {
+            response.add_header("WWW-Authenticate", &challenge);
+        }
+        return Ok(response);
+    }
+
+    // run the middleware, which may answer the request itself,
+    // choosing it by the decoded path the route is chosen by, so an encoded
+    // prefix like "/%61dmin" does not skip the middleware mounted at "/admin"
+    let target = route_path(&request.uri);
+    for middleware in router.middleware_for(&target) {
+        if let Some(response) = middleware(request) {
+            return Ok(response);
+        }
+    }
+
+    let (method, uri) = (&request.method, &request.uri);
+
+    // answer a server-wide OPTIONS with the methods of all routes
+    if method == "OPTIONS" && uri == "*" {
+        let mut response = Response::new(StatusCode::NoContent, HTML_CONTENT_TYPE, Vec::new());
+        response.add_header("Allow", &router.methods().join(", "));
+        return Ok(response);
+    }
+
+    // echo a TRACE back if enabled, which routes can't override
+    if method == "TRACE" {
+        if !config.trace {
+            let mut response = status_response(config, StatusCode::MethodNotAllowed);
+            response.add_header("Allow", &router.methods().join(", "));
+            return Ok(response);
+        }
+        return Ok(Response::new(
+            StatusCode::Ok,
+            "message/http",
+            trace_body(request),
+        ));
+    }
+
+    // requests for hosts without routes of their own are answered as configured
+    let host = match request.header("Host") {
+        host if !router.has_host_routes()
+            || host.is_some_and(|host| router.is_known_host(host)) =>
+        {
+            host
+        }
+        host => match &config.unknown_host {
+            UnknownHostResponse::NotFound => {
+                debug!("No routes for host {}.", host.unwrap_or("-"));
+                return Ok(status_response(config, StatusCode::NotFound));
+            }
+            UnknownHostResponse::DefaultHost(default) => Some(default.as_str()),
+            UnknownHostResponse::Shared => None,
+        },
+    };
+
+    // serve the route listing if the request targets it
+    if let Some(format) = router.get_routes_endpoint(method, &target) {
+        let contents = router.render_routes(format);
+        return Ok(Response::new(
+            StatusCode::Ok,
+            format.content_type(),
+            contents.into_bytes(),
+        ));
+    }
+
+    // a HEAD request is answered like a GET unless it has its own route
+    let route_method = match router.get_handler_for_host(host, method, &target) {
+        None if method == "HEAD" => "GET",
+        _ => method.as_str(),
+    };
+    let handler = match router.get_handler_for_host(host, route_method, &target) {
+        Some(handler) => handler,
+        None => return Ok(serve_unrouted(config, router, request)),
+    };
+
+    // reject bodies the route does not accept before running its handler
+    if let Some(required) = router.get_required_content_type(host, route_method, &target) {
+        let content_type = request.header("Content-Type").unwrap_or("");
+        if !media_type_matches(content_type, required) {
+            return Ok(status_response(config, StatusCode::UnsupportedMediaType));
+        }
+    }
+
+    // turn the request away if the route already handles as many as it may,
+    // or if low priority requests already use every worker left to them
+    let limits = [
+        router.get_concurrency_limit(host, route_method, &target),
+        router.get_priority_limit(host, route_method, &target),
+    ];
+    let mut permits = Vec::with_capacity(2);
+    for limit in limits.into_iter().flatten() {
+        match limit.try_acquire() {
+            Some(permit) => permits.push(permit),
+            None => {
+                warn!("Too many concurrent requests to {} {}.", method, uri);
+                return Ok(retry_later(config));
+            }
+        }
+    }
+
+    let file = match handler {
+        Handler::File(handler) => match run_handler(config, request, permits, handler, timings)? {
+            Some(file) => file,
+            None => {
+                // the handler has nothing to serve, which is not an error
+                debug!("Handler for {} {} returned no file.", method, uri);
+                return Ok(empty_handler_response(config));
+            }
+        },
+        Handler::Bytes(handler) => {
+            return match run_handler(config, request, permits, handler, timings)? {
+                Some((content_type, body)) => Ok(Response::new(StatusCode::Ok, content_type, body)),
+                None => {
+                    debug!("Handler for {} {} returned no body.", method, uri);
+                    Ok(empty_handler_response(config))
+                }
+            };
+        }
+        Handler::Response(handler) => {
+            return match run_handler(config, request, permits, handler, timings)? {
+                Ok(response) => Ok(response),
+                Err(status) => {
+                    debug!("Handler for {} {} failed with {}.", method, uri, status);
+                    Ok(status_response(config, status))
+                }
+            };
+        }
+        Handler::Request(handler) => {
+            // the handler may outlive the request if it times out, so it gets a copy
+            // which tells it when its result will be discarded
+            if !reservation.grow(request.body.len()) {
+                warn!("Memory budget exhausted; refusing {} {}.", method, uri);
+                return Ok(retry_later(config));
+            }
+            let mut copy = request.clone();
+            if let Some(timeout) = config.handler_timeout {
+                copy.extensions.insert(Deadline(Instant::now() + timeout));
+            }
+            let handler = Arc::new(move || handler(&copy));
+            return match run_handler(config, request, permits, handler, timings)? {
+                Ok(response) => Ok(response),
+                Err(status) => {
+                    debug!("Handler for {} {} failed with {}.", method, uri, status);
+                    Ok(status_response(config, status))
+                }
+            };
+        }
+    };
+
+    let path = config.path_to_resources.join(file);
+    if !is_within_resources(config, &path) {
+        return Ok(error_response(config, HTTPError::NotFound));
+    }
+    if let Some(context) = router.get_template_context(host, route_method, &target) {
+        if media_type_matches(content_type(&path), &config.template_content_type) {
+            return Ok(serve_template(config, &path, &context()));
+        }
+    }
+    Ok(serve_static_file(config, &path, request))
+}
+
+fn run_handler<T: Send + 'static>(
+    config: &Config,
+    request: &Request,
+    permits: Vec<ConcurrencyPermit>,
+    handler: Arc<dyn Fn() -> T + Send + Sync>,
+    timings: &mut Timings,
+) -> Result<T, Error> {
+    /*
+    Run a handler, giving up on it once the handler timeout has passed.
+    Without a timeout the handler runs on the worker serving the connection.
+    With one it runs on a thread of its own, and a handler that timed out keeps
+    running there, counting against the concurrency limits of its route,
+    until it returns. The worker is free again either way.
+     */
+    let start = Instant::now();
+    let result = match config.handler_timeout {
+        Some(timeout) => run_with_timeout(
+            move || {
+                let _permits = permits;
+                handler()
+            },
+            timeout,
+        ),
+        None => {
+            let result = handler();
+            drop(permits);
+            Ok(result)
+        }
+    };
+    timings.handler = Some(start.elapsed());
+    result.map_err(|e| {
+        let (method, uri) = (&request.method, &request.uri);
+        match e {
+            RecvTimeoutError::Timeout => {
+                warn!("Handler for {} {} timed out.", method, uri);
+                Error::Handler(HandlerError::TimedOut)
+            }
+            RecvTimeoutError::Disconnected => {
+                error!("Handler for {} {} panicked.", method, uri);
+                Error::Handler(HandlerError::Panicked)
+            }
+        }
+    })
+}
+
+fn empty_handler_response(config: &Config) -> Response {
+    /*
+    Get the response to a request whose handler returned no file.
+    A 204 is sent without a body, other statuses with their page.
+     */
+    match config.empty_handler_status {
+        StatusCode::NoContent => {
+            Response::new(StatusCode::NoContent, HTML_CONTENT_TYPE, Vec::new())
+        }
+        status => status_response(config, status),
+    }
+}
+
+fn serve_template(config: &Config, path: &Path, context: &HashMap<String, String>) -> Response {
+    /*
+    Serve a file with its placeholders replaced by the values of the context.
+    The rendered page changes with the context, so it is sent without validators.
+     */
+    match fs::read_to_string(path) {
+        Ok(template) => {
+            let body = render(&template, context, config.template_keep_unknown);
+            Response::new(StatusCode::Ok, content_type(path), body.into_bytes())
+        }
+        Err(e) => {
+            error!("Error reading template: {}", e);
+            error_response(config, HTTPError::InternalServerError)
+        }
+    }
+}
+
+// Headers left out of TRACE responses as they carry credentials.
+const SENSITIVE_HEADERS: [&str; 4] = [
+    "Authorization",
+    "Proxy-Authorization",
+    "Cookie",
+    "X-Api-Key",
+];
+
+fn trace_body(request: &Request) -> Vec<u8> {
+    /*
+    Reproduce the request line and headers of a request for a TRACE response.
+    Headers carrying credentials are left out, so a script on a page can't read
+    them through TRACE (cross-site tracing).
+     */
+    let mut body = format!("{} {} {}\r\n", request.method, request.uri, request.version);
+    for (name, value) in &request.headers {
+        if !SENSITIVE_HEADERS
+            .iter()
+            .any(|sensitive| sensitive.eq_ignore_ascii_case(name))
+        {
+            body.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    body.push_str("\r\n");
+    body.into_bytes()
+}
+
+fn serve_unrouted(config: &Config, router: &Router, request: &Request) -> Response {
+    /*
+    Serve a request for which no route matched.
+    The root is answered as configured, files of served directories are
+    looked up before directory listings.
+     */
+    let method = request.method.as_str();
+    if matches!(method, "GET" | "HEAD") {
+        match split_uri(&request.uri).0 {
+            "/" => return serve_root(config, request),
+            "/favicon.ico" if config.favicon != FaviconResponse::NotFound => {
+                return serve_favicon(config, request)
+            }
+            _ => (),
+        }
+        if let Some(path) = router.get_static_file(&request.uri) {
+            if path.is_file() && is_servable(config, request, &path) {
+                return serve_static_file(config, &path, request);
+            }
+            return error_response(config, HTTPError::NotFound);
+        }
+    }
+    if config.directory_listing && method == "GET" {
+        return serve_directory(config, request);
+    }
+    error_response(config, HTTPError::NotFound)
+}
+
+fn serve_root(config: &Config, request: &Request) -> Response {
+    /*
+    Serve a request for / by serving the index file, redirecting or with a 404.
+     */
+    match &config.root {
+        RootResponse::Serve(file) => {
+            let path = config.path_to_resources.join(file);
+            if path.is_file() && is_within_resources(config, &path) {
+                return serve_static_file(config, &path, request);
+            }
+            debug!("Index file {} does not exist.", path.display());
+            error_response(config, HTTPError::NotFound)
+        }
+        RootResponse::Redirect(location) => Response::redirect(StatusCode::Found, location),
+        RootResponse::NotFound => error_response(config, HTTPError::NotFound),
+    }
+}
+
+fn serve_favicon(config: &Config, request: &Request) -> Response {
+    /*
+    Serve the configured icon, so browsers asking for it are not answered with 404.
+     */
+    match &config.favicon {
+        FaviconResponse::File(path) => {
+            let path = config.path_to_resources.join(path);
+            if path.is_file() {
+                return serve_static_file(config, &path, request);
+            }
+            warn!("Favicon {} does not exist.", path.display());
+            error_response(config, HTTPError::NotFound)
+        }
+        FaviconResponse::Default => {
+            let mut response =
+                Response::new(StatusCode::Ok, "image/x-icon", DEFAULT_FAVICON.to_vec());
+            response.add_header("Cache-Control", "public, max-age=86400");
+            response
+        }
+        FaviconResponse::NotFound => error_response(config, HTTPError::NotFound),
+    }
+}
+
+fn is_within_resources(config: &Config, path: &Path) -> bool {
+    // Check whether a path stays inside the resources directory once symlinks
+    // are followed, paths leading outside are answered with 404.
+    if escapes_root(&config.path_to_resources, path) {
+        warn!(
+            "Not serving {}: it leads outside the resources directory.",
+            path.display()
+        );
+        return false;
+    }
+    true
+}
+
+fn is_servable(config: &Config, request: &Request, path: &Path) -> bool {
+    // Check whether a static file may be served for a request, hidden files
+    // and files without an allowed extension are answered with 404.
+    (config.serve_hidden_files || !is_hidden(&request.uri))
+        && config
+            .allowed_extensions
+            .as_deref()
+            .is_none_or(|allowed| has_allowed_extension(path, allowed))
+}
+
+fn serve_static_file(config: &Config, path: &Path, request: &Request) -> Response {
+    /*
+    Serve a file honoring range requests and the configured cache rules.
+     */
+    match serve_file(path, request, config.etag_algorithm) {
+        Ok(mut response) => {
+            apply_cache_headers(
+                &mut response,
+                &config.cache_rules,
+                &request.uri,
+                config.clock.now(),
+            );
+            response
+        }
+        Err(e) => {
+            error!("Error reading file: {}", e);
+            error_response(config, HTTPError::InternalServerError)
+        }
+    }
+}
+
+fn serve_directory(config: &Config, request: &Request) -> Response {
+    /*
+    Serve a directory below the resources directory.
+    The index file is served if present, otherwise a listing of the entries.
+     */
+    let dir = match resolve_path(&config.path_to_resources, &request.uri) {
+        Some(dir)
+            if dir.is_dir()
+                && (config.serve_hidden_files || !is_hidden(&request.uri))
+                && is_within_resources(config, &dir) =>
+        {
+            dir
+        }
+        _ => return error_response(config, HTTPError::NotFound),
+    };
+
+    let index = dir.join("index.html");
+    if index.is_file()
+        && is_servable(config, request, &index)
+        && is_within_resources(config, &index)
+    {
+        return serve_static_file(config, &index, request);
+    }
+
+    match directory_listing(&dir, &request.uri, config.serve_hidden_files) {
+        Ok(listing) => Response::new(StatusCode::Ok, HTML_CONTENT_TYPE, listing.into_bytes()),
+        Err(e) => {
+            error!("Error listing directory: {}", e);
+            error_response(config, HTTPError::InternalServerError)
+        }
+    }
+}
+
+fn dispatch<E: Executor, S: Connection + Send + 'static>(
+    executor: &E,
+    stream: S,
+    peer: Option<IpAddr>,
+    config: &Arc<Config>,
+    router: &Arc<Router<'static>>,
+    limits: &Arc<ConnectionLimits>,
+) {
+    /*
+    Hand a connection to a worker.
+    If the workers are saturated or the peer already holds as many connections as
+    it may, the client is answered with a 503 right away, so the accept loop
+    is not held up by connections no worker can take.
+    The limit is keyed by the peer address, as forwarded addresses are only
+    known once a request was read.
+     */
+    let guard = match peer.map(|ip| (ip, limits.acquire(ip))) {
+        Some((ip, None)) => {
+            warn!("Too many connections from {}; rejecting connection.", ip);
+            if let Err(e) = reject_overloaded(stream, config) {
+                debug!("Error rejecting connection: {}", e);
+            }
+            return;
+        }
+        Some((_, guard)) => guard,
+        None => None,
+    };
+
+    if executor.is_saturated() {
+        warn!("Workers are saturated; rejecting connection.");
+        if let Err(e) = reject_overloaded(stream, config) {
+            debug!("Error rejecting connection: {}", e);
+        }
+        return;
+    }
+
+    let config = Arc::clone(config);
+    let router = Arc::clone(router);
+    let submitted = executor.execute(move || {
+        // the connection counts against the limit of its peer until it is closed
+        let _guard = guard;
+        match handle_connection(stream, peer, &config, &router) {
+            Ok(counts) => {
+                debug!(
+                    "Connection closed: {} bytes in, {} bytes out",
+                    counts.read, counts.written
+                );
+            }
+            Err(Error::Parse(e)) => {
+                debug!("Connection closed after invalid request: {:?}", e);
+            }
+            Err(e) => {
+                error!("Error handling connection: {}", e);
+            }
+        }
+    });
+    if let Err(e) = submitted {
+        // dropping the job closes the connection
+        warn!("Could not hand the connection to a worker: {}.", e);
+    }
+}
+
+fn accept<E: Executor>(
+    listener: &TcpListener,
+    executor: &E,
+    config: &Arc<Config>,
+    router: &Arc<Router<'static>>,
+    stop: &AtomicBool,
+) {
+    // Accept connections and hand them to the executor until the listener fails
+    // or the server is stopped.
+    let limits = Arc::new(ConnectionLimits::new(config.max_connections_per_ip));
+    accept_connections(listener.incoming(), stop, |stream| {
+        let peer = match stream.peer_addr() {
+            Ok(addr) => {
+                debug!("Connection from {}", addr);
+                addr.ip()
+            }
+            Err(e) => {
+                error!("Error reading peer address: {}.", e);
+                return;
+            }
+        };
+
+        dispatch(executor, stream, Some(peer), config, router, &limits);
+    });
+}
+
+fn accept_connections<S, I, F>(incoming: I, stop: &AtomicBool, mut handle: F)
+where
+    I: Iterator<Item = io::Result<S>>,
+    F: FnMut(S),
+{
+    /*
+    Hand accepted connections to a function until the server is stopped.
+    Failed accepts are retried: transient failures, e.g. an interrupted call or
+    a client that gave up, right away, and running out of resources, e.g. file
+    descriptors, after a pause, as retrying at once would spin on the same error.
+     */
+    for stream in incoming {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        match stream {
+            Ok(stream) => handle(stream),
+            Err(e) => match AcceptError::classify(&e) {
+                AcceptError::Transient => {
+                    debug!("Retrying failed accept: {}.", e);
+                }
+                AcceptError::Exhausted => {
+                    error!(
+                        "Error accepting connection: {}; pausing for {:?}.",
+                        e, ACCEPT_BACKOFF
+                    );
+                    std::thread::sleep(ACCEPT_BACKOFF);
+                }
+                AcceptError::Other => {
+                    error!("Error accepting connection: {}.", e);
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AcceptError {
+    // The accept failed for this connection only and can be retried right away.
+    Transient,
+    // The process or system ran out of resources, retrying at once fails again.
+    Exhausted,
+    // Any other failure.
+    Other,
+}
+
+impl AcceptError {
+    fn classify(error: &io::Error) -> AcceptError {
+        // Classify a failed accept by the error kind or, for resource limits
+        // without a kind of their own, by the OS error code.
+        // ENOMEM, ENFILE and EMFILE have the same codes on Linux and macOS.
+        match error.kind() {
+            io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::TimedOut => AcceptError::Transient,
+            io::ErrorKind::OutOfMemory => AcceptError::Exhausted,
+            _ => match error.raw_os_error() {
+                Some(12 | 23 | 24) => AcceptError::Exhausted,
+                _ => AcceptError::Other,
+            },
+        }
+    }
+}
+
+fn retry_later(config: &Config) -> Response {
+    // Get a 503 asking the client to retry after the configured time.
+    let mut response = status_response(config, StatusCode::ServiceUnavailable);
+    response.add_header(
+        "Retry-After",
+        &config.retry_after.as_secs().max(1).to_string(),
+    );
+    response
+}
+
+fn reject_overloaded<S: Write>(mut stream: S, config: &Config) -> std::io::Result<()> {
+    /*
+    Answer a connection with a 503 asking the client to retry later.
+     */
+    let mut response = retry_later(config);
+    response.add_header("Date", &http_date(config.clock.now()));
+    response.add_header("Connection", "close");
+    stream.write_all(&response.to_bytes())
+}
+
+fn log_startup(config: &Config) {
+    // Log a single line with the address and the effective configuration.
+    logger().log_fields(
+        LogLevel::Info,
+        &format!(
+            "Starting webserver on {}",
+            socket_address(&config.address, &config.port)
+        ),
+        &config.summary(),
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConcurrencyModel {
+    // Hand connections to a fixed number of workers, see Config::workers.
+    Pool,
+    // Handle every connection on a thread of its own, at most as many at once if capped.
+    ThreadPerConnection(Option<usize>),
+}
+
+pub struct Server {
+    // Server accepts connections and hands them to workers.
+    config: Config,
+    router: Router<'static>,
+    model: ConcurrencyModel,
+}
+
+impl Server {
+    pub fn new(config: Config, router: Router<'static>) -> Server {
+        // Create a new server from a configuration and a router.
+        Server {
+            config,
+            router,
+            model: ConcurrencyModel::Pool,
+        }
+    }
+
+    pub fn thread_per_connection(mut self, limit: Option<usize>) -> Server {
+        // Handle every connection on a thread of its own instead of a pool of workers,
+        // so slow handlers don't hold up other connections. Connections beyond the
+        // limit are answered with 503.
+        self.model = ConcurrencyModel::ThreadPerConnection(limit);
+        self
+    }
+
+    pub fn enable_request_logging(mut self) -> Server {
+        // Log the method, path, status and duration of every request.
+        self.config.log_requests = true;
+        self
+    }
+
+    pub fn not_found(mut self, not_found: NotFoundResponse) -> Server {
+        // Answer requests for unknown paths with a page, a redirect or a JSON body.
+        self.config.not_found = not_found;
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        // Get the configuration the server runs with.
+        &self.config
+    }
+
+    pub fn bind(config: &Config) -> Result<TcpListener, Error> {
+        // Bind a listener to the configured address and port.
+        // Failures, e.g. a port already in use, are reported with the address.
+        let address = socket_address(&config.address, &config.port);
+        TcpListener::bind(&address).map_err(|e| Error::Bind(address, e))
+    }
+
+    pub fn run(self) -> Result<(), Error> {
+        // Bind the listener and serve connections until the listener fails.
+        let listener = Server::bind(&self.config)?;
+
+        // log the address the webserver is listening on and how it is configured
+        log_startup(&self.config);
+        self.serve(&listener, &AtomicBool::new(false));
+        Ok(())
+    }
+
+    pub fn spawn(self) -> Result<RunningServer, Error> {
+        /*
+        Bind the listener and serve connections on a thread of its own until
+        the returned handle is shut down, e.g. for tests against a real socket.
+        Port 0 binds to a free port, see RunningServer::address.
+         */
+        let listener = Server::bind(&self.config)?;
+        let address = listener.local_addr().map_err(Error::Io)?;
+        log_startup(&self.config);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || self.serve(&listener, &stop))
+        };
+        Ok(RunningServer {
+            address,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    fn serve(self, listener: &TcpListener, stop: &AtomicBool) {
+        // Serve connections with the configured concurrency model until accepting
+        // stops, then wait for the connections being handled.
+        let config = Arc::new(self.config);
+        let mut router = self.router;
+        if config.reserved_workers > 0 && self.model == ConcurrencyModel::Pool {
+            router.limit_low_priority(
+                config
+                    .workers
+                    .saturating_sub(config.reserved_workers)
+                    .max(1),
+            );
+        }
+        let router = Arc::new(router);
+        match self.model {
+            ConcurrencyModel::Pool => {
+                let mut thread_pool = ThreadPool::new(config.workers);
+                thread_pool.set_drain_timeout(config.drain_timeout);
+                thread_pool.set_queue_limit(config.queue_limit);
+                accept(listener, &thread_pool, &config, &router, stop);
+            }
+            ConcurrencyModel::ThreadPerConnection(limit) => {
+                let mut threads = ThreadPerJob::new(limit);
+                threads.set_drain_timeout(config.drain_timeout);
+                accept(listener, &threads, &config, &router, stop);
+            }
+        }
+        info!("Shutting down...");
+    }
+}
+
+pub struct RunningServer {
+    // RunningServer is a handle to a server serving on a thread of its own.
+    // Dropping it shuts the server down.
+    address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RunningServer {
+    pub fn address(&self) -> SocketAddr {
+        // Get the address the server is bound to, with the port picked for port 0.
+        self.address
+    }
+
+    pub fn shutdown(&mut self) {
+        /*
+        Stop accepting connections and wait until the connections being handled
+        are done or the drain timeout passes.
+        The accepting thread is blocked waiting for a connection, so a connection
+        is made to wake it up.
+         */
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        self.stop.store(true, Ordering::SeqCst);
+        if let Err(e) = TcpStream::connect(self.address) {
+            warn!("Could not wake the server to shut it down: {}.", e);
+        }
+        if thread.join().is_err() {
+            error!("The server thread panicked.");
+        }
+    }
+}
+
+impl Drop for RunningServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::FixedClock,
+        http::multipart::PartBody,
+        http::{
+            auth::parse_auth, cache::parse_cache_rules, request::parse_request, response::FileBody,
+        },
+        limit::MemoryBudget,
+        logger::log::{capture_logs, LogSampler},
+        router::{middleware::method_override, router::Priority},
+    };
+    use std::{
+        io::{Cursor, Read},
+        path::PathBuf,
+        sync::Mutex,
+        time::Duration,
+    };
+
+    struct MockStream {
+        // MockStream replays a request and records the response.
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(request: &str) -> MockStream {
+            MockStream {
+                input: Cursor::new(request.as_bytes().to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for MockStream {}
+
+    #[derive(Clone)]
+    struct SharedStream {
+        // SharedStream replays a request and keeps the response readable after a move.
+        input: Arc<std::sync::Mutex<Cursor<Vec<u8>>>>,
+        output: Arc<std::sync::Mutex<Vec<u8>>>,
+    }
+
+    impl SharedStream {
+        fn new(request: &str) -> SharedStream {
+            SharedStream {
+                input: Arc::new(std::sync::Mutex::new(Cursor::new(
+                    request.as_bytes().to_vec(),
+                ))),
+                output: Arc::new(std::sync::Mutex::new(Vec::new())),
+            }
+        }
+
+        fn response(&self) -> String {
+            // Get the response written so far.
+            String::from_utf8(self.output.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Read for SharedStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.lock().unwrap().read(buf)
+        }
+    }
+
+    impl Write for SharedStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for SharedStream {}
+
+    fn test_config() -> Config {
+        // Create a configuration serving the bundled resources.
+        Config {
+            path_to_resources: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res"),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_byte_counts() {
+        // Test that the bytes read and written are counted.
+        let config = test_config();
+        let mut router = Router::new();
+        router.add_route("POST", "/", || Some("index.html".to_string()));
+
+        let request = "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\nhello world";
+        let mut stream = MockStream::new(request);
+        let counts = handle_connection(&mut stream, None, &config, &router).unwrap();
+
+        let body = fs::read(config.path_to_resources.join("index.html")).unwrap();
+        assert!(stream.output.ends_with(&body));
+        assert_eq!(counts.read, request.len() as u64);
+        assert_eq!(counts.written, stream.output.len() as u64);
+    }
+
+    #[test]
+    fn test_cache_headers() {
+        // Test that served files get the Cache-Control of their prefix.
+        let config = Config {
+            cache_rules: parse_cache_rules("/static/=public, max-age=600;/=no-cache"),
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/static/index.html", || {
+            Some("index.html".to_string())
+        });
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+
+        let response = respond(
+            "GET /static/index.html HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.contains("\r\nCache-Control: public, max-age=600\r\n"));
+        assert!(response.contains("\r\nExpires: "));
+
+        let response = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.contains("\r\nCache-Control: no-cache\r\n"));
+
+        let response = respond(
+            "GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(!response.contains("Cache-Control"));
+    }
+
+    #[test]
+    fn test_date_header() {
+        // Test that the Date header comes from the configured clock.
+        let config = Config {
+            clock: Arc::new(FixedClock(
+                std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(784111777),
+            )),
+            ..test_config()
+        };
+        let response = respond(
+            "GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &Router::new(),
+        );
+        assert!(response.contains("\r\nDate: Sun, 06 Nov 1994 08:49:37 GMT\r\n"));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        // Test that an invalid request surfaces as a parse error after a 400.
+        let mut stream = MockStream::new("garbage\r\n\r\n");
+        let result = handle_connection(&mut stream, None, &test_config(), &Router::new());
+
+        assert!(matches!(
+            result,
+            Err(Error::Parse(HTTPError::InvalidRequest))
+        ));
+        assert!(stream.output.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_get_body() {
+        // Test that the body of a GET request doesn't leak into the next request.
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        let requests = "GET / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 42\r\n\r\n\
+                        GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                        GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+        let response = respond(requests, &test_config(), &router);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 2);
+        assert!(response.contains("HTTP/1.1 404 Not Found\r\n"));
+        assert!(!response.contains("400 Bad Request"));
+
+        let config = Config {
+            get_body: GetBody::Reject,
+            ..test_config()
+        };
+        let response = respond(requests, &config, &router);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1);
+    }
+
+    #[test]
+    fn test_unknown_method() {
+        // Test that a well-formed but unknown method is answered with 501.
+        let response = respond("FOOBAR / HTTP/1.1\r\n\r\n", &test_config(), &Router::new());
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+
+        let response = respond("FOO BAR / HTTP/1.1\r\n\r\n", &test_config(), &Router::new());
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_empty_connection() {
+        // Test that connections closed without a request get no response.
+        for request in ["", "\r\n"] {
+            let mut stream = MockStream::new(request);
+            let result = handle_connection(&mut stream, None, &test_config(), &Router::new());
+
+            assert_eq!(result.unwrap().read, request.len() as u64);
+            assert!(stream.output.is_empty());
+        }
+
+        let response = respond("\u{1}\u{2}garbage\r\n\r\n", &test_config(), &Router::new());
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_accept_errors() {
+        // Test that accepting goes on after transient failures and running out of files.
+        let incoming = vec![
+            Err(io::Error::from(io::ErrorKind::Interrupted)),
+            Err(io::Error::from(io::ErrorKind::ConnectionAborted)),
+            Ok(1),
+            Err(io::Error::from_raw_os_error(24)),
+            Ok(2),
+        ];
+        let mut accepted = Vec::new();
+        let output = capture_logs(|| {
+            accept_connections(incoming.into_iter(), &AtomicBool::new(false), |stream| {
+                accepted.push(stream)
+            });
+        });
+        assert_eq!(accepted, [1, 2]);
+        assert_eq!(output.matches("[DEBUG] Retrying failed accept").count(), 2);
+        assert_eq!(output.matches("[ERROR]").count(), 1);
+        assert!(output.contains("pausing for 100ms"));
+
+        assert_eq!(
+            AcceptError::classify(&io::Error::from_raw_os_error(23)),
+            AcceptError::Exhausted
+        );
+        assert_eq!(
+            AcceptError::classify(&io::Error::from(io::ErrorKind::PermissionDenied)),
+            AcceptError::Other
+        );
+    }
+
+    #[test]
+    fn test_saturated_pool() {
+        // Test that connections are answered with 503 while the pool is saturated.
+        let config = Arc::new(Config {
+            retry_after: Duration::from_secs(5),
+            ..test_config()
+        });
+        let mut router = Router::new();
+        router.add_route("GET", "/sleep", || {
+            std::thread::sleep(Duration::from_millis(300));
+            Some("index.html".to_string())
+        });
+        let router = Arc::new(router);
+        let mut pool = ThreadPool::new(1);
+        pool.set_queue_limit(Some(1));
+
+        let limits = Arc::new(ConnectionLimits::new(None));
+        let stream = || SharedStream::new("GET /sleep HTTP/1.1\r\nHost: localhost\r\n\r\n");
+
+        let (busy, queued, rejected) = (stream(), stream(), stream());
+        dispatch(&pool, busy.clone(), None, &config, &router, &limits);
+        std::thread::sleep(Duration::from_millis(50));
+        dispatch(&pool, queued.clone(), None, &config, &router, &limits);
+        dispatch(&pool, rejected.clone(), None, &config, &router, &limits);
+        drop(pool);
+
+        assert!(busy.response().starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(queued.response().starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rejected
+            .response()
+            .starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(rejected.response().contains("\r\nRetry-After: 5\r\n"));
+    }
+
+    #[test]
+    fn test_route_concurrency_limit() {
+        // Test that requests to a limited route beyond the limit are answered with 503.
+        let mut router = Router::new();
+        router.add_route("GET", "/report", || {
+            std::thread::sleep(Duration::from_millis(300));
+            Some("index.html".to_string())
+        });
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        router.limit_concurrency("GET", "/report", 2);
+        let config = Config {
+            retry_after: Duration::from_secs(3),
+            ..test_config()
+        };
+        let get = |path: &str| {
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            respond(&request, &config, &router)
+        };
+
+        std::thread::scope(|scope| {
+            let running: Vec<_> = (0..2).map(|_| scope.spawn(|| get("/report"))).collect();
+            std::thread::sleep(Duration::from_millis(100));
+
+            let rejected = get("/report");
+            assert!(rejected.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+            assert!(rejected.contains("\r\nRetry-After: 3\r\n"));
+            // other routes are not limited
+            assert!(get("/").starts_with("HTTP/1.1 200 OK\r\n"));
+
+            for response in running {
+                assert!(response.join().unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+            }
+        });
+        assert!(get("/report").starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_low_priority_routes() {
+        // Test that a fast request is served promptly while slow ones use the
+        // workers left to them.
+        let config = Arc::new(test_config());
+        let mut router = Router::new();
+        router.add_route("GET", "/sleep", || {
+            std::thread::sleep(Duration::from_millis(500));
+            Some("index.html".to_string())
+        });
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        router.set_priority("GET", "/sleep", Priority::Low);
+        router.limit_low_priority(2);
+        let router = Arc::new(router);
+        let limits = Arc::new(ConnectionLimits::new(None));
+        let pool = ThreadPool::new(3);
+
+        let slow: Vec<_> = (0..4)
+            .map(|_| SharedStream::new("GET /sleep HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+            .collect();
+        for stream in &slow {
+            dispatch(&pool, stream.clone(), None, &config, &router, &limits);
+        }
+        let start = Instant::now();
+        let fast = SharedStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        dispatch(&pool, fast.clone(), None, &config, &router, &limits);
+        while fast.response().is_empty() && start.elapsed() < Duration::from_secs(2) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let elapsed = start.elapsed();
+        drop(pool);
+
+        assert!(fast.response().starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(elapsed < Duration::from_millis(300), "{:?}", elapsed);
+        let statuses: Vec<_> = slow
+            .iter()
+            .map(|stream| stream.response()[..12].to_string())
+            .collect();
+        assert_eq!(statuses.iter().filter(|s| *s == "HTTP/1.1 200").count(), 2);
+        assert_eq!(statuses.iter().filter(|s| *s == "HTTP/1.1 503").count(), 2);
+    }
+
+    #[test]
+    fn test_thread_per_connection() {
+        // Test that slow requests beyond the number of pool workers run in parallel.
+        let config = Arc::new(test_config());
+        let mut router = Router::new();
+        router.add_route("GET", "/sleep", || {
+            std::thread::sleep(Duration::from_millis(300));
+            Some("index.html".to_string())
+        });
+        let router = Arc::new(router);
+        let limits = Arc::new(ConnectionLimits::new(None));
+        let threads = ThreadPerJob::new(Some(4));
+
+        let start = std::time::Instant::now();
+        let streams: Vec<_> = (0..4)
+            .map(|_| SharedStream::new("GET /sleep HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+            .collect();
+        for stream in &streams {
+            dispatch(&threads, stream.clone(), None, &config, &router, &limits);
+        }
+        let rejected = SharedStream::new("GET /sleep HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        dispatch(&threads, rejected.clone(), None, &config, &router, &limits);
+        drop(threads);
+
+        assert!(start.elapsed() < Duration::from_millis(900));
+        for stream in &streams {
+            assert!(stream.response().starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+        assert!(rejected
+            .response()
+            .starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+
+    #[test]
+    fn test_connections_per_ip() {
+        // Test that a client holding too many connections is limited while others are not.
+        let config = Arc::new(test_config());
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        let router = Arc::new(router);
+        let limits = Arc::new(ConnectionLimits::new(Some(2)));
+        let pool = ThreadPool::new(2);
+
+        let busy: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        // the busy client already holds two open connections
+        let _open = (limits.acquire(busy), limits.acquire(busy));
+
+        let stream = || SharedStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let (rejected, accepted) = (stream(), stream());
+        dispatch(
+            &pool,
+            rejected.clone(),
+            Some(busy),
+            &config,
+            &router,
+            &limits,
+        );
+        dispatch(
+            &pool,
+            accepted.clone(),
+            Some(other),
+            &config,
+            &router,
+            &limits,
+        );
+        drop(pool);
+
+        assert!(rejected
+            .response()
+            .starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(accepted.response().starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(limits.active(busy), 2);
+        assert_eq!(limits.active(other), 0);
+    }
+
+    #[test]
+    fn test_unsupported_transfer_encoding() {
+        // Test that a request with an unsupported transfer coding gets a 501.
+        let mut router = Router::new();
+        router.add_route("POST", "/", || Some("index.html".to_string()));
+
+        let response = respond(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        // Test that requests on one connection are served until the limit is reached.
+        let config = Config {
+            max_requests_per_connection: Some(2),
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = respond(&request.repeat(3), &config, &router);
+        let responses: Vec<&str> = response.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+        assert_eq!(responses.len(), 2);
+        assert!(!responses[0].contains("Connection: close"));
+        assert!(responses[1].contains("\r\nConnection: close\r\n"));
+
+        let response = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 1);
+        assert!(response.contains("\r\nConnection: close\r\n"));
+    }
+
+    #[test]
+    fn test_keep_alive_header() {
+        // Test that kept alive connections advertise the configured timeout and limit.
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        let requests = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(3);
+
+        let config = Config {
+            keep_alive_timeout: Some(Duration::from_secs(7)),
+            max_requests_per_connection: Some(3),
+            ..test_config()
+        };
+        let response = respond(&requests, &config, &router);
+        let responses: Vec<&str> = response.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+        assert!(responses[0].contains("\r\nKeep-Alive: timeout=7, max=2\r\n"));
+        assert!(responses[1].contains("\r\nKeep-Alive: timeout=7, max=1\r\n"));
+        assert!(!responses[2].contains("Keep-Alive"));
+
+        let config = Config {
+            keep_alive_timeout: None,
+            ..test_config()
+        };
+        let response = respond(&requests, &config, &router);
+        assert!(response.contains("\r\nKeep-Alive: max=99\r\n"));
+
+        let config = Config {
+            keep_alive_header: false,
+            ..test_config()
+        };
+        assert!(!respond(&requests, &config, &router).contains("Keep-Alive"));
+    }
+
+    #[test]
+    fn test_pipelining() {
+        // Test that requests sent in one write are answered in order.
+        let mut router = Router::new();
+        router.add_route("POST", "/first", || Some("index.html".to_string()));
+        router.add_route("GET", "/second", || Some("404.html".to_string()));
+
+        for read_buffer_size in [1, 16, 8 * 1024] {
+            let config = Config {
+                read_buffer_size,
+                ..test_config()
+            };
+            let response = respond(
+                "POST /first HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody\
+                 GET /second HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                &config,
+                &router,
+            );
+            let index = fs::read_to_string(config.path_to_resources.join("index.html")).unwrap();
+            let page = fs::read_to_string(config.path_to_resources.join("404.html")).unwrap();
+            let responses: Vec<&str> = response.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+            assert_eq!(responses.len(), 2);
+            assert!(responses[0].ends_with(&index));
+            assert!(responses[1].ends_with(&page));
+        }
+    }
+
+    #[test]
+    fn test_precomputed_response() {
+        // Test that a precomputed response is written unchanged without reading its file,
+        // also to requests with a query string.
+        let path = std::env::temp_dir().join(format!(
+            "rust-webserver-{}-precomputed.txt",
+            std::process::id()
+        ));
+        fs::write(&path, "ok").unwrap();
+        let file = FileBody {
+            path: path.clone(),
+            start: 0,
+            length: 2,
+        };
+        let precomputed = PrecomputedResponse::new(Response::from_file_range(
+            StatusCode::Ok,
+            "text/plain",
+            file,
+        ))
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut router = Router::new();
+        router.add_precomputed_response("GET", "/health", precomputed);
+        let expected = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nContent-Type: text/plain\r\n\r\n";
+
+        let response = respond(
+            "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n\
+             GET /health?probe=1 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+             HEAD /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert_eq!(
+            response,
+            format!(
+                "{0}ok{0}ok{1}Connection: close\r\n\r\n",
+                expected,
+                &expected[..expected.len() - 2]
+            )
+        );
+    }
+
+    #[test]
+    fn test_response_handlers() {
+        // Test that handlers may answer with a response or an error status.
+        let mut router = Router::new();
+        router.add_response_route("GET", "/admin", || Err(StatusCode::Forbidden));
+        router.add_response_route("GET", "/old", || {
+            Ok(Response::redirect(StatusCode::MovedPermanently, "/new"))
+        });
+        router.add_response_route("GET", "/status", || {
+            Ok(Response::new(StatusCode::Ok, "text/plain", b"up".to_vec()))
+        });
+        let get = |path: &str| {
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            respond(&request, &test_config(), &router)
+        };
+
+        let forbidden = get("/admin");
+        assert!(forbidden.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(forbidden.contains("403 Forbidden</h1>"));
+        let moved = get("/old");
+        assert!(moved.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(moved.contains("\r\nLocation: /new\r\n"));
+        assert!(get("/status").ends_with("\r\n\r\nup"));
+
+        let head = respond(
+            "HEAD /status HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_header_value_too_long() {
+        // Test that a request with an over-long header value or too many headers is answered with 431.
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        let config = Config {
+            max_header_value_length: Some(64),
+            ..test_config()
+        };
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n\r\n",
+            "a".repeat(100_000)
+        );
+
+        let response = respond(&request, &config, &router);
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+
+        let unlimited = Config {
+            max_header_value_length: None,
+            max_header_bytes: None,
+            ..test_config()
+        };
+        let response = respond(&request, &unlimited, &router);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        // many small headers exceed the header count
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+            "X-Small: 1\r\n".repeat(200)
+        );
+        let response = respond(&request, &config, &router);
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        // Test that requests whose bodies would exceed the memory budget get a 503
+        // while requests within it are served.
+        let mut router = Router::new();
+        router.add_bytes_route("POST", "/upload", || Some(("text/plain", vec![b'x'; 50])));
+        router.add_request_route("POST", "/echo", |request| {
+            Ok(Response::new(
+                StatusCode::Ok,
+                "text/plain",
+                request.body.clone(),
+            ))
+        });
+        let config = Config {
+            memory_budget: MemoryBudget::new(Some(100)),
+            retry_after: Duration::from_secs(2),
+            ..test_config()
+        };
+        let post_to = |path: &str, length: usize| {
+            let request = format!(
+                "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}",
+                path,
+                length,
+                "a".repeat(length)
+            );
+            respond(&request, &config, &router)
+        };
+        let post = |length: usize| post_to("/upload", length);
+
+        assert!(post(30).starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(post(60).starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        // the copy of the body a request handler gets counts too
+        assert!(post_to("/echo", 30).starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(post_to("/echo", 60).starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // a body over the budget is refused before it is sent
+        let headers = "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 120\r\n\r\n";
+        let chunked = "POST /upload HTTP/1.1\r\nHost: localhost\r\n\
+                       Transfer-Encoding: chunked\r\n\r\n78\r\n";
+        for request in [headers, chunked] {
+            let refused = respond(request, &config, &router);
+            assert!(refused.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+            assert!(refused.contains("\r\nRetry-After: 2\r\n"));
+            assert!(refused.contains("\r\nConnection: close\r\n"));
+        }
+
+        // bytes of other requests in flight count too
+        let in_flight = config.memory_budget.try_reserve(70).unwrap();
+        assert!(post(30).starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        drop(in_flight);
+        assert!(post(30).starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(config.memory_budget.used(), 0);
+    }
+
+    #[test]
+    fn test_server_timing() {
+        // Test that the Server-Timing header, when enabled, includes the time the
+        // handler ran.
+        let mut router = Router::new();
+        router.add_response_route("GET", "/report", || {
+            std::thread::sleep(Duration::from_millis(20));
+            Ok(Response::new(
+                StatusCode::Ok,
+                "text/plain",
+                b"done".to_vec(),
+            ))
+        });
+        let request = "GET /report HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let config = Config {
+            server_timing: true,
+            ..test_config()
+        };
+
+        let response = respond(request, &config, &router);
+        let timing = response
+            .lines()
+            .find_map(|line| line.strip_prefix("Server-Timing: "))
+            .unwrap();
+        let phases: Vec<(&str, f64)> = timing
+            .split(", ")
+            .map(|phase| {
+                let (name, duration) = phase.split_once(";dur=").unwrap();
+                (name, duration.parse().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            phases.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            ["read", "route", "handler"]
+        );
+        let handler = phases[2].1;
+        assert!((20.0..5000.0).contains(&handler), "{}", timing);
+        assert!(phases[0].1 < handler && phases[1].1 < handler, "{}", timing);
+
+        let response = respond(request, &test_config(), &router);
+        assert!(!response.contains("Server-Timing"));
+    }
+
+    #[test]
+    fn test_stream_uploads() {
+        // Test that files uploaded to a route streaming uploads reach its handler on
+        // disk unchanged and are removed once it returned.
+        let dir =
+            std::env::temp_dir().join(format!("rust-webserver-{}-uploads", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let contents: String = (0..60_000)
+            .map(|i| (b'a' + (i % 26) as u8) as char)
+            .collect();
+        let expected = contents.clone();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handled = Arc::clone(&seen);
+        let mut router = Router::new();
+        router.add_request_route("POST", "/photos", move |request| {
+            let uploads = request.uploads();
+            let path = match uploads.get(1).map(|part| &part.body) {
+                Some(PartBody::File(path)) => path.clone(),
+                _ => return Err(StatusCode::BadRequest),
+            };
+            let matches = fs::read_to_string(&path).is_ok_and(|read| read == expected);
+            handled.lock().unwrap().push(path);
+            match (
+                uploads[0].body == PartBody::Memory(b"holiday".to_vec()),
+                matches,
+            ) {
+                (true, true) => Ok(Response::new(
+                    StatusCode::Ok,
+                    "text/plain",
+                    b"stored".to_vec(),
+                )),
+                _ => Err(StatusCode::UnprocessableContent),
+            }
+        });
+        router.stream_uploads("POST", "/photos");
+        let upload = |config: &Config| {
+            let body = format!(
+                "--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nholiday\r\n\
+                 --XyZ\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"a.txt\"\r\n\r\n\
+                 {}\r\n--XyZ--\r\nepilogue",
+                contents
+            );
+            let request = format!(
+                "POST /photos HTTP/1.1\r\nHost: localhost\r\n\
+                 Content-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {}\r\n\r\n{}\
+                 GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                body.len(),
+                body
+            );
+            respond(&request, config, &router)
+        };
+
+        let config = Config {
+            upload_dir: dir.clone(),
+            ..test_config()
+        };
+        let response = upload(&config);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("HTTP/1.1 404 Not Found\r\n"));
+        let seen = seen.lock().unwrap().clone();
+        assert_eq!(seen.len(), 1);
+        assert!(seen[0].starts_with(&dir));
+        assert!(!seen[0].exists());
+
+        let limited = Config {
+            upload_dir: dir.clone(),
+            max_upload_size: 10_000,
+            ..test_config()
+        };
+        let response = upload(&limited);
+        assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_route_with_query() {
+        // Test that requests with a query string are routed by their path and
+        // request handlers read the parameters of the query.
+        let mut router = Router::new();
+        router.add_request_route("GET", "/search results", |request| {
+            let query = request.param("q").unwrap_or_default();
+            Ok(Response::new(
+                StatusCode::Ok,
+                "text/plain",
+                query.into_bytes(),
+            ))
+        });
+        let request = "GET /search%20results?q=hello+world HTTP/1.1\r\nHost: localhost\r\n\
+                       Connection: close\r\n\r\n";
+        let response = respond(request, &test_config(), &router);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nhello world"));
+    }
+
+    #[test]
+    fn test_bytes_handler() {
+        // Test that bytes computed by a handler are served verbatim with their content type.
+        let mut router = Router::new();
+        router.add_bytes_route("GET", "/pixel", || {
+            Some(("image/gif", vec![0x47, 0x49, 0x46, 0x00, 0xff, 0x0a]))
+        });
+        router.add_bytes_route("GET", "/nothing", || None);
+        let get = |path: &str| {
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            let mut stream = MockStream::new(&request);
+            let _ = handle_connection(&mut stream, None, &test_config(), &router);
+            stream.output
+        };
+
+        let pixel = get("/pixel");
+        let head = String::from_utf8_lossy(&pixel);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("\r\nContent-Type: image/gif\r\n"));
+        assert!(head.contains("\r\nContent-Length: 6\r\n"));
+        assert!(pixel.ends_with(&[0x47, 0x49, 0x46, 0x00, 0xff, 0x0a]));
+        assert!(get("/nothing").starts_with(b"HTTP/1.1 204 No Content\r\n"));
+    }
+
+    #[test]
+    fn test_request_deadline() {
+        // Test that handlers read a deadline matching the handler timeout.
+        let mut router = Router::new();
+        router.add_request_route("GET", "/deadline", |request| {
+            let remaining = match request.deadline() {
+                Some(deadline) => deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_millis()
+                    .to_string(),
+                None => "none".to_string(),
+            };
+            Ok(Response::new(
+                StatusCode::Ok,
+                "text/plain",
+                remaining.into_bytes(),
+            ))
+        });
+        let get = |handler_timeout: Option<Duration>| {
+            let config = Config {
+                handler_timeout,
+                ..test_config()
+            };
+            let request = "GET /deadline HTTP/1.1\r\nHost: localhost\r\n\r\n";
+            let response = respond(request, &config, &router);
+            response.split("\r\n\r\n").nth(1).unwrap().to_string()
+        };
+
+        let remaining: u64 = get(Some(Duration::from_secs(2))).parse().unwrap();
+        assert!(remaining > 1000 && remaining <= 2000, "{}", remaining);
+        assert_eq!(get(None), "none");
+    }
+
+    #[test]
+    fn test_unreadable_file_body() {
+        // Test that a file body which can't be read is answered with an actual 500.
+        let mut router = Router::new();
+        router.add_response_route("GET", "/report", || {
+            Ok(Response::from_file_range(
+                StatusCode::Ok,
+                "text/plain",
+                FileBody {
+                    path: PathBuf::from("/nonexistent/report.txt"),
+                    start: 0,
+                    length: 1 << 20,
+                },
+            ))
+        });
+        let request = "GET /report HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                       GET /report HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let response = respond(request, &test_config(), &router);
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert_eq!(response.matches("HTTP/1.1 500 ").count(), 2);
+        assert!(!response.contains("200 OK"));
+    }
+
+    #[test]
+    fn test_closure_handler() {
+        // Test that handlers can be closures sharing state between requests.
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let mut router = Router::new();
+        let counter = Arc::clone(&hits);
+        router.add_response_route("GET", "/hits", move || {
+            let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Response::new(
+                StatusCode::Ok,
+                "text/plain",
+                count.to_string().into_bytes(),
+            ))
+        });
+
+        let request = "GET /hits HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert!(respond(request, &test_config(), &router).ends_with("\r\n\r\n1"));
+        assert!(respond(request, &test_config(), &router).ends_with("\r\n\r\n2"));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_access_log_sampling() {
+        // Test that one in n successful requests and every failed request is logged.
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        let config = Config {
+            log_requests: true,
+            access_log_sampler: LogSampler::new(4),
+            ..test_config()
+        };
+
+        let output = capture_logs(|| {
+            for _ in 0..20 {
+                respond(
+                    "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                    &config,
+                    &router,
+                );
+            }
+            for _ in 0..3 {
+                respond(
+                    "GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                    &config,
+                    &router,
+                );
+            }
+        });
+        assert_eq!(output.matches(" GET / 200 ").count(), 5);
+        assert_eq!(output.matches(" GET /missing 404 ").count(), 3);
+    }
+
+    #[test]
+    fn test_handler_without_file() {
+        // Test that a handler returning None is answered with 204 and the connection is kept.
+        let mut router = Router::new();
+        router.add_route("POST", "/ping", || None);
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        let requests = "POST /ping HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n\
+                        GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        let response = respond(requests, &test_config(), &router);
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        let (no_content, _) = response.split_once("HTTP/1.1 200 OK\r\n").unwrap();
+        assert!(!no_content.contains("Content-Length"));
+        assert!(no_content.ends_with("\r\n\r\n"));
+
+        let config = Config {
+            empty_handler_status: StatusCode::NotFound,
+            ..test_config()
+        };
+        let response = respond(requests, &config, &router);
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_generated_conditional_get() {
+        // Test that validators set on a generated response are honored with 304.
+        fn api(request: &mut Request) -> Option<Response> {
+            if request.uri != "/api/user" {
+                return None;
+            }
+            let mut response = Response::new(
+                StatusCode::Ok,
+                "application/json",
+                b"{\"name\":\"ada\"}".to_vec(),
+            );
+            response.add_header("ETag", "\"v1\"");
+            response.add_header("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT");
+            Some(response)
+        }
+        let mut router = Router::new();
+        router.add_middleware(api);
+        let get = |header: &str| {
+            let request = format!(
+                "GET /api/user HTTP/1.1\r\nHost: localhost\r\n{}\r\n\r\n",
+                header
+            );
+            respond(&request, &test_config(), &router)
+        };
+
+        let matching = get("If-None-Match: W/\"v1\"");
+        assert!(matching.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(matching.contains("\r\nETag: \"v1\"\r\n"));
+        assert!(!matching.contains("Content-Length"));
+        assert!(matching.ends_with("\r\n\r\n"));
+        assert!(get("If-None-Match: \"v2\"").ends_with("{\"name\":\"ada\"}"));
+
+        let since = get("If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!(since.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        let earlier = get("If-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT");
+        assert!(earlier.starts_with("HTTP/1.1 200 OK\r\n"));
+        // If-None-Match takes precedence over a matching If-Modified-Since
+        let both = get("If-None-Match: \"v2\"\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!(both.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_startup_log() {
+        // Test that the startup line summarizes the configuration without credentials.
+        let config = Config {
+            workers: 7,
+            auth: parse_auth("basic", "admin", "alice:secret"),
+            trace: true,
+            ..test_config()
+        };
+        let output = capture_logs(|| log_startup(&config));
+
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.contains("Starting webserver on 127.0.0.1:8080"));
+        assert!(output.contains(" workers=7 "));
+        assert!(output.contains(&format!(
+            " resources={} ",
+            config.path_to_resources.display()
+        )));
+        assert!(output.contains(" auth=\"basic (1 credentials)\" "));
+        assert!(output.contains("trace"));
+        assert!(!output.contains("secret"));
+    }
+
+    #[test]
+    fn test_unknown_host() {
+        // Test that requests for unknown hosts are answered as configured.
+        let mut router = Router::new();
+        router.add_host_route("www.example.com", "GET", "/", || {
+            Some("index.html".to_string())
+        });
+        router.add_route("GET", "/shared", || Some("404.html".to_string()));
+        let get = |host: &str, path: &str, unknown_host: UnknownHostResponse| {
+            let config = Config {
+                unknown_host,
+                root: RootResponse::NotFound,
+                ..test_config()
+            };
+            let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, host);
+            respond(&request, &config, &router)
+        };
+        let index = fs::read_to_string(test_config().path_to_resources.join("index.html")).unwrap();
+
+        let known = get("WWW.example.com:8080", "/", UnknownHostResponse::NotFound);
+        assert!(known.starts_with("HTTP/1.1 200 OK\r\n"));
+        let unknown = get("evil.example", "/shared", UnknownHostResponse::NotFound);
+        assert!(unknown.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let default = UnknownHostResponse::DefaultHost("www.example.com".to_string());
+        let response = get("evil.example", "/", default);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&index));
+
+        let shared = get("evil.example", "/shared", UnknownHostResponse::Shared);
+        assert!(shared.starts_with("HTTP/1.1 200 OK\r\n"));
+        let shared = get("evil.example", "/", UnknownHostResponse::Shared);
+        assert!(shared.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_root() {
+        // Test that / is answered as configured unless a route matches it.
+        let get = |root: RootResponse, router: &Router| {
+            let config = Config {
+                root,
+                ..test_config()
+            };
+            respond("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n", &config, router)
+        };
+        let router = Router::new();
+        let index = fs::read_to_string(test_config().path_to_resources.join("index.html")).unwrap();
+
+        let response = get(RootResponse::Serve("index.html".to_string()), &router);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&index));
+
+        let response = respond(
+            "GET /?lang=en HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(response.ends_with(&index));
+        let response = get(RootResponse::Serve("404.html".to_string()), &router);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!response.ends_with(&index));
+
+        let response = get(RootResponse::Serve("missing.html".to_string()), &router);
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = get(RootResponse::Redirect("/docs/".to_string()), &router);
+        assert!(response.starts_with("HTTP/1.1 302 Found\r\n"));
+        assert!(response.contains("\r\nLocation: /docs/\r\n"));
+
+        let response = get(RootResponse::NotFound, &router);
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("404.html".to_string()));
+        let response = get(RootResponse::Redirect("/docs/".to_string()), &router);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_favicon() {
+        // Test that /favicon.ico is only served once enabled.
+        let path =
+            std::env::temp_dir().join(format!("rust-webserver-{}-icon.ico", std::process::id()));
+        fs::write(&path, "custom icon").unwrap();
+        let get = |favicon: FaviconResponse| {
+            let config = Config {
+                favicon,
+                ..test_config()
+            };
+            respond(
+                "GET /favicon.ico HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                &config,
+                &Router::new(),
+            )
+        };
+
+        let default = get(FaviconResponse::Default);
+        let file = get(FaviconResponse::File(path.clone()));
+        let disabled = get(FaviconResponse::NotFound);
+        fs::remove_file(&path).unwrap();
+
+        assert!(default.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(default.contains("\r\nContent-Type: image/x-icon\r\n"));
+        assert!(default.contains("\r\nContent-Length: 70\r\n"));
+        assert!(file.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(file.contains("\r\nContent-Type: image/x-icon\r\n"));
+        assert!(file.ends_with("custom icon"));
+        assert!(disabled.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_response_body_limit() {
+        // Test that bodies above the limit are truncated or answered with 500.
+        fn large(_: &mut Request) -> Option<Response> {
+            Some(Response::new(StatusCode::Ok, "text/plain", vec![b'x'; 100]))
+        }
+        let mut router = Router::new();
+        router.add_middleware(large);
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        let config = Config {
+            max_response_body: Some(10),
+            oversized_response: OversizedResponse::Truncate,
+            ..test_config()
+        };
+        let mut response = String::new();
+        let output = capture_logs(|| response = respond(request, &config, &router));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n"));
+        assert!(response.ends_with("\r\n\r\nxxxxxxxxxx"));
+        assert!(output.contains("[WARNING] Response to GET / has a body of 100 bytes"));
+
+        let config = Config {
+            max_response_body: Some(10),
+            ..test_config()
+        };
+        let response = respond(request, &config, &router);
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+
+        let config = Config {
+            max_response_body: Some(100),
+            ..test_config()
+        };
+        assert!(respond(request, &config, &router).ends_with(&"x".repeat(100)));
+    }
+
+    #[test]
+    fn test_response_middleware() {
+        // Test that response middleware modifies routed and error responses.
+        fn request_id(request: &Request, response: &mut Response) {
+            response.add_header(
+                "X-Request-Id",
+                &format!("{}-{}", request.method, request.uri),
+            );
+        }
+        fn admin_only(_: &Request, response: &mut Response) {
+            response.add_header("X-Admin", "1");
+        }
+        let mut admin = Router::new();
+        admin.add_response_middleware(admin_only);
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        router.add_response_middleware(request_id);
+        router.mount("/admin", admin);
+
+        let routed = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(routed.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(routed.contains("\r\nX-Request-Id: GET-/\r\n"));
+        assert!(!routed.contains("X-Admin"));
+
+        let missing = respond(
+            "GET /admin/x HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(missing.contains("\r\nX-Request-Id: GET-/admin/x\r\n"));
+        assert!(missing.contains("\r\nX-Admin: 1\r\n"));
+    }
+
+    #[test]
+    fn test_encoded_mount_prefix() {
+        // Test that the middleware mounted at a prefix runs for requests spelling it encoded.
+        fn deny(_: &mut Request) -> Option<Response> {
+            Some(Response::new(
+                StatusCode::Forbidden,
+                "text/plain",
+                Vec::new(),
+            ))
+        }
+        fn mark(_: &Request, response: &mut Response) {
+            response.add_header("X-Admin", "1");
+        }
+        let mut admin = Router::new();
+        admin.add_route("GET", "/x", || Some("index.html".to_string()));
+        admin.add_middleware(deny);
+        admin.add_response_middleware(mark);
+        let mut router = Router::new();
+        router.mount("/admin", admin);
+
+        for uri in ["/admin/x", "/%61dmin/x", "/%61dmin/x?q=1"] {
+            let response = respond(
+                &format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", uri),
+                &test_config(),
+                &router,
+            );
+            assert!(
+                response.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+                "{}",
+                uri
+            );
+            assert!(response.contains("\r\nX-Admin: 1\r\n"), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn test_request_extensions() {
+        // Test that data attached by middleware is read by later middleware.
+        struct UserId(u32);
+        fn authenticate(request: &mut Request) -> Option<Response> {
+            let user = request.header("X-User")?.parse().ok()?;
+            request.extensions.insert(UserId(user));
+            None
+        }
+        fn profile(request: &mut Request) -> Option<Response> {
+            let body = match request.extensions.get::<UserId>() {
+                Some(UserId(user)) => format!("user {}", user),
+                None => "anonymous".to_string(),
+            };
+            Some(Response::new(
+                StatusCode::Ok,
+                "text/plain",
+                body.into_bytes(),
+            ))
+        }
+        fn tag_user(request: &Request, response: &mut Response) {
+            if let Some(UserId(user)) = request.extensions.get::<UserId>() {
+                response.add_header("X-User-Id", &user.to_string());
+            }
+        }
+        let mut router = Router::new();
+        router.add_middleware(authenticate);
+        router.add_middleware(profile);
+        router.add_response_middleware(tag_user);
+
+        let response = respond(
+            "GET /me HTTP/1.1\r\nHost: localhost\r\nX-User: 42\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(response.contains("\r\nX-User-Id: 42\r\n"));
+        assert!(response.ends_with("user 42"));
+
+        let response = respond(
+            "GET /me HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(!response.contains("X-User-Id"));
+        assert!(response.ends_with("anonymous"));
+    }
+
+    #[test]
+    fn test_missing_host() {
+        // Test that HTTP/1.1 requests without a Host are rejected with 400.
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+
+        let response = respond("GET / HTTP/1.1\r\n\r\n", &test_config(), &router);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+
+        let response = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let response = respond(
+            "GET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 1);
+    }
+
+    #[test]
+    fn test_uri_too_long() {
+        // Test that a path or request line over the configured length is answered with 414.
+        let config = Config {
+            max_uri_length: Some(16),
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+
+        let request = format!(
+            "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "a".repeat(16)
+        );
+        let response = respond(&request, &config, &router);
+        assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+
+        // a request line too long to hold such a path is not read to its end
+        let request = format!("GET /{}", "a".repeat(1 << 20));
+        let response = respond(&request, &config, &router);
+        assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+
+        let response = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    fn serve_tcp(
+        config: Config,
+        client: impl FnOnce(std::net::TcpStream) + Send,
+    ) -> Result<ByteCounts, Error> {
+        // Serve one connection over a local socket while the client runs on another thread.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(move || client(std::net::TcpStream::connect(address).unwrap()));
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, None, &config, &Router::new())
+        })
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        // Test that a request which stalls halfway is answered with 408.
+        let config = Config {
+            keep_alive_timeout: Some(Duration::from_secs(5)),
+            request_timeout: Some(Duration::from_millis(100)),
+            ..test_config()
+        };
+        let start = Instant::now();
+        let result = serve_tcp(config, |mut client| {
+            client.write_all(b"GET / HTTP/1.1\r\nHost: loc").unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        });
+
+        assert!(matches!(
+            result,
+            Err(Error::Parse(HTTPError::RequestTimeout))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_write_timeout() {
+        // Test that a client which stops reading is dropped once a write times out.
+        const BODY_SIZE: usize = 64 << 20;
+        let config = Config {
+            write_timeout: Some(Duration::from_millis(100)),
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_bytes_route("GET", "/large", || {
+            Some(("application/octet-stream", vec![0; BODY_SIZE]))
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (done, wait) = std::sync::mpsc::channel::<()>();
+
+        let start = Instant::now();
+        let mut result = None;
+        let output = capture_logs(|| {
+            std::thread::scope(|scope| {
+                scope.spawn(move || {
+                    let mut client = std::net::TcpStream::connect(address).unwrap();
+                    client
+                        .write_all(b"GET /large HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                        .unwrap();
+                    // keep the connection open without reading until the server gave up
+                    let _ = wait.recv();
+                });
+                let (stream, _) = listener.accept().unwrap();
+                result = Some(handle_connection(stream, None, &config, &router));
+                drop(done);
+            });
+        });
+
+        let written = result.unwrap().unwrap().written;
+        assert!(written < BODY_SIZE as u64);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(output.contains("[DEBUG] Client stopped reading; dropping the connection.\n"));
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        // Test that an idle connection is closed silently after the keep-alive timeout.
+        let config = Config {
+            keep_alive_timeout: Some(Duration::from_millis(100)),
+            request_timeout: Some(Duration::from_secs(5)),
+            ..test_config()
+        };
+        let start = Instant::now();
+        let result = serve_tcp(config, |mut client| {
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).unwrap();
+            assert!(response.is_empty());
+        });
+
+        assert_eq!(result.unwrap().written, 0);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_fuzz_handle_connection() {
+        // Test that arbitrary input never panics and gets a valid response or none.
+        const SEEDS: [&str; 6] = [
+            "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\n_method=PUT",
+            "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+            "GET /assets/../%2e%2e/a%zz?x=a+b HTTP/1.1\r\nHost: localhost\r\nRange: bytes=5-1\r\n\r\n",
+            "OPTIONS * HTTP/1.1\r\nHost: localhost\r\nForwarded: for=\"[::1]:80\"\r\nX-Forwarded-For: ,,\r\n\r\n",
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: \"a, W/\r\nIf-Range: x\r\nRange: bytes=-0\r\n\r\n",
+        ];
+        const TOKENS: [&str; 12] = [
+            "\r\n",
+            ":",
+            " ",
+            "%",
+            "\"",
+            "Content-Length: 18446744073709551616\r\n",
+            "Content-Length: 99999999999999\r\n",
+            "Transfer-Encoding: chunked\r\n",
+            "ffffffffffffffff\r\n",
+            "Range: bytes=0-18446744073709551615\r\n",
+            "Host: [::1\r\n",
+            "\u{0}",
+        ];
+
+        // xorshift keeps the cases reproducible without a dependency
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+
+        let config = Config {
+            handler_timeout: None,
+            directory_listing: true,
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        router.add_route("POST", "/", || Some("index.html".to_string()));
+        router.add_middleware(method_override);
+        router.serve_dir("/assets", config.path_to_resources.clone());
+
+        // the failing cases are logged, which would drown the test output
+        capture_logs(|| {
+            for _ in 0..3000 {
+                let mut input = SEEDS[next(SEEDS.len())].as_bytes().to_vec();
+                for _ in 0..next(6) {
+                    let at = next(input.len() + 1);
+                    match next(4) {
+                        0 => input.truncate(at),
+                        1 => input.insert(at, next(256) as u8),
+                        2 if at < input.len() => input[at] = next(256) as u8,
+                        _ => {
+                            let token = TOKENS[next(TOKENS.len())].as_bytes();
+                            input.splice(at..at, token.iter().copied());
+                        }
+                    }
+                }
+
+                let mut stream = MockStream {
+                    input: Cursor::new(input.clone()),
+                    output: Vec::new(),
+                };
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    let _ = handle_connection(&mut stream, None, &config, &router);
+                }));
+                assert!(
+                    result.is_ok(),
+                    "panicked on {:?}",
+                    String::from_utf8_lossy(&input)
+                );
+                assert!(
+                    stream.output.is_empty() || stream.output.starts_with(b"HTTP/1.1 "),
+                    "invalid response to {:?}",
+                    String::from_utf8_lossy(&input)
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_io_error() {
+        // Test that a failed write surfaces as an I/O error.
+        struct BrokenStream;
+
+        impl Read for BrokenStream {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"
+                    .as_bytes()
+                    .read(buf)
+            }
+        }
+
+        impl Write for BrokenStream {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    "client went away",
+                ))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl Connection for BrokenStream {}
+
+        let result = handle_connection(BrokenStream, None, &test_config(), &Router::new());
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_access_log_line() {
+        // Test formatting of access log lines.
+        let mut reader = "GET /index HTTP/1.1\r\nHost: localhost\r\n\r\n".as_bytes();
+        let request = parse_request(&mut reader).unwrap();
+        let response = Response::new(StatusCode::NotFound, HTML_CONTENT_TYPE, Vec::new());
+
+        let client = "127.0.0.1".parse().ok();
+        assert_eq!(
+            access_log_line(
+                client,
+                Some(&request),
+                &response,
+                Duration::from_millis(12),
+                QueryLogging::Full
+            ),
+            "127.0.0.1 GET /index 404 12ms"
+        );
+        assert_eq!(
+            access_log_line(
+                None,
+                None,
+                &response,
+                Duration::from_millis(0),
+                QueryLogging::Full
+            ),
+            "- - - 404 0ms"
+        );
+    }
+
+    #[test]
+    fn test_access_log_query() {
+        // Test that the query string is logged, left out or redacted as configured.
+        let mut reader = "GET /login?user=ann&token=s3cr3t&debug HTTP/1.1\r\n\r\n".as_bytes();
+        let request = parse_request(&mut reader).unwrap();
+        let response = Response::new(StatusCode::Ok, HTML_CONTENT_TYPE, Vec::new());
+        let line = |query: QueryLogging| {
+            access_log_line(None, Some(&request), &response, Duration::ZERO, query)
+        };
+
+        assert_eq!(
+            line(QueryLogging::Full),
+            "- GET /login?user=ann&token=s3cr3t&debug 200 0ms"
+        );
+        assert_eq!(line(QueryLogging::Strip), "- GET /login 200 0ms");
+        assert_eq!(
+            line(QueryLogging::Redact),
+            "- GET /login?user=***&token=***&debug 200 0ms"
+        );
+        assert_eq!(log_uri("/plain", QueryLogging::Redact), "/plain");
+    }
+
+    #[test]
+    fn test_slow_request_warning() {
+        // Test that only requests slower than the threshold log a warning.
+        let config = Config {
+            slow_request_threshold: Some(Duration::from_millis(50)),
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/slow", || {
+            std::thread::sleep(Duration::from_millis(100));
+            Some("index.html".to_string())
+        });
+        router.add_route("GET", "/fast", || Some("index.html".to_string()));
+
+        let output = capture_logs(|| {
+            respond(
+                "GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                &config,
+                &router,
+            );
+            respond(
+                "GET /fast HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                &config,
+                &router,
+            );
+        });
+
+        assert!(output
+            .lines()
+            .any(|line| line.contains("[WARNING] Slow request: GET /slow took")));
+        assert!(!output.lines().any(|line| line.contains("GET /fast took")));
+    }
+
+    #[test]
+    fn test_bind_error() {
+        // Test that binding to a port in use reports the address.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = Config {
+            port: listener.local_addr().unwrap().port().to_string(),
+            ..test_config()
+        };
+
+        match Server::bind(&config) {
+            Err(error @ Error::Bind(..)) => {
+                assert!(error
+                    .to_string()
+                    .starts_with(&format!("could not bind to 127.0.0.1:{}: ", config.port)));
+            }
+            other => panic!("expected a bind error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_bind_ipv6() {
+        // Test binding to the IPv6 loopback given with or without brackets.
+        for address in ["::1", "[::1]"] {
+            let config = Config {
+                address: address.to_string(),
+                port: "0".to_string(),
+                ..test_config()
+            };
+            let listener = Server::bind(&config).unwrap();
+            assert_eq!(
+                listener.local_addr().unwrap().ip(),
+                "::1".parse::<IpAddr>().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_request_logging() {
+        // Test that enabling request logging produces one log line per request.
+        let server = Server::new(test_config(), Router::new()).enable_request_logging();
+        assert!(server.config().log_requests);
+
+        let router = Router::new();
+        let output = capture_logs(|| {
+            respond(
+                "GET /logged HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                server.config(),
+                &router,
+            );
+            respond(
+                "GET /logged HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                server.config(),
+                &router,
+            );
+        });
+        let lines = output
+            .lines()
+            .filter(|line| line.contains("GET /logged 404"))
+            .count();
+        assert_eq!(lines, 2);
+    }
+
+    #[test]
+    fn test_forwarded_client_ip() {
+        // Test that the logged client comes from X-Forwarded-For only via trusted proxies.
+        let config = Config {
+            log_requests: true,
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+            ..test_config()
+        };
+        let router = Router::new();
+        let request =
+            "GET /forwarded HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-For: 198.51.100.1\r\n\r\n";
+
+        let output = capture_logs(|| {
+            let mut stream = MockStream::new(request);
+            let _ = handle_connection(&mut stream, "10.0.0.1".parse().ok(), &config, &router);
+            let mut stream = MockStream::new(request);
+            let _ = handle_connection(&mut stream, "203.0.113.7".parse().ok(), &config, &router);
+        });
+
+        assert!(output.contains("198.51.100.1 GET /forwarded 404"));
+        assert!(output.contains("203.0.113.7 GET /forwarded 404"));
+        assert!(!output.contains("10.0.0.1 GET /forwarded"));
+    }
+
+    #[test]
+    fn test_serve_dir() {
+        // Test serving the files of a directory with their content type.
+        let dir =
+            std::env::temp_dir().join(format!("rust-webserver-{}-assets", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("app.css"), "body {}").unwrap();
+
+        let config = Config {
+            cache_rules: parse_cache_rules("/assets/=max-age=60"),
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.serve_dir("/assets", &dir);
+
+        let css = respond(
+            "GET /assets/app.css HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        let missing = respond(
+            "GET /assets/app.js HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        let escape = respond(
+            "GET /assets/../503.html HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(css.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(css.contains("\r\nContent-Type: text/css; charset=UTF-8\r\n"));
+        assert!(css.contains("\r\nCache-Control: max-age=60\r\n"));
+        assert!(css.ends_with("body {}"));
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(escape.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_escape() {
+        // Test that symlinks leading outside the served directories are not followed.
+        use std::os::unix::fs::symlink;
+
+        let base =
+            std::env::temp_dir().join(format!("rust-webserver-{}-symlinks", std::process::id()));
+        let (root, outside) = (base.join("res"), base.join("outside"));
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret.html"), "secret").unwrap();
+        fs::write(root.join("page.html"), "page").unwrap();
+        symlink(outside.join("secret.html"), root.join("leak.html")).unwrap();
+        symlink(&outside, root.join("leaked")).unwrap();
+        symlink(root.join("page.html"), root.join("alias.html")).unwrap();
+
+        let config = Config {
+            path_to_resources: root.clone(),
+            directory_listing: true,
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/leak", || Some("leak.html".to_string()));
+        router.add_route("GET", "/alias", || Some("alias.html".to_string()));
+        router.serve_dir("/files", &root);
+        let get = |path: &str| {
+            respond(
+                &format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path),
+                &config,
+                &router,
+            )
+        };
+
+        let responses = [
+            get("/leak"),
+            get("/files/leak.html"),
+            get("/files/leaked/secret.html"),
+            get("/leaked/"),
+        ];
+        let alias = get("/alias");
+        let page = get("/files/alias.html");
+        fs::remove_dir_all(&base).unwrap();
+
+        for response in responses {
+            assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+            assert!(!response.contains("secret"));
+        }
+        assert!(alias.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(page.ends_with("page"));
+    }
+
+    #[test]
+    fn test_serve_precompressed_vary() {
+        // Test that only files with a precompressed sibling vary by Accept-Encoding.
+        fn vary_origin(_: &Request, response: &mut Response) {
+            response.add_vary("Origin");
+        }
+        let dir = std::env::temp_dir().join(format!("rust-webserver-{}-vary", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("app.js"), "plain").unwrap();
+        fs::write(dir.join("app.js.gz"), "gzip").unwrap();
+        fs::write(dir.join("logo.png"), "png").unwrap();
+
+        let mut router = Router::new();
+        router.serve_dir("/static", &dir);
+        router.add_response_middleware(vary_origin);
+        let get = |path: &str, accept_encoding: &str| {
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {}\r\n\r\n",
+                path, accept_encoding
+            );
+            respond(&request, &test_config(), &router)
+        };
+
+        let compressed = get("/static/app.js", "gzip");
+        let identity = get("/static/app.js", "identity");
+        let uncompressible = get("/static/logo.png", "gzip");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(compressed.contains("\r\nContent-Encoding: gzip\r\n"));
+        assert!(compressed.contains("\r\nVary: Accept-Encoding, Origin\r\n"));
+        assert!(compressed.ends_with("gzip"));
+        assert!(!identity.contains("Content-Encoding"));
+        assert!(identity.contains("\r\nVary: Accept-Encoding, Origin\r\n"));
+        assert!(identity.ends_with("plain"));
+        // the response is the same whatever the client accepts
+        assert!(!uncompressible.contains("Content-Encoding"));
+        assert!(uncompressible.contains("\r\nVary: Origin\r\n"));
+    }
+
+    #[test]
+    fn test_serve_dir_filters() {
+        // Test that hidden files and disallowed extensions are not served.
+        let dir =
+            std::env::temp_dir().join(format!("rust-webserver-{}-filters", std::process::id()));
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join(".env"), "SECRET=1").unwrap();
+        fs::write(dir.join(".git").join("config"), "[core]").unwrap();
+        fs::write(dir.join("page.html"), "<p>page</p>").unwrap();
+        fs::write(dir.join("main.rs"), "fn main() {}").unwrap();
+
+        let mut router = Router::new();
+        router.serve_dir("/site", &dir);
+        let get = |path: &str, config: &Config| {
+            let request = format!("GET /site/{} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            respond(&request, config, &router)
+        };
+
+        let config = test_config();
+        assert!(get(".env", &config).starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(get(".git/config", &config).starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(get("page.html", &config).ends_with("<p>page</p>"));
+        assert!(get("main.rs", &config).starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let config = Config {
+            allowed_extensions: Some(vec!["html".to_string()]),
+            ..test_config()
+        };
+        assert!(get("page.html", &config).ends_with("<p>page</p>"));
+        assert!(get("main.rs", &config).starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let config = Config {
+            serve_hidden_files: true,
+            ..test_config()
+        };
+        assert!(get(".env", &config).ends_with("SECRET=1"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_directory_listing() {
+        // Test that directory listings are served only when enabled.
+        let dir = std::env::temp_dir().join(format!("rust-webserver-{}-dirs", std::process::id()));
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::create_dir_all(dir.join("site")).unwrap();
+        fs::write(dir.join("docs").join("guide.txt"), "guide").unwrap();
+        fs::write(dir.join("site").join("index.html"), "welcome").unwrap();
+
+        let router = Router::new();
+        let config = Config {
+            path_to_resources: dir.clone(),
+            directory_listing: true,
+            ..Config::default()
+        };
+        let listing = respond(
+            "GET /docs HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        let index = respond(
+            "GET /site/ HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        let escape = respond(
+            "GET /docs/../.. HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+
+        let config = Config {
+            directory_listing: false,
+            ..config
+        };
+        let disabled = respond(
+            "GET /docs HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(listing.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(listing.contains("href=\"/docs/guide.txt\""));
+        assert!(index.ends_with("welcome"));
+        assert!(escape.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(disabled.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_status_response() {
+        // Test responses for statuses with and without a page.
+        let config = test_config();
+
+        let response = status_response(&config, StatusCode::NotFound);
+        assert_eq!(response.status, StatusCode::NotFound);
+        assert!(String::from_utf8(response.body)
+            .unwrap()
+            .contains("Oops! Error 404"));
+
+        let response = status_response(&config, StatusCode::Conflict);
+        assert!(response
+            .to_bytes()
+            .starts_with(b"HTTP/1.1 409 Conflict\r\n"));
+        assert!(String::from_utf8(response.body)
+            .unwrap()
+            .contains("<h1>409 Conflict</h1>"));
+    }
+
+    fn respond(request: &str, config: &Config, router: &Router) -> String {
+        // Handle a request and return the raw response.
+        let mut stream = MockStream::new(request);
+        let _ = handle_connection(&mut stream, None, config, router);
+        String::from_utf8(stream.output).unwrap()
+    }
+
+    #[test]
+    fn test_handle_connection() {
+        // Test serving a routed file and a missing route.
+        let config = test_config();
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+
+        let response = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let response = respond(
+            "GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_options_asterisk() {
+        // Test that OPTIONS * lists the methods of all routes.
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        router.add_route("POST", "/items", || Some("index.html".to_string()));
+        router.add_route("DELETE", "/items", || Some("index.html".to_string()));
+
+        let response = respond(
+            "OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(response.contains("\r\nAllow: DELETE, GET, OPTIONS, POST\r\n"));
+    }
+
+    #[test]
+    fn test_bearer_auth() {
+        // Test that only requests with a configured token are served.
+        let config = Config {
+            auth: parse_auth("bearer", "api", "secret-token"),
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+
+        let response = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer secret-token\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        for request in [
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer wrong-token\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ] {
+            let response = respond(request, &config, &router);
+            assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+            assert!(response.contains("\r\nWWW-Authenticate: Bearer realm=\"api\"\r\n"));
+        }
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        // Test that Basic credentials are challenged for and accepted.
+        let config = Config {
+            auth: parse_auth("basic", "admin", "alice:secret"),
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+
+        let response = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(response.contains("\r\nWWW-Authenticate: Basic realm=\"admin\"\r\n"));
+
+        let response = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic YWxpY2U6c2VjcmV0\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_head_request() {
+        // Test that a HEAD request gets the headers of the GET route without a body.
+        let config = test_config();
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+        let length = fs::metadata(config.path_to_resources.join("index.html"))
+            .unwrap()
+            .len();
+
+        let response = respond(
+            "HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(&format!("\r\nContent-Length: {}\r\n", length)));
+        assert!(response.contains("\r\nContent-Type: text/html; charset=UTF-8\r\n"));
+        assert!(response.contains("\r\nETag: "));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = respond(
+            "HEAD /missing HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_not_found_responses() {
+        // Test each of the configurable responses for unknown paths.
+        let router = Router::new();
+        let request = "GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        let server = Server::new(test_config(), Router::new());
+        let response = respond(request, server.config(), &router);
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("Oops! Error 404"));
+
+        let server = Server::new(test_config(), Router::new())
+            .not_found(NotFoundResponse::Redirect("/start".to_string()));
+        let response = respond(request, server.config(), &router);
+        assert!(response.starts_with("HTTP/1.1 302 Found\r\n"));
+        assert!(response.contains("\r\nLocation: /start\r\n"));
+
+        let server = Server::new(test_config(), Router::new()).not_found(NotFoundResponse::Json);
+        let response = respond(request, server.config(), &router);
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("\r\nContent-Type: application/json\r\n"));
+        assert!(response.ends_with("{\"status\":404,\"error\":\"Not Found\"}"));
+    }
+
+    #[test]
+    fn test_method_override() {
+        // Test that a POST with a method override routes to the DELETE handler.
+        let config = test_config();
+        let mut router = Router::new();
+        router.add_route("POST", "/", || Some("missing.html".to_string()));
+        router.add_route("DELETE", "/", || Some("index.html".to_string()));
+        router.add_middleware(method_override);
+
+        let response = respond(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nX-HTTP-Method-Override: DELETE\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_middleware_order() {
+        // Test that middleware runs in registration order and each sees the request.
+        fn first(request: &mut Request) -> Option<Response> {
+            request
+                .headers
+                .push(("X-Seen".to_string(), "first".to_string()));
+            None
+        }
+        fn second(request: &mut Request) -> Option<Response> {
+            let seen = request.header("X-Seen").unwrap_or("none").to_string();
+            let body = format!("{} {}", request.uri, seen);
+            Some(Response::new(
+                StatusCode::Ok,
+                "text/plain",
+                body.into_bytes(),
+            ))
+        }
+
+        let mut router = Router::new();
+        router.add_named_middleware("first", first);
+        router.add_named_middleware("second", second);
+        let response = respond(
+            "GET /order HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(response.ends_with("\r\n\r\n/order first"));
+
+        router.remove_middleware("first");
+        let response = respond(
+            "GET /order HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &test_config(),
+            &router,
+        );
+        assert!(response.ends_with("\r\n\r\n/order none"));
+    }
+
+    #[test]
+    fn test_required_content_type() {
+        // Test that a JSON-only route rejects other bodies with 415.
+        let mut router = Router::new();
+        router.add_route("POST", "/api", || Some("index.html".to_string()));
+        router.require_content_type("POST", "/api", "application/json");
+
+        let request = |content_type: &str| {
+            format!(
+                "POST /api HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: 2\r\n\r\n{{}}",
+                content_type
+            )
+        };
+        let response = respond(&request("text/plain"), &test_config(), &router);
+        assert!(response.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"));
+
+        let response = respond(
+            &request("application/json; charset=utf-8"),
+            &test_config(),
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_template_route() {
+        // Test that the file of a route with a template context is rendered.
+        let dir =
+            std::env::temp_dir().join(format!("rust-webserver-{}-template", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("hello.html"), "<p>Hello {{name}}{{unknown}}</p>").unwrap();
+        let config = Config {
+            path_to_resources: dir.clone(),
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/hello", || Some("hello.html".to_string()));
+        router.set_template_context("GET", "/hello", || {
+            HashMap::from([("name".to_string(), "Ferris".to_string())])
+        });
+
+        let response = respond(
+            "GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        let config = Config {
+            path_to_resources: dir.clone(),
+            template_keep_unknown: false,
+            ..test_config()
+        };
+        let removed = respond(
+            "GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(response.ends_with("\r\n\r\n<p>Hello Ferris{{unknown}}</p>"));
+        assert!(!response.contains("ETag"));
+        assert!(removed.ends_with("\r\n\r\n<p>Hello Ferris</p>"));
+    }
+
+    #[test]
+    fn test_trace() {
+        // Test that TRACE echoes the request without credentials only when enabled.
+        let request =
+            "TRACE /debug HTTP/1.1\r\nHost: localhost\r\nCookie: session=1\r\nX-Test: yes\r\n\r\n";
+        let config = Config {
+            trace: true,
+            ..test_config()
+        };
+        let response = respond(request, &config, &Router::new());
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\nContent-Type: message/http\r\n"));
+        assert!(response
+            .ends_with("\r\n\r\nTRACE /debug HTTP/1.1\r\nHost: localhost\r\nX-Test: yes\r\n\r\n"));
+
+        let response = respond(request, &test_config(), &Router::new());
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(!response.contains("session=1"));
+    }
+
+    #[test]
+    fn test_internal_server_error_page() {
+        // Test that a 500 serves the custom page from the resources directory.
+        let config = test_config();
+        let mut router = Router::new();
+        router.add_route("GET", "/broken", || Some("missing.html".to_string()));
+
+        let response = respond(
+            "GET /broken HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(response.contains("Sorry, something went very wrong."));
+    }
+
+    #[test]
+    fn test_internal_server_error_fallback() {
+        // Test that the inline page is used if the custom page is unreadable.
+        let config = Config {
+            path_to_resources: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("does-not-exist"),
+            ..Config::default()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+
+        let response = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(response.ends_with(&generated_status_page(StatusCode::InternalServerError)));
+    }
+
+    #[test]
+    fn test_handler_timeout() {
+        // Test that a handler exceeding the timeout yields a 503.
+        let config = Config {
+            handler_timeout: Some(Duration::from_millis(50)),
+            ..test_config()
+        };
+        let mut router = Router::new();
+        router.add_route("GET", "/sleep", || {
+            std::thread::sleep(Duration::from_millis(500));
+            Some("index.html".to_string())
+        });
+        router.add_route("GET", "/", || Some("index.html".to_string()));
+
+        let response = respond(
+            "GET /sleep HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        let response = respond(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &config,
+            &router,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
@@ -1,17 +1,52 @@
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
     thread::{self},
+    time::{Duration, Instant},
 };
 
-use crate::info;
+use crate::{info, warn};
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: Option<mpsc::Sender<Job>>,
+    drain_timeout: Option<Duration>,
+    // Number of jobs waiting for a free worker.
+    queued: Arc<AtomicUsize>,
+    queue_limit: Option<usize>,
+    // Threads submitting scheduled tasks, stopped by dropping their sender.
+    timers: Vec<(mpsc::Sender<()>, thread::JoinHandle<()>)>,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// The error returned when a job is submitted to a pool that was shut down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolShutDown;
+
+impl fmt::Display for PoolShutDown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the thread pool was shut down")
+    }
+}
+
+impl std::error::Error for PoolShutDown {}
+
+/// Something running the jobs handling connections, e.g. a `ThreadPool`.
+pub trait Executor {
+    /// Submit a job to be run.
+    fn execute<F>(&self, f: F) -> Result<(), PoolShutDown>
+    where
+        F: FnOnce() + Send + 'static;
+
+    /// Check whether new jobs should be turned away instead of submitted.
+    fn is_saturated(&self) -> bool;
+}
+
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
@@ -26,38 +61,154 @@ impl ThreadPool {
         let (sender, receiver) = mpsc::channel();
 
         let receiver = Arc::new(Mutex::new(receiver));
+        let queued = Arc::new(AtomicUsize::new(0));
 
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&queued)));
         }
 
         ThreadPool {
             workers,
             sender: Some(sender),
+            drain_timeout: None,
+            queued,
+            queue_limit: None,
+            timers: Vec::new(),
+        }
+    }
+
+    /// Set how long dropping the pool waits for running jobs to finish.
+    ///
+    /// Workers still busy after the timeout are abandoned. Without a timeout the
+    /// pool waits for every job to finish.
+    pub fn set_drain_timeout(&mut self, timeout: Option<Duration>) {
+        self.drain_timeout = timeout;
+    }
+
+    /// Set how many jobs may wait for a free worker before the pool is saturated.
+    ///
+    /// The pool keeps accepting jobs when saturated; callers check
+    /// `is_saturated` to turn work away instead. Without a limit the pool is
+    /// never saturated.
+    pub fn set_queue_limit(&mut self, limit: Option<usize>) {
+        self.queue_limit = limit;
+    }
+
+    /// Check whether the jobs waiting for a free worker have reached the queue limit.
+    pub fn is_saturated(&self) -> bool {
+        match self.queue_limit {
+            Some(limit) => self.queued.load(Ordering::SeqCst) >= limit,
+            None => false,
         }
     }
 
-    pub fn execute<F>(&self, f: F)
+    /// Run a task on the pool every `interval` until the pool is dropped.
+    ///
+    /// A timer thread submits the task to the workers like any other job. A run
+    /// is skipped while the previous one is still waiting or running, so a slow
+    /// task never piles up in the queue.
+    pub fn schedule<F>(&mut self, interval: Duration, task: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let sender = self.sender.as_ref().unwrap().clone();
+        let queued = Arc::clone(&self.queued);
+        let task = Arc::new(task);
+        let running = Arc::new(AtomicBool::new(false));
+
+        let timer = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if running.swap(true, Ordering::SeqCst) {
+                    continue;
+                }
+                let task = Arc::clone(&task);
+                let running = Arc::clone(&running);
+                queued.fetch_add(1, Ordering::SeqCst);
+                let job: Job = Box::new(move || {
+                    task();
+                    running.store(false, Ordering::SeqCst);
+                });
+                if sender.send(job).is_err() {
+                    break;
+                }
+            }
+        });
+        self.timers.push((stop, timer));
+    }
+
+    /// Submit a job to be run by the next free worker.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolShutDown` if the pool no longer accepts jobs. The job is
+    /// dropped without running.
+    pub fn execute<F>(&self, f: F) -> Result<(), PoolShutDown>
     where
         F: FnOnce() + Send + 'static,
     {
+        let sender = self.sender.as_ref().ok_or(PoolShutDown)?;
         let job = Box::new(f);
 
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        sender.send(job).map_err(|_| {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            PoolShutDown
+        })
+    }
+
+    /// Stop accepting jobs.
+    ///
+    /// Scheduled tasks stop and the workers exit once the jobs already queued
+    /// have run. Dropping the pool shuts it down and waits for the workers.
+    pub fn shutdown(&mut self) {
+        // stop the timers first, their senders keep the workers alive otherwise
+        for (stop, timer) in self.timers.drain(..) {
+            drop(stop);
+            let _ = timer.join();
+        }
+        if self.sender.take().is_some() {
+            info!("Sending terminate message to all workers.");
+        }
+    }
+}
+
+impl Executor for ThreadPool {
+    fn execute<F>(&self, f: F) -> Result<(), PoolShutDown>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        ThreadPool::execute(self, f)
+    }
+
+    fn is_saturated(&self) -> bool {
+        ThreadPool::is_saturated(self)
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
-        info!("Sending terminate message to all workers.");
+        self.shutdown();
 
+        let deadline = self.drain_timeout.map(|timeout| Instant::now() + timeout);
         for worker in &mut self.workers {
             info!("Shutting down worker {}", worker._id);
 
             if let Some(thread) = worker.thread.take() {
+                if let Some(deadline) = deadline {
+                    while !thread.is_finished() && Instant::now() < deadline {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    if !thread.is_finished() {
+                        warn!(
+                            "Worker {} did not finish within the drain timeout; abandoning it.",
+                            worker._id
+                        );
+                        continue;
+                    }
+                }
                 thread.join().unwrap();
             }
         }
@@ -70,12 +221,17 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+        queued: Arc<AtomicUsize>,
+    ) -> Worker {
         let thread = thread::spawn(move || loop {
             let message = receiver.lock().unwrap().recv();
 
             match message {
                 Ok(job) => {
+                    queued.fetch_sub(1, Ordering::SeqCst);
                     info!("Worker {} got a job; executing.", id);
                     job();
                 }
@@ -93,13 +249,111 @@ impl Worker {
     }
 }
 
+/// Runs every job on a thread of its own, trading memory for concurrency.
+///
+/// Unlike a `ThreadPool`, slow jobs never hold up other ones. The number of
+/// jobs running at once may be capped.
+pub struct ThreadPerJob {
+    limit: Option<usize>,
+    running: Arc<AtomicUsize>,
+    drain_timeout: Option<Duration>,
+}
+
+impl ThreadPerJob {
+    /// Create an executor running at most `limit` jobs at once, any number without a limit.
+    pub fn new(limit: Option<usize>) -> ThreadPerJob {
+        ThreadPerJob {
+            limit,
+            running: Arc::new(AtomicUsize::new(0)),
+            drain_timeout: None,
+        }
+    }
+
+    /// Set how long dropping the executor waits for running jobs to finish.
+    ///
+    /// Without a timeout it waits for every job to finish.
+    pub fn set_drain_timeout(&mut self, timeout: Option<Duration>) {
+        self.drain_timeout = timeout;
+    }
+
+    /// Get the number of jobs running.
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+}
+
+impl Executor for ThreadPerJob {
+    fn execute<F>(&self, f: F) -> Result<(), PoolShutDown>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let running = Arc::clone(&self.running);
+        running.fetch_add(1, Ordering::SeqCst);
+        thread::spawn(move || {
+            // count the job as finished even if it panics
+            struct Finished(Arc<AtomicUsize>);
+            impl Drop for Finished {
+                fn drop(&mut self) {
+                    self.0.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+            let _finished = Finished(running);
+            f();
+        });
+        Ok(())
+    }
+
+    /// Check whether the cap on running jobs is reached.
+    fn is_saturated(&self) -> bool {
+        self.limit.is_some_and(|limit| self.running() >= limit)
+    }
+}
+
+impl Drop for ThreadPerJob {
+    fn drop(&mut self) {
+        let deadline = self.drain_timeout.map(|timeout| Instant::now() + timeout);
+        while self.running() > 0 {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                warn!(
+                    "{} jobs did not finish within the drain timeout; abandoning them.",
+                    self.running()
+                );
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Run a function on a separate thread and wait at most `timeout` for its result.
+///
+/// If the timeout passes, the function keeps running detached and its result is
+/// discarded, so the calling thread is free to continue. Nothing stops the
+/// function, so every call may leave a thread behind for as long as it runs.
+///
+/// Returns `RecvTimeoutError::Disconnected` if the function panicked.
+pub fn run_with_timeout<F, T>(f: F, timeout: Duration) -> Result<T, RecvTimeoutError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        // the receiver is gone if the caller already gave up
+        let _ = sender.send(f());
+    });
+
+    receiver.recv_timeout(timeout)
+}
+
 #[test]
 fn test_worker() {
     let (sender, receiver) = mpsc::channel();
 
     let receiver = Arc::new(Mutex::new(receiver));
 
-    let worker = Worker::new(0, Arc::clone(&receiver));
+    let worker = Worker::new(0, Arc::clone(&receiver), Arc::new(AtomicUsize::new(2)));
 
     sender
         .send(Box::new(|| {
@@ -125,6 +379,111 @@ fn test_thread_pool() {
     for i in 0..8 {
         pool.execute(move || {
             info!("Task {} is running.", i);
-        });
+        })
+        .unwrap();
+    }
+}
+
+#[test]
+fn test_execute_after_shutdown() {
+    let mut pool = ThreadPool::new(2);
+    let runs = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&runs);
+    pool.execute(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    })
+    .unwrap();
+
+    pool.shutdown();
+    let counter = Arc::clone(&runs);
+    let result = pool.execute(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+    assert_eq!(result, Err(PoolShutDown));
+    assert!(!pool.is_saturated());
+
+    // the job queued before the shutdown still runs
+    drop(pool);
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_queue_limit() {
+    let mut pool = ThreadPool::new(1);
+    pool.set_queue_limit(Some(1));
+    pool.set_drain_timeout(Some(Duration::from_millis(10)));
+    assert!(!pool.is_saturated());
+
+    pool.execute(|| thread::sleep(Duration::from_millis(300)))
+        .unwrap();
+    // give the worker the chance to pick up the job
+    thread::sleep(Duration::from_millis(50));
+    assert!(!pool.is_saturated());
+
+    pool.execute(|| ()).unwrap();
+    assert!(pool.is_saturated());
+
+    // the queued job runs once the worker is free again
+    thread::sleep(Duration::from_millis(500));
+    assert!(!pool.is_saturated());
+}
+
+#[test]
+fn test_schedule() {
+    let mut pool = ThreadPool::new(2);
+    let runs = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&runs);
+    pool.schedule(Duration::from_millis(20), move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+
+    thread::sleep(Duration::from_millis(200));
+    drop(pool);
+    let after_shutdown = runs.load(Ordering::SeqCst);
+    assert!(after_shutdown >= 2);
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(runs.load(Ordering::SeqCst), after_shutdown);
+}
+
+#[test]
+fn test_thread_per_job() {
+    let executor = ThreadPerJob::new(Some(3));
+    let start = Instant::now();
+    for _ in 0..3 {
+        executor
+            .execute(|| thread::sleep(Duration::from_millis(200)))
+            .unwrap();
     }
+    assert!(executor.is_saturated());
+
+    // the jobs run in parallel, so they finish together
+    drop(executor);
+    assert!(start.elapsed() < Duration::from_millis(500));
+}
+
+#[test]
+fn test_run_with_timeout() {
+    assert_eq!(run_with_timeout(|| 42, Duration::from_secs(1)), Ok(42));
+
+    let result = run_with_timeout(
+        || thread::sleep(Duration::from_millis(500)),
+        Duration::from_millis(10),
+    );
+    assert_eq!(result, Err(RecvTimeoutError::Timeout));
+}
+
+#[test]
+fn test_drain_timeout() {
+    let mut pool = ThreadPool::new(2);
+    pool.set_drain_timeout(Some(Duration::from_millis(100)));
+
+    pool.execute(|| thread::sleep(Duration::from_secs(5)))
+        .unwrap();
+    // give a worker the chance to pick up the job
+    thread::sleep(Duration::from_millis(20));
+
+    let start = Instant::now();
+    drop(pool);
+    assert!(start.elapsed() < Duration::from_secs(1));
 }
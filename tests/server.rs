@@ -0,0 +1,81 @@
+/*
+* End-to-end tests against the server listening on a real socket.
+*/
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+};
+
+use rust_webserver::{
+    config::Config,
+    router::router::Router,
+    server::{RunningServer, Server},
+};
+
+struct TestServer {
+    // TestServer runs a server on a free local port, shut down when dropped.
+    server: RunningServer,
+}
+
+impl TestServer {
+    fn start(router: Router<'static>) -> TestServer {
+        // Start a server serving the resources of the repository.
+        let config = Config {
+            port: "0".to_string(),
+            path_to_resources: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res"),
+            ..Config::default()
+        };
+        let server = Server::new(config, router).spawn().unwrap();
+        TestServer { server }
+    }
+
+    fn request(&self, request: &str) -> String {
+        // Send a raw request and read the response until the server closes the connection.
+        let mut stream = TcpStream::connect(self.server.address()).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn get(&self, path: &str) -> String {
+        // Send a GET request for a path on a connection closed after the response.
+        self.request(&format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        ))
+    }
+}
+
+#[test]
+fn test_serve_files() {
+    // Test that the index is served and unknown paths are answered with 404.
+    let server = TestServer::start(Router::new());
+    assert!(server.get("/").starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(server
+        .get("/missing")
+        .starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
+
+#[test]
+fn test_routes() {
+    // Test that requests reach the routes of the router.
+    let mut router = Router::new();
+    router.add_route("GET", "/home", || Some("index.html".to_string()));
+    let server = TestServer::start(router);
+    let response = server.get("/home");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/html; charset=UTF-8\r\n"));
+}
+
+#[test]
+fn test_shutdown() {
+    // Test that a server stops accepting connections once shut down.
+    let mut server = TestServer::start(Router::new());
+    let address = server.server.address();
+    server.server.shutdown();
+    server.server.shutdown();
+    assert!(TcpStream::connect(address).is_err());
+}